@@ -0,0 +1,410 @@
+#include <inttypes.h>
+#include <stdarg.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+/* Addresses below this are stored in a contiguous array, the others in a hash table */
+#define DENSE_LIMIT ((uint64_t)1 << 20)
+
+static int64_t *dense;
+static uint64_t dense_length;
+static uint64_t dense_capacity;
+
+static uint64_t *sparse_addresses;
+static int64_t *sparse_values;
+static unsigned char *sparse_used;
+static uint64_t sparse_length;
+static uint64_t sparse_capacity;
+
+static void fail(const char *format, ...) {
+    va_list arguments;
+    va_start(arguments, format);
+    vprintf(format, arguments);
+    va_end(arguments);
+    putchar('\n');
+    exit(1);
+}
+
+static void *allocate(void *pointer, size_t size) {
+    pointer = realloc(pointer, size);
+    if (pointer == NULL) {
+        fail("Out of memory");
+    }
+    return pointer;
+}
+
+static uint64_t sparse_slot(uint64_t address) {
+    uint64_t slot = (address * UINT64_C(0x9e3779b97f4a7c15)) & (sparse_capacity - 1);
+    while (sparse_used[slot] && sparse_addresses[slot] != address) {
+        slot = (slot + 1) & (sparse_capacity - 1);
+    }
+    return slot;
+}
+
+static void sparse_grow(void) {
+    uint64_t *addresses = sparse_addresses;
+    int64_t *values = sparse_values;
+    unsigned char *used = sparse_used;
+    uint64_t capacity = sparse_capacity;
+    uint64_t i;
+
+    sparse_capacity = capacity == 0 ? 64 : capacity * 2;
+    sparse_addresses = allocate(NULL, sparse_capacity * sizeof(uint64_t));
+    sparse_values = allocate(NULL, sparse_capacity * sizeof(int64_t));
+    sparse_used = calloc(sparse_capacity, 1);
+    if (sparse_used == NULL) {
+        fail("Out of memory");
+    }
+    for (i = 0; i < capacity; i++) {
+        if (used[i]) {
+            uint64_t slot = sparse_slot(addresses[i]);
+            sparse_used[slot] = 1;
+            sparse_addresses[slot] = addresses[i];
+            sparse_values[slot] = values[i];
+        }
+    }
+    free(addresses);
+    free(values);
+    free(used);
+}
+
+/* Reads a cell, returning 0 if it lies past the end of memory */
+static int mem_get(uint64_t address, int64_t *value) {
+    uint64_t slot;
+    if (address < dense_length) {
+        *value = dense[address];
+        return 1;
+    }
+    if (sparse_capacity == 0) {
+        return 0;
+    }
+    slot = sparse_slot(address);
+    if (!sparse_used[slot]) {
+        return 0;
+    }
+    *value = sparse_values[slot];
+    return 1;
+}
+
+static int64_t mem_read(uint64_t address) {
+    int64_t value = 0;
+    if (address < dense_length) {
+        return dense[address];
+    }
+    mem_get(address, &value);
+    return value;
+}
+
+static void mem_write(uint64_t address, int64_t value) {
+    if (address < dense_length) {
+        dense[address] = value;
+    } else if (address < DENSE_LIMIT) {
+        if (address >= dense_capacity) {
+            dense_capacity = dense_capacity * 2 > address ? dense_capacity * 2 : address + 1;
+            dense = allocate(dense, dense_capacity * sizeof(int64_t));
+        }
+        memset(dense + dense_length, 0, (address - dense_length) * sizeof(int64_t));
+        dense_length = address + 1;
+        dense[address] = value;
+    } else {
+        uint64_t slot;
+        if ((sparse_length + 1) * 2 > sparse_capacity) {
+            sparse_grow();
+        }
+        slot = sparse_slot(address);
+        if (!sparse_used[slot]) {
+            sparse_used[slot] = 1;
+            sparse_addresses[slot] = address;
+            sparse_length++;
+        }
+        sparse_values[slot] = value;
+    }
+}
+
+static void overflow(int64_t opcode, uint64_t position) {
+    fail("Arithmetic overflow for opcode \"%" PRId64 "\" at position %" PRIu64, opcode, position);
+}
+
+static uint64_t relative(int64_t rb, int64_t offset, int64_t opcode, uint64_t position) {
+    int64_t address;
+    if ((offset > 0 && rb > INT64_MAX - offset) || (offset < 0 && rb < INT64_MIN - offset)) {
+        overflow(opcode, position);
+    }
+    address = rb + offset;
+    if (address < 0) {
+        fail("Address %" PRId64 " out of bounds for opcode \"%" PRId64 "\" at position %" PRIu64,
+             address, opcode, position);
+    }
+    return (uint64_t)address;
+}
+
+static int64_t checked_add(int64_t a, int64_t b, int64_t opcode, uint64_t position) {
+    if ((b > 0 && a > INT64_MAX - b) || (b < 0 && a < INT64_MIN - b)) {
+        overflow(opcode, position);
+    }
+    return a + b;
+}
+
+static int64_t checked_mul(int64_t a, int64_t b, int64_t opcode, uint64_t position) {
+    int overflows;
+    if (a > 0) {
+        overflows = b > 0 ? a > INT64_MAX / b : b < INT64_MIN / a;
+    } else {
+        overflows = b > 0 ? a < INT64_MIN / b : a != 0 && b < INT64_MAX / a;
+    }
+    if (overflows) {
+        overflow(opcode, position);
+    }
+    return a * b;
+}
+
+/* Checks a jump target, which may lie right past the end of memory where the program halts */
+static uint64_t target(int64_t target, int64_t opcode, uint64_t position) {
+    int64_t value;
+    if (target < 0 || ((uint64_t)target != dense_length && !mem_get((uint64_t)target, &value))) {
+        fail("Jump target %" PRId64 " out of bounds for opcode \"%" PRId64 "\" at position %" PRIu64,
+             target, opcode, position);
+    }
+    return (uint64_t)target;
+}
+
+// io
+
+/* Reads a line into a growing buffer without its line ending, returning 0 at the end of input */
+static int read_line(char **buffer, size_t *capacity) {
+    size_t length = 0;
+    int c;
+    fflush(stdout);
+    while ((c = getchar()) != EOF && c != '\n') {
+        if (length + 1 >= *capacity) {
+            *capacity = *capacity == 0 ? 128 : *capacity * 2;
+            *buffer = allocate(*buffer, *capacity);
+        }
+        (*buffer)[length++] = (char)c;
+    }
+    if (c == EOF && length == 0) {
+        return 0;
+    }
+    if (length > 0 && (*buffer)[length - 1] == '\r') {
+        length--;
+    }
+    if (*capacity == 0) {
+        *capacity = 128;
+        *buffer = allocate(*buffer, *capacity);
+    }
+    (*buffer)[length] = '\0';
+    return 1;
+}
+
+/* Parses a whole line as a decimal number, the same way Rust parses an i64 */
+static int parse_value(const char *text, int64_t *value) {
+    int negative = *text == '-';
+    uint64_t limit = negative ? (uint64_t)INT64_MAX + 1 : (uint64_t)INT64_MAX;
+    uint64_t result = 0;
+    if (*text == '-' || *text == '+') {
+        text++;
+    }
+    if (*text == '\0') {
+        return 0;
+    }
+    for (; *text != '\0'; text++) {
+        uint64_t digit = (uint64_t)(*text - '0');
+        if (*text < '0' || *text > '9' || result > (limit - digit) / 10) {
+            return 0;
+        }
+        result = result * 10 + digit;
+    }
+    *value = negative ? (int64_t)(0 - result) : (int64_t)result;
+    return 1;
+}
+
+static int read_input(int64_t *value) {
+    static char *buffer;
+    static size_t capacity;
+    static size_t length;
+    static size_t pending;
+
+    if (ascii) {
+        if (pending == length) {
+            if (!read_line(&buffer, &capacity)) {
+                return 0;
+            }
+            length = strlen(buffer);
+            buffer[length++] = '\n';
+            pending = 0;
+        }
+        *value = (unsigned char)buffer[pending++];
+        return 1;
+    }
+
+    for (;;) {
+        fputs("> ", stdout);
+        if (!read_line(&buffer, &capacity)) {
+            return 0;
+        }
+        if (parse_value(buffer, value)) {
+            putchar('\n');
+            return 1;
+        }
+        puts("Invalid\n");
+    }
+}
+
+static void write_output(int64_t value) {
+    if (ascii && value >= 0 && value <= 127) {
+        putchar((int)value);
+    } else {
+        printf("%" PRId64 "\n", value);
+    }
+}
+
+// valid
+
+static void invalidate(uint64_t address) {
+    // invalidate
+}
+
+typedef struct {
+    int64_t mode;
+    int64_t word;
+} Parameter;
+
+static Parameter parameter(uint64_t *i, int64_t mode, int n, int64_t opcode, int destination) {
+    Parameter p;
+    if (!mem_get(*i, &p.word)) {
+        fail("Missing parameter %d for opcode \"%" PRId64 "\" at position %" PRIu64, n, opcode,
+             *i);
+    }
+    *i += 1;
+    if (mode == 0 && p.word < 0) {
+        fail("Negative value %" PRId64 " for positional parameter %d for opcode \"%" PRId64
+             "\" at position %" PRIu64,
+             p.word, n, opcode, *i);
+    }
+    if (mode < 0 || mode > 2 || (destination && mode == 1)) {
+        fail("Invalid parameter mode \"%" PRId64 "\" for parameter %d of opcode \"%" PRId64
+             "\" at position %" PRIu64,
+             mode, n, opcode, *i);
+    }
+    p.mode = mode;
+    return p;
+}
+
+static uint64_t address(Parameter p, int64_t rb, int64_t opcode, uint64_t position) {
+    if (p.mode == 0) {
+        return (uint64_t)p.word;
+    }
+    return relative(rb, p.word, opcode, position);
+}
+
+static int64_t value(Parameter p, int64_t rb, int64_t opcode, uint64_t position) {
+    if (p.mode == 1) {
+        return p.word;
+    }
+    return mem_read(address(p, rb, opcode, position));
+}
+
+/* Interprets a single instruction, returning 0 once the program halts or runs out of input */
+static int step(uint64_t *pc, int64_t *rb) {
+    uint64_t start = *pc;
+    uint64_t i = start;
+    int64_t word;
+    int64_t opcode;
+    Parameter p[3];
+    int64_t a;
+    int64_t b;
+    uint64_t to;
+
+    if (!mem_get(i, &word)) {
+        return 0;
+    }
+    i++;
+    opcode = word % 100;
+    switch (opcode) {
+    case 1:
+    case 2:
+    case 7:
+    case 8:
+        p[0] = parameter(&i, word / 100 % 10, 0, opcode, 0);
+        p[1] = parameter(&i, word / 1000 % 10, 1, opcode, 0);
+        p[2] = parameter(&i, word / 10000 % 10, 2, opcode, 1);
+        a = value(p[0], *rb, opcode, start);
+        b = value(p[1], *rb, opcode, start);
+        to = address(p[2], *rb, opcode, start);
+        switch (opcode) {
+        case 1:
+            a = checked_add(a, b, opcode, start);
+            break;
+        case 2:
+            a = checked_mul(a, b, opcode, start);
+            break;
+        case 7:
+            a = a < b;
+            break;
+        default:
+            a = a == b;
+            break;
+        }
+        mem_write(to, a);
+        invalidate(to);
+        break;
+    case 3:
+        p[0] = parameter(&i, word / 100 % 10, 0, opcode, 1);
+        if (!read_input(&a)) {
+            return 0;
+        }
+        to = address(p[0], *rb, opcode, start);
+        mem_write(to, a);
+        invalidate(to);
+        break;
+    case 4:
+        p[0] = parameter(&i, word / 100 % 10, 0, opcode, 0);
+        write_output(value(p[0], *rb, opcode, start));
+        break;
+    case 5:
+    case 6:
+        p[0] = parameter(&i, word / 100 % 10, 0, opcode, 0);
+        p[1] = parameter(&i, word / 1000 % 10, 1, opcode, 0);
+        a = value(p[0], *rb, opcode, start);
+        if ((a != 0) == (opcode == 5)) {
+            i = target(value(p[1], *rb, opcode, start), opcode, start);
+        }
+        break;
+    case 9:
+        p[0] = parameter(&i, word / 100 % 10, 0, opcode, 0);
+        *rb = checked_add(value(p[0], *rb, opcode, start), *rb, opcode, start);
+        break;
+    case 99:
+        return 0;
+    default:
+        fail("Invalid opcode \"%" PRId64 "\" at position %" PRIu64, opcode, i);
+    }
+    *pc = i;
+    return 1;
+}
+
+static void run(uint64_t pc, int64_t rb) {
+    for (;;) {
+        switch (pc) {
+            // blocks
+        default:
+        fallback:
+            if (!step(&pc, &rb)) {
+                return;
+            }
+        }
+    }
+}
+
+int main(void) {
+    // output
+    // code
+    // iterator
+    // relative base
+
+    memset(valid, 1, sizeof(valid));
+    run(i, rb);
+    return 0;
+}
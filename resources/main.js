@@ -0,0 +1,222 @@
+/* Values are BigInts, and addresses below this are stored in an array, the others in a map */
+const DENSE_LIMIT = 1n << 20n;
+const MAX = (1n << 63n) - 1n;
+const MIN = -(1n << 63n);
+
+// output
+// code
+// iterator
+// relative base
+// valid
+
+function fail(message) {
+  throw new Error(message);
+}
+
+function checked(value, opcode, position) {
+  if (value > MAX || value < MIN) {
+    fail(`Arithmetic overflow for opcode "${opcode}" at position ${position}`);
+  }
+  return value;
+}
+
+/* Accepts inputs as numbers, strings or BigInts as long as they fit in 64 bits */
+function input(value) {
+  const result = BigInt(value);
+  if (result > MAX || result < MIN) {
+    throw new RangeError(`Input ${value} does not fit in 64 bits`);
+  }
+  return result;
+}
+
+/**
+ * Runs the program step by step, yielding each output value as it is produced and `undefined`
+ * when it needs an input, which is then passed back through `next`
+ */
+export function* machine() {
+  const dense = IMAGE.slice();
+  const sparse = new Map(SPARSE);
+  const valid = new Uint8Array(BLOCKS).fill(1);
+  let pc = ENTRY;
+  let rb = RELATIVE_BASE;
+
+  /* Reads a cell, returning undefined if it lies past the end of memory */
+  function memGet(address) {
+    if (address < dense.length) {
+      return dense[Number(address)];
+    }
+    return sparse.get(address);
+  }
+
+  function memRead(address) {
+    const value = memGet(address);
+    return value === undefined ? 0n : value;
+  }
+
+  function memWrite(address, value) {
+    if (address < dense.length) {
+      dense[Number(address)] = value;
+    } else if (address < DENSE_LIMIT) {
+      while (dense.length < address) {
+        dense.push(0n);
+      }
+      dense.push(value);
+    } else {
+      sparse.set(address, value);
+    }
+  }
+
+  function relative(offset, opcode, position) {
+    const address = checked(rb + offset, opcode, position);
+    if (address < 0n) {
+      fail(`Address ${address} out of bounds for opcode "${opcode}" at position ${position}`);
+    }
+    return address;
+  }
+
+  /* Checks a jump target, which may lie right past the end of memory where the program halts */
+  function target(target, opcode, position) {
+    if (target < 0n || (target !== BigInt(dense.length) && memGet(target) === undefined)) {
+      fail(`Jump target ${target} out of bounds for opcode "${opcode}" at position ${position}`);
+    }
+    return target;
+  }
+
+  function invalidate(address) {
+    // invalidate
+  }
+
+  /* Reads the word of parameter `n` at `i` and checks it against its mode */
+  function parameter(i, mode, n, opcode, destination) {
+    const word = memGet(i);
+    if (word === undefined) {
+      fail(`Missing parameter ${n} for opcode "${opcode}" at position ${i}`);
+    }
+    if (mode === 0n && word < 0n) {
+      fail(
+        `Negative value ${word} for positional parameter ${n} for opcode "${opcode}" ` +
+          `at position ${i + 1n}`
+      );
+    }
+    if (mode < 0n || mode > 2n || (destination && mode === 1n)) {
+      fail(
+        `Invalid parameter mode "${mode}" for parameter ${n} of opcode "${opcode}" ` +
+          `at position ${i + 1n}`
+      );
+    }
+    return word;
+  }
+
+  function address(mode, word, opcode, position) {
+    return mode === 0n ? word : relative(word, opcode, position);
+  }
+
+  function value(mode, word, opcode, position) {
+    return mode === 1n ? word : memRead(address(mode, word, opcode, position));
+  }
+
+  /* Interprets a single instruction, returning false once the program halts */
+  function* step() {
+    const start = pc;
+    let i = start;
+    const word = memGet(i);
+    if (word === undefined) {
+      return false;
+    }
+    i += 1n;
+    const opcode = word % 100n;
+    const modes = [(word / 100n) % 10n, (word / 1000n) % 10n, (word / 10000n) % 10n];
+    const p = [];
+    const fetch = (n, destination) => {
+      p.push(parameter(i, modes[n], n, opcode, destination));
+      i += 1n;
+    };
+    switch (opcode) {
+      case 1n:
+      case 2n:
+      case 7n:
+      case 8n: {
+        fetch(0, false);
+        fetch(1, false);
+        fetch(2, true);
+        let a = value(modes[0], p[0], opcode, start);
+        const b = value(modes[1], p[1], opcode, start);
+        const to = address(modes[2], p[2], opcode, start);
+        if (opcode === 1n) {
+          a = checked(a + b, opcode, start);
+        } else if (opcode === 2n) {
+          a = checked(a * b, opcode, start);
+        } else if (opcode === 7n) {
+          a = a < b ? 1n : 0n;
+        } else {
+          a = a === b ? 1n : 0n;
+        }
+        memWrite(to, a);
+        invalidate(to);
+        break;
+      }
+      case 3n: {
+        fetch(0, true);
+        const a = input(yield);
+        const to = address(modes[0], p[0], opcode, start);
+        memWrite(to, a);
+        invalidate(to);
+        break;
+      }
+      case 4n:
+        fetch(0, false);
+        yield value(modes[0], p[0], opcode, start);
+        break;
+      case 5n:
+      case 6n:
+        fetch(0, false);
+        fetch(1, false);
+        if ((value(modes[0], p[0], opcode, start) !== 0n) === (opcode === 5n)) {
+          i = target(value(modes[1], p[1], opcode, start), opcode, start);
+        }
+        break;
+      case 9n:
+        fetch(0, false);
+        rb = checked(value(modes[0], p[0], opcode, start) + rb, opcode, start);
+        break;
+      case 99n:
+        return false;
+      default:
+        fail(`Invalid opcode "${opcode}" at position ${i}`);
+    }
+    pc = i;
+    return true;
+  }
+
+  yield* OUTPUT;
+  for (;;) {
+    switch (pc) {
+      // blocks
+    }
+    if (!(yield* step())) {
+      return;
+    }
+  }
+}
+
+/**
+ * Runs the program with a list of inputs, returning its outputs once it halts or runs out of
+ * input
+ */
+export function run(inputs = []) {
+  const outputs = [];
+  const program = machine();
+  let next = program.next();
+  let i = 0;
+  while (!next.done) {
+    if (next.value !== undefined) {
+      outputs.push(next.value);
+      next = program.next();
+    } else if (i < inputs.length) {
+      next = program.next(inputs[i++]);
+    } else {
+      break;
+    }
+  }
+  return outputs;
+}
@@ -0,0 +1,678 @@
+; Runtime of programs translated by the LLVM backend, using opaque pointers and the C library
+
+declare ptr @realloc(ptr, i64)
+declare i32 @printf(ptr, ...)
+declare i32 @putchar(i32)
+declare i32 @getchar()
+declare i32 @fflush(ptr)
+declare void @exit(i32) noreturn
+declare void @llvm.memset.p0.i64(ptr, i8, i64, i1)
+declare void @llvm.memcpy.p0.p0.i64(ptr, ptr, i64, i1)
+declare { i64, i1 } @llvm.sadd.with.overflow.i64(i64, i64)
+declare { i64, i1 } @llvm.ssub.with.overflow.i64(i64, i64)
+declare { i64, i1 } @llvm.smul.with.overflow.i64(i64, i64)
+
+@out_of_memory = private unnamed_addr constant [15 x i8] c"Out of memory\0A\00"
+@overflow_message = private unnamed_addr constant [56 x i8] c"Arithmetic overflow for opcode \22%lld\22 at position %lld\0A\00"
+@address_message = private unnamed_addr constant [63 x i8] c"Address %lld out of bounds for opcode \22%lld\22 at position %lld\0A\00"
+@jump_message = private unnamed_addr constant [67 x i8] c"Jump target %lld out of bounds for opcode \22%lld\22 at position %lld\0A\00"
+@opcode_message = private unnamed_addr constant [40 x i8] c"Invalid opcode \22%lld\22 at position %lld\0A\00"
+@missing_message = private unnamed_addr constant [59 x i8] c"Missing parameter %lld for opcode \22%lld\22 at position %lld\0A\00"
+@negative_message = private unnamed_addr constant [86 x i8] c"Negative value %lld for positional parameter %lld for opcode \22%lld\22 at position %lld\0A\00"
+@mode_message = private unnamed_addr constant [84 x i8] c"Invalid parameter mode \22%lld\22 for parameter %lld of opcode \22%lld\22 at position %lld\0A\00"
+@prompt = private unnamed_addr constant [3 x i8] c"> \00"
+@invalid_message = private unnamed_addr constant [10 x i8] c"Invalid\0A\0A\00"
+@number_format = private unnamed_addr constant [6 x i8] c"%lld\0A\00"
+
+; Addresses below 1 << 20 are stored in a contiguous array, the others in a list of cells
+@dense = internal global ptr null
+@dense_length = internal global i64 0
+@dense_capacity = internal global i64 0
+@sparse_addresses = internal global ptr null
+@sparse_values = internal global ptr null
+@sparse_length = internal global i64 0
+@sparse_capacity = internal global i64 0
+
+; Line being fed to the program in ASCII mode, followed by a newline
+@line = internal global ptr null
+@line_capacity = internal global i64 0
+@line_length = internal global i64 0
+@line_position = internal global i64 1
+
+; globals
+
+define internal void @fail_overflow(i64 %opcode, i64 %position) noreturn {
+  call i32 (ptr, ...) @printf(ptr @overflow_message, i64 %opcode, i64 %position)
+  call void @exit(i32 1)
+  unreachable
+}
+
+define internal void @fail_address(i64 %address, i64 %opcode, i64 %position) noreturn {
+  call i32 (ptr, ...) @printf(ptr @address_message, i64 %address, i64 %opcode, i64 %position)
+  call void @exit(i32 1)
+  unreachable
+}
+
+define internal void @fail_jump(i64 %target, i64 %opcode, i64 %position) noreturn {
+  call i32 (ptr, ...) @printf(ptr @jump_message, i64 %target, i64 %opcode, i64 %position)
+  call void @exit(i32 1)
+  unreachable
+}
+
+define internal ptr @allocate(ptr %pointer, i64 %size) {
+entry:
+  %result = call ptr @realloc(ptr %pointer, i64 %size)
+  %null = icmp eq ptr %result, null
+  br i1 %null, label %fail, label %ok
+fail:
+  call i32 (ptr, ...) @printf(ptr @out_of_memory)
+  call void @exit(i32 1)
+  unreachable
+ok:
+  ret ptr %result
+}
+
+define internal i64 @sparse_find(i64 %address) {
+entry:
+  %length = load i64, ptr @sparse_length
+  %addresses = load ptr, ptr @sparse_addresses
+  br label %loop
+loop:
+  %i = phi i64 [ 0, %entry ], [ %next, %check ]
+  %done = icmp eq i64 %i, %length
+  br i1 %done, label %missing, label %check
+check:
+  %slot = getelementptr i64, ptr %addresses, i64 %i
+  %candidate = load i64, ptr %slot
+  %found = icmp eq i64 %candidate, %address
+  %next = add i64 %i, 1
+  br i1 %found, label %hit, label %loop
+hit:
+  ret i64 %i
+missing:
+  ret i64 -1
+}
+
+; Reads a cell, returning false if it lies past the end of memory
+define internal i1 @mem_get(i64 %address, ptr %value) {
+entry:
+  %length = load i64, ptr @dense_length
+  %inside = icmp ult i64 %address, %length
+  br i1 %inside, label %dense, label %sparse
+dense:
+  %base = load ptr, ptr @dense
+  %slot = getelementptr i64, ptr %base, i64 %address
+  %cell = load i64, ptr %slot
+  store i64 %cell, ptr %value
+  ret i1 true
+sparse:
+  %index = call i64 @sparse_find(i64 %address)
+  %missing = icmp slt i64 %index, 0
+  br i1 %missing, label %none, label %found
+found:
+  %values = load ptr, ptr @sparse_values
+  %sparse_slot = getelementptr i64, ptr %values, i64 %index
+  %sparse_cell = load i64, ptr %sparse_slot
+  store i64 %sparse_cell, ptr %value
+  ret i1 true
+none:
+  ret i1 false
+}
+
+define internal i64 @mem_read(i64 %address) {
+entry:
+  %cell = alloca i64
+  %length = load i64, ptr @dense_length
+  %inside = icmp ult i64 %address, %length
+  br i1 %inside, label %dense, label %other
+dense:
+  %base = load ptr, ptr @dense
+  %slot = getelementptr i64, ptr %base, i64 %address
+  %value = load i64, ptr %slot
+  ret i64 %value
+other:
+  store i64 0, ptr %cell
+  call i1 @mem_get(i64 %address, ptr %cell)
+  %other_value = load i64, ptr %cell
+  ret i64 %other_value
+}
+
+define internal void @mem_write(i64 %address, i64 %value) {
+entry:
+  %length = load i64, ptr @dense_length
+  %inside = icmp ult i64 %address, %length
+  br i1 %inside, label %store, label %outside
+store:
+  %base = load ptr, ptr @dense
+  %slot = getelementptr i64, ptr %base, i64 %address
+  store i64 %value, ptr %slot
+  ret void
+outside:
+  %small = icmp ult i64 %address, 1048576
+  br i1 %small, label %grow, label %sparse
+grow:
+  %capacity = load i64, ptr @dense_capacity
+  %full = icmp uge i64 %address, %capacity
+  br i1 %full, label %reallocate, label %extend
+reallocate:
+  %doubled = shl i64 %capacity, 1
+  %needed = add i64 %address, 1
+  %enough = icmp ugt i64 %doubled, %address
+  %new_capacity = select i1 %enough, i64 %doubled, i64 %needed
+  store i64 %new_capacity, ptr @dense_capacity
+  %old = load ptr, ptr @dense
+  %bytes = shl i64 %new_capacity, 3
+  %new = call ptr @allocate(ptr %old, i64 %bytes)
+  store ptr %new, ptr @dense
+  br label %extend
+extend:
+  %grown = load ptr, ptr @dense
+  %gap_start = getelementptr i64, ptr %grown, i64 %length
+  %gap = sub i64 %address, %length
+  %gap_bytes = shl i64 %gap, 3
+  call void @llvm.memset.p0.i64(ptr %gap_start, i8 0, i64 %gap_bytes, i1 false)
+  %new_length = add i64 %address, 1
+  store i64 %new_length, ptr @dense_length
+  %grown_slot = getelementptr i64, ptr %grown, i64 %address
+  store i64 %value, ptr %grown_slot
+  ret void
+sparse:
+  %index = call i64 @sparse_find(i64 %address)
+  %missing = icmp slt i64 %index, 0
+  br i1 %missing, label %append, label %overwrite
+overwrite:
+  %values = load ptr, ptr @sparse_values
+  %sparse_slot = getelementptr i64, ptr %values, i64 %index
+  store i64 %value, ptr %sparse_slot
+  ret void
+append:
+  %count = load i64, ptr @sparse_length
+  %sparse_capacity = load i64, ptr @sparse_capacity
+  %sparse_full = icmp eq i64 %count, %sparse_capacity
+  br i1 %sparse_full, label %sparse_grow, label %sparse_store
+sparse_grow:
+  %sparse_doubled = shl i64 %sparse_capacity, 1
+  %sparse_grown = add i64 %sparse_doubled, 16
+  store i64 %sparse_grown, ptr @sparse_capacity
+  %sparse_bytes = shl i64 %sparse_grown, 3
+  %old_addresses = load ptr, ptr @sparse_addresses
+  %new_addresses = call ptr @allocate(ptr %old_addresses, i64 %sparse_bytes)
+  store ptr %new_addresses, ptr @sparse_addresses
+  %old_values = load ptr, ptr @sparse_values
+  %new_values = call ptr @allocate(ptr %old_values, i64 %sparse_bytes)
+  store ptr %new_values, ptr @sparse_values
+  br label %sparse_store
+sparse_store:
+  %addresses = load ptr, ptr @sparse_addresses
+  %address_slot = getelementptr i64, ptr %addresses, i64 %count
+  store i64 %address, ptr %address_slot
+  %cells = load ptr, ptr @sparse_values
+  %value_slot = getelementptr i64, ptr %cells, i64 %count
+  store i64 %value, ptr %value_slot
+  %new_count = add i64 %count, 1
+  store i64 %new_count, ptr @sparse_length
+  ret void
+}
+
+define internal i64 @relative(i64 %rb, i64 %offset, i64 %opcode, i64 %position) {
+entry:
+  %sum = call { i64, i1 } @llvm.sadd.with.overflow.i64(i64 %rb, i64 %offset)
+  %overflowed = extractvalue { i64, i1 } %sum, 1
+  br i1 %overflowed, label %overflow, label %checked
+overflow:
+  call void @fail_overflow(i64 %opcode, i64 %position)
+  unreachable
+checked:
+  %address = extractvalue { i64, i1 } %sum, 0
+  %negative = icmp slt i64 %address, 0
+  br i1 %negative, label %out_of_bounds, label %ok
+out_of_bounds:
+  call void @fail_address(i64 %address, i64 %opcode, i64 %position)
+  unreachable
+ok:
+  ret i64 %address
+}
+
+define internal i64 @checked_add(i64 %a, i64 %b, i64 %opcode, i64 %position) {
+entry:
+  %sum = call { i64, i1 } @llvm.sadd.with.overflow.i64(i64 %a, i64 %b)
+  %overflowed = extractvalue { i64, i1 } %sum, 1
+  br i1 %overflowed, label %overflow, label %ok
+overflow:
+  call void @fail_overflow(i64 %opcode, i64 %position)
+  unreachable
+ok:
+  %result = extractvalue { i64, i1 } %sum, 0
+  ret i64 %result
+}
+
+define internal i64 @checked_mul(i64 %a, i64 %b, i64 %opcode, i64 %position) {
+entry:
+  %product = call { i64, i1 } @llvm.smul.with.overflow.i64(i64 %a, i64 %b)
+  %overflowed = extractvalue { i64, i1 } %product, 1
+  br i1 %overflowed, label %overflow, label %ok
+overflow:
+  call void @fail_overflow(i64 %opcode, i64 %position)
+  unreachable
+ok:
+  %result = extractvalue { i64, i1 } %product, 0
+  ret i64 %result
+}
+
+; Checks a jump target, which may lie right past the end of memory where the program halts
+define internal i64 @target(i64 %target, i64 %opcode, i64 %position) {
+entry:
+  %cell = alloca i64
+  %negative = icmp slt i64 %target, 0
+  br i1 %negative, label %fail, label %end
+end:
+  %length = load i64, ptr @dense_length
+  %at_end = icmp eq i64 %target, %length
+  br i1 %at_end, label %ok, label %lookup
+lookup:
+  %exists = call i1 @mem_get(i64 %target, ptr %cell)
+  br i1 %exists, label %ok, label %fail
+fail:
+  call void @fail_jump(i64 %target, i64 %opcode, i64 %position)
+  unreachable
+ok:
+  ret i64 %target
+}
+
+define internal void @print(ptr %text, i64 %length) {
+entry:
+  br label %loop
+loop:
+  %i = phi i64 [ 0, %entry ], [ %next, %character ]
+  %done = icmp eq i64 %i, %length
+  br i1 %done, label %exit, label %character
+character:
+  %slot = getelementptr i8, ptr %text, i64 %i
+  %byte = load i8, ptr %slot
+  %c = zext i8 %byte to i32
+  call i32 @putchar(i32 %c)
+  %next = add i64 %i, 1
+  br label %loop
+exit:
+  ret void
+}
+
+; Reads a line into @line without its line ending, returning false at the end of input
+define internal i1 @read_line(ptr %length_out) {
+entry:
+  call i32 @fflush(ptr null)
+  br label %loop
+loop:
+  %length = phi i64 [ 0, %entry ], [ %next, %store ]
+  %c = call i32 @getchar()
+  %eof = icmp eq i32 %c, -1
+  br i1 %eof, label %end, label %check_newline
+check_newline:
+  %newline = icmp eq i32 %c, 10
+  br i1 %newline, label %done, label %reserve
+reserve:
+  %capacity = load i64, ptr @line_capacity
+  %needed = add i64 %length, 1
+  %full = icmp uge i64 %needed, %capacity
+  br i1 %full, label %grow, label %store
+grow:
+  %doubled = shl i64 %capacity, 1
+  %grown = add i64 %doubled, 128
+  store i64 %grown, ptr @line_capacity
+  %old = load ptr, ptr @line
+  %new = call ptr @allocate(ptr %old, i64 %grown)
+  store ptr %new, ptr @line
+  br label %store
+store:
+  %buffer = load ptr, ptr @line
+  %slot = getelementptr i8, ptr %buffer, i64 %length
+  %byte = trunc i32 %c to i8
+  store i8 %byte, ptr %slot
+  %next = add i64 %length, 1
+  br label %loop
+end:
+  %empty = icmp eq i64 %length, 0
+  br i1 %empty, label %finished, label %done
+finished:
+  ret i1 false
+done:
+  %has_characters = icmp ugt i64 %length, 0
+  br i1 %has_characters, label %check_return, label %trimmed
+check_return:
+  %text = load ptr, ptr @line
+  %last_index = sub i64 %length, 1
+  %last_slot = getelementptr i8, ptr %text, i64 %last_index
+  %last = load i8, ptr %last_slot
+  %carriage_return = icmp eq i8 %last, 13
+  %without_return = select i1 %carriage_return, i64 %last_index, i64 %length
+  br label %trimmed
+trimmed:
+  %final = phi i64 [ %length, %done ], [ %without_return, %check_return ]
+  store i64 %final, ptr %length_out
+  ret i1 true
+}
+
+; Parses a whole line as a decimal number, the same way Rust parses an i64
+define internal i1 @parse_value(ptr %text, i64 %length, ptr %value) {
+entry:
+  %empty = icmp eq i64 %length, 0
+  br i1 %empty, label %reject, label %sign
+sign:
+  %first = load i8, ptr %text
+  %minus = icmp eq i8 %first, 45
+  %plus = icmp eq i8 %first, 43
+  %signed = or i1 %minus, %plus
+  %start = zext i1 %signed to i64
+  %only_sign = icmp eq i64 %start, %length
+  br i1 %only_sign, label %reject, label %loop
+loop:
+  %i = phi i64 [ %start, %sign ], [ %next, %accumulate ]
+  %negated = phi i64 [ 0, %sign ], [ %difference, %accumulate ]
+  %done = icmp eq i64 %i, %length
+  br i1 %done, label %finish, label %digit
+digit:
+  %slot = getelementptr i8, ptr %text, i64 %i
+  %c = load i8, ptr %slot
+  %d = sub i8 %c, 48
+  %is_digit = icmp ult i8 %d, 10
+  br i1 %is_digit, label %accumulate, label %reject
+accumulate:
+  %digit_value = zext i8 %d to i64
+  %product = call { i64, i1 } @llvm.smul.with.overflow.i64(i64 %negated, i64 10)
+  %product_value = extractvalue { i64, i1 } %product, 0
+  %product_overflow = extractvalue { i64, i1 } %product, 1
+  %subtracted = call { i64, i1 } @llvm.ssub.with.overflow.i64(i64 %product_value, i64 %digit_value)
+  %difference = extractvalue { i64, i1 } %subtracted, 0
+  %difference_overflow = extractvalue { i64, i1 } %subtracted, 1
+  %overflow = or i1 %product_overflow, %difference_overflow
+  %next = add i64 %i, 1
+  br i1 %overflow, label %reject, label %loop
+finish:
+  br i1 %minus, label %negative, label %positive
+negative:
+  store i64 %negated, ptr %value
+  ret i1 true
+positive:
+  %positive_result = call { i64, i1 } @llvm.ssub.with.overflow.i64(i64 0, i64 %negated)
+  %positive_value = extractvalue { i64, i1 } %positive_result, 0
+  %positive_overflow = extractvalue { i64, i1 } %positive_result, 1
+  br i1 %positive_overflow, label %reject, label %accept
+accept:
+  store i64 %positive_value, ptr %value
+  ret i1 true
+reject:
+  ret i1 false
+}
+
+; Reads the next input, returning false at the end of input
+define internal i1 @read_input(ptr %value) {
+entry:
+  %length_cell = alloca i64
+  %ascii = load i1, ptr @ascii
+  br i1 %ascii, label %ascii_input, label %prompt
+ascii_input:
+  %position = load i64, ptr @line_position
+  %length = load i64, ptr @line_length
+  %exhausted = icmp ugt i64 %position, %length
+  br i1 %exhausted, label %refill, label %emit
+refill:
+  %refilled = call i1 @read_line(ptr @line_length)
+  br i1 %refilled, label %rewind, label %end
+rewind:
+  store i64 0, ptr @line_position
+  br label %emit
+emit:
+  %current = load i64, ptr @line_position
+  %current_length = load i64, ptr @line_length
+  %advanced = add i64 %current, 1
+  store i64 %advanced, ptr @line_position
+  %at_end = icmp eq i64 %current, %current_length
+  br i1 %at_end, label %newline, label %character
+newline:
+  store i64 10, ptr %value
+  ret i1 true
+character:
+  %text = load ptr, ptr @line
+  %slot = getelementptr i8, ptr %text, i64 %current
+  %byte = load i8, ptr %slot
+  %code = zext i8 %byte to i64
+  store i64 %code, ptr %value
+  ret i1 true
+prompt:
+  call i32 (ptr, ...) @printf(ptr @prompt)
+  %read = call i1 @read_line(ptr %length_cell)
+  br i1 %read, label %parse, label %end
+parse:
+  %line = load ptr, ptr @line
+  %line_length = load i64, ptr %length_cell
+  %parsed = call i1 @parse_value(ptr %line, i64 %line_length, ptr %value)
+  br i1 %parsed, label %accepted, label %invalid
+accepted:
+  call i32 @putchar(i32 10)
+  ret i1 true
+invalid:
+  call i32 (ptr, ...) @printf(ptr @invalid_message)
+  br label %prompt
+end:
+  ret i1 false
+}
+
+define internal void @write_output(i64 %value) {
+entry:
+  %ascii = load i1, ptr @ascii
+  %small = icmp ult i64 %value, 128
+  %character = and i1 %ascii, %small
+  br i1 %character, label %put, label %number
+put:
+  %c = trunc i64 %value to i32
+  call i32 @putchar(i32 %c)
+  ret void
+number:
+  call i32 (ptr, ...) @printf(ptr @number_format, i64 %value)
+  ret void
+}
+
+define internal void @invalidate(i64 %address) {
+entry:
+; invalidate
+  br label %exit
+exit:
+  ret void
+}
+
+; Decodes a parameter, moving the instruction pointer past it
+define internal i64 @parameter(ptr %i, i64 %mode, i64 %n, i64 %opcode, i1 %destination) {
+entry:
+  %cell = alloca i64
+  %position = load i64, ptr %i
+  %exists = call i1 @mem_get(i64 %position, ptr %cell)
+  br i1 %exists, label %found, label %missing
+missing:
+  call i32 (ptr, ...) @printf(ptr @missing_message, i64 %n, i64 %opcode, i64 %position)
+  call void @exit(i32 1)
+  unreachable
+found:
+  %word = load i64, ptr %cell
+  %after = add i64 %position, 1
+  store i64 %after, ptr %i
+  %is_position = icmp eq i64 %mode, 0
+  %is_negative = icmp slt i64 %word, 0
+  %negative_position = and i1 %is_position, %is_negative
+  br i1 %negative_position, label %negative, label %check_mode
+negative:
+  call i32 (ptr, ...) @printf(ptr @negative_message, i64 %word, i64 %n, i64 %opcode, i64 %after)
+  call void @exit(i32 1)
+  unreachable
+check_mode:
+  %unknown = icmp ugt i64 %mode, 2
+  %is_immediate = icmp eq i64 %mode, 1
+  %immediate_destination = and i1 %is_immediate, %destination
+  %invalid = or i1 %unknown, %immediate_destination
+  br i1 %invalid, label %invalid_mode, label %ok
+invalid_mode:
+  call i32 (ptr, ...) @printf(ptr @mode_message, i64 %mode, i64 %n, i64 %opcode, i64 %after)
+  call void @exit(i32 1)
+  unreachable
+ok:
+  ret i64 %word
+}
+
+define internal i64 @address(i64 %mode, i64 %word, i64 %rb, i64 %opcode, i64 %position) {
+entry:
+  %is_position = icmp eq i64 %mode, 0
+  br i1 %is_position, label %absolute, label %relative
+absolute:
+  ret i64 %word
+relative:
+  %address = call i64 @relative(i64 %rb, i64 %word, i64 %opcode, i64 %position)
+  ret i64 %address
+}
+
+define internal i64 @value(i64 %mode, i64 %word, i64 %rb, i64 %opcode, i64 %position) {
+entry:
+  %is_immediate = icmp eq i64 %mode, 1
+  br i1 %is_immediate, label %immediate, label %memory
+immediate:
+  ret i64 %word
+memory:
+  %address = call i64 @address(i64 %mode, i64 %word, i64 %rb, i64 %opcode, i64 %position)
+  %value = call i64 @mem_read(i64 %address)
+  ret i64 %value
+}
+
+; Interprets a single instruction, returning false once the program halts or runs out of input
+define internal i1 @step(ptr %pc, ptr %rb) {
+entry:
+  %cell = alloca i64
+  %i = alloca i64
+  %start = load i64, ptr %pc
+  %exists = call i1 @mem_get(i64 %start, ptr %cell)
+  br i1 %exists, label %decode, label %halt
+decode:
+  %word = load i64, ptr %cell
+  %after = add i64 %start, 1
+  store i64 %after, ptr %i
+  %opcode = srem i64 %word, 100
+  %modes = sdiv i64 %word, 100
+  %mode1 = srem i64 %modes, 10
+  %modes2 = sdiv i64 %modes, 10
+  %mode2 = srem i64 %modes2, 10
+  %modes3 = sdiv i64 %modes2, 10
+  %mode3 = srem i64 %modes3, 10
+  %base = load i64, ptr %rb
+  switch i64 %opcode, label %invalid [
+    i64 1, label %arithmetic
+    i64 2, label %arithmetic
+    i64 7, label %arithmetic
+    i64 8, label %arithmetic
+    i64 3, label %input
+    i64 4, label %output
+    i64 5, label %jump
+    i64 6, label %jump
+    i64 9, label %adjust
+    i64 99, label %halt
+  ]
+arithmetic:
+  %w1 = call i64 @parameter(ptr %i, i64 %mode1, i64 0, i64 %opcode, i1 false)
+  %w2 = call i64 @parameter(ptr %i, i64 %mode2, i64 1, i64 %opcode, i1 false)
+  %w3 = call i64 @parameter(ptr %i, i64 %mode3, i64 2, i64 %opcode, i1 true)
+  %a = call i64 @value(i64 %mode1, i64 %w1, i64 %base, i64 %opcode, i64 %start)
+  %b = call i64 @value(i64 %mode2, i64 %w2, i64 %base, i64 %opcode, i64 %start)
+  %to = call i64 @address(i64 %mode3, i64 %w3, i64 %base, i64 %opcode, i64 %start)
+  switch i64 %opcode, label %equals [
+    i64 1, label %add
+    i64 2, label %multiply
+    i64 7, label %less_than
+  ]
+add:
+  %sum = call i64 @checked_add(i64 %a, i64 %b, i64 %opcode, i64 %start)
+  br label %store
+multiply:
+  %product = call i64 @checked_mul(i64 %a, i64 %b, i64 %opcode, i64 %start)
+  br label %store
+less_than:
+  %less = icmp slt i64 %a, %b
+  %less_value = zext i1 %less to i64
+  br label %store
+equals:
+  %equal = icmp eq i64 %a, %b
+  %equal_value = zext i1 %equal to i64
+  br label %store
+store:
+  %result = phi i64 [ %sum, %add ], [ %product, %multiply ], [ %less_value, %less_than ], [ %equal_value, %equals ]
+  call void @mem_write(i64 %to, i64 %result)
+  call void @invalidate(i64 %to)
+  br label %next
+input:
+  %input_word = call i64 @parameter(ptr %i, i64 %mode1, i64 0, i64 %opcode, i1 true)
+  %read = call i1 @read_input(ptr %cell)
+  br i1 %read, label %input_store, label %halt
+input_store:
+  %input_value = load i64, ptr %cell
+  %input_to = call i64 @address(i64 %mode1, i64 %input_word, i64 %base, i64 %opcode, i64 %start)
+  call void @mem_write(i64 %input_to, i64 %input_value)
+  call void @invalidate(i64 %input_to)
+  br label %next
+output:
+  %output_word = call i64 @parameter(ptr %i, i64 %mode1, i64 0, i64 %opcode, i1 false)
+  %output_value = call i64 @value(i64 %mode1, i64 %output_word, i64 %base, i64 %opcode, i64 %start)
+  call void @write_output(i64 %output_value)
+  br label %next
+jump:
+  %test_word = call i64 @parameter(ptr %i, i64 %mode1, i64 0, i64 %opcode, i1 false)
+  %goto_word = call i64 @parameter(ptr %i, i64 %mode2, i64 1, i64 %opcode, i1 false)
+  %test = call i64 @value(i64 %mode1, i64 %test_word, i64 %base, i64 %opcode, i64 %start)
+  %nonzero = icmp ne i64 %test, 0
+  %if_true = icmp eq i64 %opcode, 5
+  %taken = icmp eq i1 %nonzero, %if_true
+  br i1 %taken, label %jump_taken, label %next
+jump_taken:
+  %goto = call i64 @value(i64 %mode2, i64 %goto_word, i64 %base, i64 %opcode, i64 %start)
+  %target = call i64 @target(i64 %goto, i64 %opcode, i64 %start)
+  store i64 %target, ptr %i
+  br label %next
+adjust:
+  %by_word = call i64 @parameter(ptr %i, i64 %mode1, i64 0, i64 %opcode, i1 false)
+  %by = call i64 @value(i64 %mode1, i64 %by_word, i64 %base, i64 %opcode, i64 %start)
+  %new_base = call i64 @checked_add(i64 %by, i64 %base, i64 %opcode, i64 %start)
+  store i64 %new_base, ptr %rb
+  br label %next
+invalid:
+  %invalid_position = load i64, ptr %i
+  call i32 (ptr, ...) @printf(ptr @opcode_message, i64 %opcode, i64 %invalid_position)
+  call void @exit(i32 1)
+  unreachable
+next:
+  %next_ip = load i64, ptr %i
+  store i64 %next_ip, ptr %pc
+  ret i1 true
+halt:
+  ret i1 false
+}
+
+define internal void @run(i64 %entry_pc, i64 %entry_rb) {
+entry:
+  %pc = alloca i64
+  %rb = alloca i64
+  %cell = alloca i64
+  store i64 %entry_pc, ptr %pc
+  store i64 %entry_rb, ptr %rb
+  br label %dispatch
+dispatch:
+  %address = load i64, ptr %pc
+  switch i64 %address, label %fallback [
+; cases
+  ]
+; blocks
+fallback:
+  %running = call i1 @step(ptr %pc, ptr %rb)
+  br i1 %running, label %dispatch, label %exit
+exit:
+  ret void
+}
+
+define i32 @main() {
+entry:
+; main
+  ret i32 0
+}
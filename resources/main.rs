@@ -1,10 +1,91 @@
+use io::{IntcodeInput, IntcodeOutput};
+use runtime::{Error, Machine, Memory, Status};
+use std::convert::TryInto;
+
+#[allow(dead_code)]
+fn relative(rb: isize, offset: isize, opcode: i64, position: usize) -> Result<usize, Error> {
+    let address = rb
+        .checked_add(offset)
+        .ok_or(Error::ArithmeticOverflow { opcode, position })?;
+    address.try_into().map_err(|_| Error::AddressOutOfBounds {
+        address: address as i64,
+        opcode,
+        position,
+    })
+}
+
+#[allow(dead_code)]
+fn checked(result: Option<i64>, opcode: i64, position: usize) -> Result<i64, Error> {
+    result.ok_or(Error::ArithmeticOverflow { opcode, position })
+}
+
+#[allow(dead_code)]
+fn target(code: &Memory, target: i64, opcode: i64, position: usize) -> Result<usize, Error> {
+    match target.try_into() {
+        Ok(t) if code.get(t).is_some() || t == code.dense().len() => Ok(t),
+        _ => Err(Error::JumpOutOfBounds {
+            target,
+            opcode,
+            position,
+        }),
+    }
+}
+
+#[allow(dead_code)]
+fn invalidate(valid: &mut [bool], address: usize) {
+    let blocks: &[usize] = match address {
+        // invalidate
+        _ => return,
+    };
+    for block in blocks {
+        valid[*block] = false;
+    }
+}
+
+#[allow(unused_mut, unused_variables)]
+fn run(
+    mut machine: Machine,
+    input: &mut dyn IntcodeInput,
+    output: &mut dyn IntcodeOutput,
+) -> Result<(), Error> {
+    // valid
+    let mut pc = machine.ip();
+    let mut rb = machine.relative_base();
+    loop {
+        match pc {
+            // blocks
+            _ => {
+                machine.set_ip(pc);
+                machine.set_relative_base(rb);
+                match machine.step()? {
+                    Status::NeedsInput => match input.read() {
+                        Some(i) => machine.push_input(i),
+                        None => return Ok(()),
+                    },
+                    Status::Output(o) => output.write(o),
+                    Status::Halted => return Ok(()),
+                    Status::Running => (),
+                }
+                if let Some(address) = machine.last_write() {
+                    invalidate(&mut valid, address);
+                }
+                pc = machine.ip();
+                rb = machine.relative_base();
+            }
+        }
+    }
+}
+
 fn main() {
     // output
     // code
     // iterator
+    // relative base
+    // io
 
-    let result = run(&mut code, i);
+    let result = run(Machine::with_state(code, i, rb), &mut input, &mut output);
     if let Err(e) = result {
         println!("{}", e);
+        std::process::exit(1);
     }
 }
@@ -0,0 +1,398 @@
+(module
+  ;; Returns the next input value, the host throws to stop the program once it runs out of input
+  (import "env" "input" (func $input (result i64)))
+  (import "env" "output" (func $output (param i64)))
+
+  ;; Linear memory holds the sparse cells' addresses at 0 and their values at 524288, 65536 of
+  ;; each, then the validity flags of the blocks at 1048576, then the dense cells at `cells`
+  ;; memory
+
+  ;; Set right before trapping: 1 for an arithmetic overflow, 2 for an address out of bounds, 3
+  ;; for a jump target out of bounds, 4 for an invalid opcode, 5 for a missing parameter, 6 for a
+  ;; negative positional parameter, 7 for an invalid parameter mode and 8 when out of memory
+  (global $fault (export "fault") (mut i32) (i32.const 0))
+  (global $fault_value (export "fault_value") (mut i64) (i64.const 0))
+  (global $fault_parameter (export "fault_parameter") (mut i64) (i64.const 0))
+  (global $fault_opcode (export "fault_opcode") (mut i64) (i64.const 0))
+  (global $fault_position (export "fault_position") (mut i64) (i64.const 0))
+
+  (global $sparse_length (mut i32) (i32.const 0))
+  ;; state
+
+  (func $fail
+    (param $fault i32) (param $value i64) (param $parameter i64) (param $opcode i64)
+    (param $position i64)
+    (global.set $fault (local.get $fault))
+    (global.set $fault_value (local.get $value))
+    (global.set $fault_parameter (local.get $parameter))
+    (global.set $fault_opcode (local.get $opcode))
+    (global.set $fault_position (local.get $position))
+    (unreachable))
+
+  (func $out_of_memory
+    (call $fail (i32.const 8) (i64.const 0) (i64.const 0) (i64.const 0) (i64.const 0)))
+
+  ;; Offset of a dense cell
+  (func $cell (param $address i64) (result i32)
+    (i32.add
+      (global.get $cells)
+      (i32.shl (i32.wrap_i64 (local.get $address)) (i32.const 3))))
+
+  ;; Offset of the address of a sparse cell, or of the free slot it would be stored in
+  (func $sparse_slot (param $address i64) (result i32)
+    (local $slot i32)
+    (local $stored i64)
+    (local.set $slot
+      (i32.wrap_i64
+        (i64.shr_u
+          (i64.mul (local.get $address) (i64.const 0x9e3779b97f4a7c15))
+          (i64.const 48))))
+    (block $found
+      (loop $probe
+        (local.set $stored (i64.load (i32.shl (local.get $slot) (i32.const 3))))
+        (br_if $found (i64.eqz (local.get $stored)))
+        (br_if $found (i64.eq (local.get $stored) (local.get $address)))
+        (local.set $slot (i32.and (i32.add (local.get $slot) (i32.const 1)) (i32.const 65535)))
+        (br $probe)))
+    (i32.shl (local.get $slot) (i32.const 3)))
+
+  ;; Whether a cell lies within memory
+  (func $mem_has (param $address i64) (result i32)
+    (if (i64.lt_u (local.get $address) (global.get $length))
+      (then (return (i32.const 1))))
+    (if (i64.lt_u (local.get $address) (i64.const 1048576))
+      (then (return (i32.const 0))))
+    (i64.ne (i64.load (call $sparse_slot (local.get $address))) (i64.const 0)))
+
+  (func $mem_read (param $address i64) (result i64)
+    (local $slot i32)
+    (if (i64.lt_u (local.get $address) (global.get $length))
+      (then (return (i64.load (call $cell (local.get $address))))))
+    (if (i64.lt_u (local.get $address) (i64.const 1048576))
+      (then (return (i64.const 0))))
+    (local.set $slot (call $sparse_slot (local.get $address)))
+    (if (i64.eqz (i64.load (local.get $slot)))
+      (then (return (i64.const 0))))
+    (i64.load offset=524288 (local.get $slot)))
+
+  (func $mem_write (param $address i64) (param $value i64)
+    (local $end i32)
+    (local $slot i32)
+    (if (i64.lt_u (local.get $address) (global.get $length))
+      (then
+        (i64.store (call $cell (local.get $address)) (local.get $value))
+        (return)))
+    (if (i64.lt_u (local.get $address) (i64.const 1048576))
+      (then
+        ;; Fresh pages are zeroed and cells past the end are never written, so growing the dense
+        ;; cells only takes enough pages to hold them
+        (local.set $end (i32.add (call $cell (local.get $address)) (i32.const 8)))
+        (if (i32.gt_u (local.get $end) (i32.shl (memory.size) (i32.const 16)))
+          (then
+            (if (i32.eq
+                  (memory.grow
+                    (i32.sub
+                      (i32.shr_u (i32.add (local.get $end) (i32.const 65535)) (i32.const 16))
+                      (memory.size)))
+                  (i32.const -1))
+              (then (call $out_of_memory)))))
+        (global.set $length (i64.add (local.get $address) (i64.const 1)))
+        (i64.store (call $cell (local.get $address)) (local.get $value))
+        (return)))
+    (local.set $slot (call $sparse_slot (local.get $address)))
+    (if (i64.eqz (i64.load (local.get $slot)))
+      (then
+        ;; Keeps at least half of the slots free so probing stays short
+        (if (i32.ge_u (global.get $sparse_length) (i32.const 32768))
+          (then (call $out_of_memory)))
+        (global.set $sparse_length (i32.add (global.get $sparse_length) (i32.const 1)))
+        (i64.store (local.get $slot) (local.get $address))))
+    (i64.store offset=524288 (local.get $slot) (local.get $value)))
+
+  (func $overflow (param $opcode i64) (param $position i64)
+    (call $fail
+      (i32.const 1) (i64.const 0) (i64.const 0) (local.get $opcode) (local.get $position)))
+
+  (func $checked_add
+    (param $a i64) (param $b i64) (param $opcode i64) (param $position i64) (result i64)
+    (if (i32.or
+          (i32.and
+            (i64.gt_s (local.get $b) (i64.const 0))
+            (i64.gt_s (local.get $a) (i64.sub (i64.const 0x7fffffffffffffff) (local.get $b))))
+          (i32.and
+            (i64.lt_s (local.get $b) (i64.const 0))
+            (i64.lt_s (local.get $a) (i64.sub (i64.const -0x8000000000000000) (local.get $b)))))
+      (then (call $overflow (local.get $opcode) (local.get $position))))
+    (i64.add (local.get $a) (local.get $b)))
+
+  (func $checked_mul
+    (param $a i64) (param $b i64) (param $opcode i64) (param $position i64) (result i64)
+    (local $overflows i32)
+    ;; Divisions trap where C's would be undefined, so each one sits behind its own test
+    (if (i64.gt_s (local.get $a) (i64.const 0))
+      (then
+        (if (i64.gt_s (local.get $b) (i64.const 0))
+          (then
+            (local.set $overflows
+              (i64.gt_s
+                (local.get $a)
+                (i64.div_s (i64.const 0x7fffffffffffffff) (local.get $b)))))
+          (else
+            (local.set $overflows
+              (i64.lt_s
+                (local.get $b)
+                (i64.div_s (i64.const -0x8000000000000000) (local.get $a)))))))
+      (else
+        (if (i64.gt_s (local.get $b) (i64.const 0))
+          (then
+            (local.set $overflows
+              (i64.lt_s
+                (local.get $a)
+                (i64.div_s (i64.const -0x8000000000000000) (local.get $b)))))
+          (else
+            (if (i64.ne (local.get $a) (i64.const 0))
+              (then
+                (local.set $overflows
+                  (i64.lt_s
+                    (local.get $b)
+                    (i64.div_s (i64.const 0x7fffffffffffffff) (local.get $a))))))))))
+    (if (local.get $overflows)
+      (then (call $overflow (local.get $opcode) (local.get $position))))
+    (i64.mul (local.get $a) (local.get $b)))
+
+  (func $relative
+    (param $rb i64) (param $offset i64) (param $opcode i64) (param $position i64) (result i64)
+    (local $address i64)
+    (local.set $address
+      (call $checked_add
+        (local.get $rb) (local.get $offset) (local.get $opcode) (local.get $position)))
+    (if (i64.lt_s (local.get $address) (i64.const 0))
+      (then
+        (call $fail
+          (i32.const 2) (local.get $address) (i64.const 0) (local.get $opcode)
+          (local.get $position))))
+    (local.get $address))
+
+  ;; Checks a jump target, which may lie right past the end of memory where the program halts
+  (func $target (param $target i64) (param $opcode i64) (param $position i64) (result i64)
+    (if (i32.or
+          (i64.lt_s (local.get $target) (i64.const 0))
+          (i32.and
+            (i64.ne (local.get $target) (global.get $length))
+            (i32.eqz (call $mem_has (local.get $target)))))
+      (then
+        (call $fail
+          (i32.const 3) (local.get $target) (i64.const 0) (local.get $opcode)
+          (local.get $position))))
+    (local.get $target))
+
+  ;; Clears the validity flags of the blocks covering an address, with the covered ranges in
+  ;; ascending order
+  (func $invalidate (param $address i64)
+    ;; invalidate
+  )
+
+  ;; Reads the word of parameter `n` at `i` and checks it against its mode
+  (func $parameter
+    (param $i i64) (param $mode i64) (param $n i64) (param $opcode i64) (param $destination i32)
+    (result i64)
+    (local $word i64)
+    (if (i32.eqz (call $mem_has (local.get $i)))
+      (then
+        (call $fail
+          (i32.const 5) (i64.const 0) (local.get $n) (local.get $opcode) (local.get $i))))
+    (local.set $word (call $mem_read (local.get $i)))
+    (if (i32.and
+          (i64.eqz (local.get $mode))
+          (i64.lt_s (local.get $word) (i64.const 0)))
+      (then
+        (call $fail
+          (i32.const 6) (local.get $word) (local.get $n) (local.get $opcode)
+          (i64.add (local.get $i) (i64.const 1)))))
+    (if (i32.or
+          (i32.or
+            (i64.lt_s (local.get $mode) (i64.const 0))
+            (i64.gt_s (local.get $mode) (i64.const 2)))
+          (i32.and (local.get $destination) (i64.eq (local.get $mode) (i64.const 1))))
+      (then
+        (call $fail
+          (i32.const 7) (local.get $mode) (local.get $n) (local.get $opcode)
+          (i64.add (local.get $i) (i64.const 1)))))
+    (local.get $word))
+
+  (func $address
+    (param $mode i64) (param $word i64) (param $opcode i64) (param $position i64) (result i64)
+    (if (result i64) (i64.eqz (local.get $mode))
+      (then (local.get $word))
+      (else
+        (call $relative
+          (global.get $rb) (local.get $word) (local.get $opcode) (local.get $position)))))
+
+  (func $value
+    (param $mode i64) (param $word i64) (param $opcode i64) (param $position i64) (result i64)
+    (if (result i64) (i64.eq (local.get $mode) (i64.const 1))
+      (then (local.get $word))
+      (else
+        (call $mem_read
+          (call $address
+            (local.get $mode) (local.get $word) (local.get $opcode) (local.get $position))))))
+
+  ;; Interprets a single instruction, returning 0 once the program halts
+  (func $step (result i32)
+    (local $start i64)
+    (local $i i64)
+    (local $word i64)
+    (local $opcode i64)
+    (local $m0 i64)
+    (local $m1 i64)
+    (local $m2 i64)
+    (local $w0 i64)
+    (local $w1 i64)
+    (local $w2 i64)
+    (local $a i64)
+    (local $b i64)
+    (local $to i64)
+    (local.set $start (global.get $pc))
+    (local.set $i (local.get $start))
+    (if (i32.eqz (call $mem_has (local.get $i)))
+      (then (return (i32.const 0))))
+    (local.set $word (call $mem_read (local.get $i)))
+    (local.set $i (i64.add (local.get $i) (i64.const 1)))
+    (local.set $opcode (i64.rem_s (local.get $word) (i64.const 100)))
+    (local.set $m0 (i64.rem_s (i64.div_s (local.get $word) (i64.const 100)) (i64.const 10)))
+    (local.set $m1 (i64.rem_s (i64.div_s (local.get $word) (i64.const 1000)) (i64.const 10)))
+    (local.set $m2 (i64.rem_s (i64.div_s (local.get $word) (i64.const 10000)) (i64.const 10)))
+    (block $done
+      (if (i32.or
+            (i32.or
+              (i64.eq (local.get $opcode) (i64.const 1))
+              (i64.eq (local.get $opcode) (i64.const 2)))
+            (i32.or
+              (i64.eq (local.get $opcode) (i64.const 7))
+              (i64.eq (local.get $opcode) (i64.const 8))))
+        (then
+          (local.set $w0
+            (call $parameter
+              (local.get $i) (local.get $m0) (i64.const 0) (local.get $opcode) (i32.const 0)))
+          (local.set $i (i64.add (local.get $i) (i64.const 1)))
+          (local.set $w1
+            (call $parameter
+              (local.get $i) (local.get $m1) (i64.const 1) (local.get $opcode) (i32.const 0)))
+          (local.set $i (i64.add (local.get $i) (i64.const 1)))
+          (local.set $w2
+            (call $parameter
+              (local.get $i) (local.get $m2) (i64.const 2) (local.get $opcode) (i32.const 1)))
+          (local.set $i (i64.add (local.get $i) (i64.const 1)))
+          (local.set $a
+            (call $value
+              (local.get $m0) (local.get $w0) (local.get $opcode) (local.get $start)))
+          (local.set $b
+            (call $value
+              (local.get $m1) (local.get $w1) (local.get $opcode) (local.get $start)))
+          (local.set $to
+            (call $address
+              (local.get $m2) (local.get $w2) (local.get $opcode) (local.get $start)))
+          (if (i64.eq (local.get $opcode) (i64.const 1))
+            (then
+              (local.set $a
+                (call $checked_add
+                  (local.get $a) (local.get $b) (local.get $opcode) (local.get $start))))
+            (else
+              (if (i64.eq (local.get $opcode) (i64.const 2))
+                (then
+                  (local.set $a
+                    (call $checked_mul
+                      (local.get $a) (local.get $b) (local.get $opcode) (local.get $start))))
+                (else
+                  (if (i64.eq (local.get $opcode) (i64.const 7))
+                    (then
+                      (local.set $a
+                        (i64.extend_i32_u (i64.lt_s (local.get $a) (local.get $b)))))
+                    (else
+                      (local.set $a
+                        (i64.extend_i32_u (i64.eq (local.get $a) (local.get $b))))))))))
+          (call $mem_write (local.get $to) (local.get $a))
+          (call $invalidate (local.get $to))
+          (br $done)))
+      (if (i64.eq (local.get $opcode) (i64.const 3))
+        (then
+          (local.set $w0
+            (call $parameter
+              (local.get $i) (local.get $m0) (i64.const 0) (local.get $opcode) (i32.const 1)))
+          (local.set $i (i64.add (local.get $i) (i64.const 1)))
+          (local.set $a (call $input))
+          (local.set $to
+            (call $address
+              (local.get $m0) (local.get $w0) (local.get $opcode) (local.get $start)))
+          (call $mem_write (local.get $to) (local.get $a))
+          (call $invalidate (local.get $to))
+          (br $done)))
+      (if (i64.eq (local.get $opcode) (i64.const 4))
+        (then
+          (local.set $w0
+            (call $parameter
+              (local.get $i) (local.get $m0) (i64.const 0) (local.get $opcode) (i32.const 0)))
+          (local.set $i (i64.add (local.get $i) (i64.const 1)))
+          (call $output
+            (call $value
+              (local.get $m0) (local.get $w0) (local.get $opcode) (local.get $start)))
+          (br $done)))
+      (if (i32.or
+            (i64.eq (local.get $opcode) (i64.const 5))
+            (i64.eq (local.get $opcode) (i64.const 6)))
+        (then
+          (local.set $w0
+            (call $parameter
+              (local.get $i) (local.get $m0) (i64.const 0) (local.get $opcode) (i32.const 0)))
+          (local.set $i (i64.add (local.get $i) (i64.const 1)))
+          (local.set $w1
+            (call $parameter
+              (local.get $i) (local.get $m1) (i64.const 1) (local.get $opcode) (i32.const 0)))
+          (local.set $i (i64.add (local.get $i) (i64.const 1)))
+          (local.set $a
+            (call $value
+              (local.get $m0) (local.get $w0) (local.get $opcode) (local.get $start)))
+          (if (i32.eq
+                (i64.ne (local.get $a) (i64.const 0))
+                (i64.eq (local.get $opcode) (i64.const 5)))
+            (then
+              (local.set $i
+                (call $target
+                  (call $value
+                    (local.get $m1) (local.get $w1) (local.get $opcode) (local.get $start))
+                  (local.get $opcode)
+                  (local.get $start)))))
+          (br $done)))
+      (if (i64.eq (local.get $opcode) (i64.const 9))
+        (then
+          (local.set $w0
+            (call $parameter
+              (local.get $i) (local.get $m0) (i64.const 0) (local.get $opcode) (i32.const 0)))
+          (local.set $i (i64.add (local.get $i) (i64.const 1)))
+          (global.set $rb
+            (call $checked_add
+              (call $value
+                (local.get $m0) (local.get $w0) (local.get $opcode) (local.get $start))
+              (global.get $rb)
+              (local.get $opcode)
+              (local.get $start)))
+          (br $done)))
+      (if (i64.eq (local.get $opcode) (i64.const 99))
+        (then (return (i32.const 0))))
+      (call $fail
+        (i32.const 4) (i64.const 0) (i64.const 0) (local.get $opcode) (local.get $i)))
+    (global.set $pc (local.get $i))
+    (i32.const 1))
+
+  ;; Runs the program to completion, and may only be called once
+  (func (export "run")
+    (local $n1 i64)
+    (local $n2 i64)
+    (local $v i64)
+    (local $to i64)
+    ;; output
+    ;; code
+    (loop $dispatch
+      ;; blocks
+      (br_if $dispatch (call $step))))
+)
@@ -0,0 +1,325 @@
+use crate::{disasm::OPCODES, error::Error};
+use std::collections::HashMap;
+
+/// A token along with the column it starts at
+struct Token<'a> {
+    text: &'a str,
+    column: usize,
+}
+
+enum Statement<'a> {
+    Instruction {
+        mnemonic: Token<'a>,
+        operands: Vec<Token<'a>>,
+    },
+    Data {
+        values: Vec<Token<'a>>,
+    },
+}
+
+struct Line<'a> {
+    number: usize,
+    statement: Statement<'a>,
+}
+
+fn opcode(mnemonic: &str) -> Option<(i64, usize)> {
+    OPCODES
+        .iter()
+        .find(|(_, m, _)| *m == mnemonic)
+        .map(|(opcode, _, count)| (*opcode, *count))
+}
+
+fn is_label(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+/// Column of `inner`, which must be a slice of `outer`
+fn column(outer: &str, inner: &str) -> usize {
+    inner.as_ptr() as usize - outer.as_ptr() as usize + 1
+}
+
+/// Splits the operands of a line on commas
+fn split_operands<'a>(line: &'a str, operands: &'a str) -> Vec<Token<'a>> {
+    operands
+        .split(',')
+        .map(|piece| {
+            let text = piece.trim();
+            Token {
+                text,
+                column: column(line, text),
+            }
+        })
+        .collect()
+}
+
+/// Splits a line into its labels and statement
+fn parse_line(line: &str, number: usize) -> Result<(Vec<Token<'_>>, Option<Line<'_>>), Error> {
+    let code = match line.find(';') {
+        Some(i) => &line[..i],
+        None => line,
+    };
+
+    let mut labels = Vec::new();
+    let mut rest = code.trim();
+    while let Some(i) = rest.find(':') {
+        let label = rest[..i].trim_end();
+        if !is_label(label) {
+            break;
+        }
+        labels.push(Token {
+            text: label,
+            column: column(line, label),
+        });
+        rest = rest[i + 1..].trim_start();
+    }
+
+    if rest.is_empty() {
+        return Ok((labels, None));
+    }
+
+    let (word, operands) = match rest.find(char::is_whitespace) {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, ""),
+    };
+    let column = column(line, word);
+    let operands = if operands.trim().is_empty() {
+        Vec::new()
+    } else {
+        split_operands(line, operands)
+    };
+
+    let statement = if word == ".data" {
+        if operands.is_empty() {
+            return Err(Error::OperandCount {
+                mnemonic: word.to_owned(),
+                expected: 1,
+                found: 0,
+                line: number,
+                column,
+            });
+        }
+        Statement::Data { values: operands }
+    } else {
+        Statement::Instruction {
+            mnemonic: Token { text: word, column },
+            operands,
+        }
+    };
+    Ok((labels, Some(Line { number, statement })))
+}
+
+fn resolve(
+    expression: &str,
+    labels: &HashMap<&str, usize>,
+    line: usize,
+    column: usize,
+) -> Result<i64, Error> {
+    if let Ok(v) = expression.parse() {
+        return Ok(v);
+    }
+
+    let (label, offset) = match expression.find(['+', '-']) {
+        Some(i) => (expression[..i].trim(), expression[i..].replace(' ', "")),
+        None => (expression, "0".to_owned()),
+    };
+    let invalid = || Error::InvalidOperand {
+        operand: expression.to_owned(),
+        line,
+        column,
+    };
+    if !is_label(label) {
+        return Err(invalid());
+    }
+    let offset: i64 = offset
+        .trim_start_matches('+')
+        .parse()
+        .map_err(|_| invalid())?;
+    match labels.get(label) {
+        Some(address) => Ok(*address as i64 + offset),
+        None => Err(Error::UndefinedLabel {
+            label: label.to_owned(),
+            line,
+            column,
+        }),
+    }
+}
+
+/// Returns the mode and value of an operand
+fn operand(
+    token: &Token<'_>,
+    labels: &HashMap<&str, usize>,
+    line: usize,
+) -> Result<(i64, i64), Error> {
+    let text = token.text;
+    let (mode, inner) = if let Some(inner) = text.strip_prefix('#') {
+        (1, inner)
+    } else if let Some(inner) = text.strip_prefix("rb[").and_then(|t| t.strip_suffix(']')) {
+        (2, inner)
+    } else if let Some(inner) = text.strip_prefix('[').and_then(|t| t.strip_suffix(']')) {
+        (0, inner)
+    } else {
+        return Err(Error::InvalidOperand {
+            operand: text.to_owned(),
+            line,
+            column: token.column,
+        });
+    };
+    let inner = inner.trim();
+    let value = resolve(inner, labels, line, token.column + column(text, inner) - 1)?;
+    Ok((mode, value))
+}
+
+/// Assembles Intcode assembly into a program
+///
+/// Each line holds optional `label:` definitions followed by either an instruction or a `.data`
+/// directive, and `;` starts a comment. Operands are written `#imm`, `[pos]` or `rb[rel]`, where
+/// values are either integers or labels with an optional `+`/`-` offset.
+pub fn assemble(source: &str) -> Result<Vec<i64>, Error> {
+    let mut labels = HashMap::new();
+    let mut lines = Vec::new();
+
+    let mut address = 0;
+    for (i, line) in source.lines().enumerate() {
+        let number = i + 1;
+        let (defined, line) = parse_line(line, number)?;
+        for label in defined {
+            if labels.insert(label.text, address).is_some() {
+                return Err(Error::DuplicateLabel {
+                    label: label.text.to_owned(),
+                    line: number,
+                    column: label.column,
+                });
+            }
+        }
+        if let Some(line) = line {
+            address += match &line.statement {
+                Statement::Instruction { operands, .. } => operands.len() + 1,
+                Statement::Data { values } => values.len(),
+            };
+            lines.push(line);
+        }
+    }
+
+    let mut code = Vec::with_capacity(address);
+    for Line { number, statement } in lines {
+        match statement {
+            Statement::Instruction { mnemonic, operands } => {
+                let (opcode, count) = match opcode(mnemonic.text) {
+                    Some(o) => o,
+                    None => {
+                        return Err(Error::UnknownMnemonic {
+                            mnemonic: mnemonic.text.to_owned(),
+                            line: number,
+                            column: mnemonic.column,
+                        })
+                    }
+                };
+                if operands.len() != count {
+                    return Err(Error::OperandCount {
+                        mnemonic: mnemonic.text.to_owned(),
+                        expected: count,
+                        found: operands.len(),
+                        line: number,
+                        column: mnemonic.column,
+                    });
+                }
+
+                let mut modes = 0;
+                let mut values = Vec::with_capacity(count);
+                for (n, token) in operands.iter().enumerate() {
+                    let (mode, value) = operand(token, &labels, number)?;
+                    let writes = match opcode {
+                        1 | 2 | 7 | 8 => n == 2,
+                        3 => true,
+                        _ => false,
+                    };
+                    if writes && mode == 1 {
+                        return Err(Error::InvalidOperand {
+                            operand: token.text.to_owned(),
+                            line: number,
+                            column: token.column,
+                        });
+                    }
+                    modes += mode * 10i64.pow(n as u32 + 2);
+                    values.push(value);
+                }
+                code.push(opcode + modes);
+                code.extend(values);
+            }
+            Statement::Data { values } => {
+                for token in values {
+                    code.push(resolve(token.text, &labels, number, token.column)?);
+                }
+            }
+        }
+    }
+
+    Ok(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{assembler::assemble, error::Error, interpreter::eval};
+
+    #[test]
+    fn modes() {
+        let source = "add [4], #-3, rb[2]\nhlt";
+        assert_eq!(vec![21001, 4, -3, 2, 99], assemble(source).unwrap());
+    }
+
+    #[test]
+    fn labels_and_data() {
+        let source = "
+            ; counts down from 3
+            loop: out [counter]
+                  add [counter], #-1, [counter]
+                  jnz [counter], #loop
+                  hlt
+            counter: .data 3
+        ";
+        let code = assemble(source).unwrap();
+        assert_eq!(vec![3, 2, 1], eval(code, vec![]).unwrap().output);
+    }
+
+    #[test]
+    fn label_offset() {
+        let source = "out [end-1]\nend: .data 5, 99";
+        assert_eq!(vec![4, 1, 5, 99], assemble(source).unwrap());
+    }
+
+    #[test]
+    fn unknown_mnemonic() {
+        let expected = Error::UnknownMnemonic {
+            mnemonic: "jmp".to_owned(),
+            line: 2,
+            column: 3,
+        };
+        assert_eq!(expected, assemble("hlt\n  jmp #0").unwrap_err());
+    }
+
+    #[test]
+    fn undefined_label() {
+        let expected = Error::UndefinedLabel {
+            label: "nowhere".to_owned(),
+            line: 1,
+            column: 10,
+        };
+        assert_eq!(expected, assemble("jnz #1, #nowhere").unwrap_err());
+    }
+
+    #[test]
+    fn immediate_destination() {
+        let expected = Error::InvalidOperand {
+            operand: "#3".to_owned(),
+            line: 1,
+            column: 4,
+        };
+        assert_eq!(expected, assemble("in #3").unwrap_err());
+    }
+}
@@ -0,0 +1,154 @@
+use crate::{
+    error::Error,
+    interpreter::{Machine, Status},
+    parser,
+    program::Program,
+};
+
+/// Most phase settings `best` tries every permutation of
+pub const MAX_PHASES: usize = 10;
+
+/// Parses phase settings, either comma-separated or as an inclusive range like `5-9`
+pub fn parse_phases(input: &str) -> Result<Vec<i64>, Error> {
+    let invalid = || Error::InvalidPhases(input.trim().to_owned());
+    let bounds: Vec<&str> = input.trim().splitn(2, '-').collect();
+    let phases: Vec<i64> = if bounds.len() == 2 && !bounds[0].is_empty() {
+        let bound = |b: &str| b.parse::<i64>().map_err(|_| invalid());
+        let (start, end) = (bound(bounds[0])?, bound(bounds[1])?);
+        // Ranges are only meant for a handful of amplifiers
+        match end.checked_sub(start) {
+            Some(n) if n < MAX_PHASES as i64 => (),
+            _ => return Err(invalid()),
+        }
+        (start..=end).collect()
+    } else {
+        parser::parse(input).map_err(|_| invalid())?
+    };
+    if phases.is_empty() {
+        return Err(invalid());
+    }
+    Ok(phases)
+}
+
+/// Runs one copy of the program per phase setting in series, starting with a signal of 0
+///
+/// In feedback mode, the output of the last amplifier is fed back into the first one until the
+/// amplifiers halt.
+pub fn signal(program: &Program, phases: &[i64], feedback: bool) -> Result<i64, Error> {
+    let mut amplifiers: Vec<Machine> = phases
+        .iter()
+        .map(|phase| {
+            let mut machine = program.machine();
+            machine.push_input(*phase);
+            machine
+        })
+        .collect();
+
+    let mut signal = 0;
+    let mut first = true;
+    loop {
+        for (n, amplifier) in amplifiers.iter_mut().enumerate() {
+            amplifier.push_input(signal);
+            match amplifier.run_until()? {
+                Status::Output(o) => signal = o,
+                Status::Halted if feedback && n == 0 && !first => return Ok(signal),
+                _ => return Err(Error::MissingOutput { machine: n }),
+            }
+        }
+        if !feedback {
+            return Ok(signal);
+        }
+        first = false;
+    }
+}
+
+fn permutations(items: &[i64]) -> Vec<Vec<i64>> {
+    if items.len() <= 1 {
+        return vec![items.to_vec()];
+    }
+    let mut result = Vec::new();
+    for (i, item) in items.iter().enumerate() {
+        let mut rest = items.to_vec();
+        rest.remove(i);
+        for mut permutation in permutations(&rest) {
+            permutation.insert(0, *item);
+            result.push(permutation);
+        }
+    }
+    result
+}
+
+/// Tries every permutation of the phase settings, returning the best signal and its phases
+pub fn best(program: &Program, phases: &[i64], feedback: bool) -> Result<(i64, Vec<i64>), Error> {
+    if phases.len() > MAX_PHASES {
+        return Err(Error::TooManyPhases {
+            phases: phases.len(),
+            max: MAX_PHASES,
+        });
+    }
+    let mut best: Option<(i64, Vec<i64>)> = None;
+    for permutation in permutations(phases) {
+        let signal = signal(program, &permutation, feedback)?;
+        match &best {
+            Some((s, _)) if *s >= signal => (),
+            _ => best = Some((signal, permutation)),
+        }
+    }
+    Ok(best.unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        chain::{best, parse_phases, signal, MAX_PHASES},
+        error::Error,
+        program::Program,
+    };
+
+    fn series() -> Program {
+        Program::new(vec![
+            3, 15, 3, 16, 1002, 16, 10, 16, 1, 16, 15, 15, 4, 15, 99, 0, 0,
+        ])
+    }
+
+    fn feedback() -> Program {
+        Program::new(vec![
+            3, 26, 1001, 26, -4, 26, 3, 27, 1002, 27, 2, 27, 1, 27, 26, 27, 4, 27, 1001, 28, -1,
+            28, 1005, 28, 6, 99, 0, 0, 5,
+        ])
+    }
+
+    #[test]
+    fn phases() {
+        assert_eq!(vec![5, 6, 7, 8, 9], parse_phases("5-9").unwrap());
+        assert_eq!(vec![4, 3, 2, 1, 0], parse_phases("4,3,2,1,0").unwrap());
+        for invalid in ["5-x", "9-5", "0-1000000000", "4,x", ""] {
+            let expected = Error::InvalidPhases(invalid.to_owned());
+            assert_eq!(Err(expected), parse_phases(invalid));
+        }
+    }
+
+    #[test]
+    fn series_signal() {
+        assert_eq!(43210, signal(&series(), &[4, 3, 2, 1, 0], false).unwrap());
+    }
+
+    #[test]
+    fn feedback_signal() {
+        let result = signal(&feedback(), &[9, 8, 7, 6, 5], true);
+        assert_eq!(139629729, result.unwrap());
+    }
+
+    #[test]
+    fn best_permutation() {
+        let expected = (43210, vec![4, 3, 2, 1, 0]);
+        assert_eq!(expected, best(&series(), &[0, 1, 2, 3, 4], false).unwrap());
+
+        let phases: Vec<i64> = (0..=MAX_PHASES as i64).collect();
+        let expected = Error::TooManyPhases {
+            phases: MAX_PHASES + 1,
+            max: MAX_PHASES,
+        };
+        assert_eq!(Err(expected), best(&series(), &phases, false));
+    }
+}
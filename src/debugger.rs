@@ -0,0 +1,380 @@
+use crate::{
+    disasm,
+    error::Error,
+    interpreter::{Machine, Status},
+    io::IntcodeOutput,
+};
+use std::{
+    collections::BTreeSet,
+    io::{self, BufRead, Write},
+};
+
+static HELP: &str = "\
+step [N]          execute N instructions (s)
+continue          run until a breakpoint, a watchpoint, an input or a halt (c)
+break ADDR        set a breakpoint before the instruction at ADDR (b)
+delete ADDR       remove the breakpoint at ADDR
+watch ADDR        stop whenever the memory cell at ADDR is written to (w)
+unwatch ADDR      remove the watchpoint on ADDR
+input VALUE       queue an input value (i)
+regs              show the instruction pointer, relative base and queued inputs (r)
+mem ADDR [LEN]    dump LEN memory cells starting at ADDR (m)
+disasm [ADDR] [N] disassemble N instructions starting at ADDR or the current one (d)
+help              show this message (h)
+quit              exit the debugger (q)";
+
+/// A debugger command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Step(usize),
+    Continue,
+    Break(usize),
+    Delete(usize),
+    Watch(usize),
+    Unwatch(usize),
+    Input(i64),
+    Registers,
+    Memory(usize, usize),
+    Disassemble(Option<usize>, usize),
+    Help,
+    Quit,
+}
+
+impl Command {
+    /// Parses a command line, returning `None` if it isn't a valid command
+    pub fn parse(line: &str) -> Option<Self> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (command, arguments) = words.split_first()?;
+        let number = |n: usize| arguments[n].parse().ok();
+
+        let command = match (*command, arguments.len()) {
+            ("s" | "step", 0) => Command::Step(1),
+            ("s" | "step", 1) => Command::Step(number(0)?),
+            ("c" | "continue", 0) => Command::Continue,
+            ("b" | "break", 1) => Command::Break(number(0)?),
+            ("delete", 1) => Command::Delete(number(0)?),
+            ("w" | "watch", 1) => Command::Watch(number(0)?),
+            ("unwatch", 1) => Command::Unwatch(number(0)?),
+            ("i" | "input", 1) => Command::Input(arguments[0].parse().ok()?),
+            ("r" | "regs", 0) => Command::Registers,
+            ("m" | "mem", 1) => Command::Memory(number(0)?, 8),
+            ("m" | "mem", 2) => Command::Memory(number(0)?, number(1)?),
+            ("d" | "disasm", 0) => Command::Disassemble(None, 1),
+            ("d" | "disasm", 1) => Command::Disassemble(Some(number(0)?), 1),
+            ("d" | "disasm", 2) => Command::Disassemble(Some(number(0)?), number(1)?),
+            ("h" | "help", 0) => Command::Help,
+            ("q" | "quit", 0) => Command::Quit,
+            _ => return None,
+        };
+        Some(command)
+    }
+}
+
+/// Why a debugged machine stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    /// The next instruction is on a breakpoint
+    Breakpoint(usize),
+    /// A watched memory cell was written to
+    Watchpoint {
+        address: usize,
+        old: i64,
+        new: i64,
+    },
+    /// The machine is blocked on an input instruction with no queued input
+    NeedsInput,
+    Halted,
+}
+
+/// A machine along with breakpoints and watchpoints
+pub struct Debugger {
+    machine: Machine,
+    breakpoints: BTreeSet<usize>,
+    watchpoints: BTreeSet<usize>,
+}
+
+impl Debugger {
+    pub fn new(machine: Machine) -> Self {
+        Self {
+            machine,
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeSet::new(),
+        }
+    }
+
+    pub fn machine(&self) -> &Machine {
+        &self.machine
+    }
+
+    pub fn machine_mut(&mut self) -> &mut Machine {
+        &mut self.machine
+    }
+
+    /// Sets a breakpoint, returning whether it wasn't already set
+    pub fn add_breakpoint(&mut self, address: usize) -> bool {
+        self.breakpoints.insert(address)
+    }
+
+    pub fn remove_breakpoint(&mut self, address: usize) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    /// Sets a watchpoint, returning whether it wasn't already set
+    pub fn add_watchpoint(&mut self, address: usize) -> bool {
+        self.watchpoints.insert(address)
+    }
+
+    pub fn remove_watchpoint(&mut self, address: usize) -> bool {
+        self.watchpoints.remove(&address)
+    }
+
+    /// Executes a single instruction, ignoring breakpoints
+    pub fn step<O: IntcodeOutput + ?Sized>(
+        &mut self,
+        output: &mut O,
+    ) -> Result<Option<Stop>, Error> {
+        let memory = self.machine.memory();
+        let watched: Vec<(usize, i64)> = self
+            .watchpoints
+            .iter()
+            .map(|a| (*a, memory.read(*a)))
+            .collect();
+
+        match self.machine.step()? {
+            Status::Running => (),
+            Status::Output(o) => output.write(o),
+            Status::NeedsInput => return Ok(Some(Stop::NeedsInput)),
+            Status::Halted => return Ok(Some(Stop::Halted)),
+        }
+
+        let written = match self.machine.last_write() {
+            Some(w) => w,
+            None => return Ok(None),
+        };
+        Ok(watched
+            .into_iter()
+            .find(|(address, _)| *address == written)
+            .map(|(address, old)| Stop::Watchpoint {
+                address,
+                old,
+                new: self.machine.memory().read(address),
+            }))
+    }
+
+    /// Executes instructions until the machine stops
+    ///
+    /// The current instruction is always executed, so resuming from a breakpoint moves past it.
+    pub fn resume<O: IntcodeOutput + ?Sized>(&mut self, output: &mut O) -> Result<Stop, Error> {
+        if let Some(stop) = self.step(output)? {
+            return Ok(stop);
+        }
+        loop {
+            let ip = self.machine.ip();
+            if self.breakpoints.contains(&ip) {
+                return Ok(Stop::Breakpoint(ip));
+            }
+            if let Some(stop) = self.step(output)? {
+                return Ok(stop);
+            }
+        }
+    }
+
+    /// Disassembles `count` instructions starting at `address`
+    pub fn disassemble(&self, address: usize, count: usize) -> String {
+        let mut result = String::new();
+        let mut address = address;
+        for _ in 0..count {
+            let (text, next) = disasm::instruction(self.machine.memory(), address);
+            let marker = if address == self.machine.ip() {
+                '>'
+            } else {
+                ' '
+            };
+            result.push_str(&format!("{}{:>5}: {}\n", marker, address, text));
+            address = next;
+        }
+        result
+    }
+
+    /// Dumps `length` memory cells starting at `address`, eight per line
+    pub fn dump(&self, address: usize, length: usize) -> String {
+        let memory = self.machine.memory();
+        let mut result = String::new();
+        let end = address.saturating_add(length);
+        for line in (address..end).step_by(8) {
+            let values: Vec<String> = (line..line.saturating_add(8).min(end))
+                .map(|a| memory.read(a).to_string())
+                .collect();
+            result.push_str(&format!("{:>6}: {}\n", line, values.join(" ")));
+        }
+        result
+    }
+
+    pub fn registers(&self) -> String {
+        format!(
+            "ip: {}  rb: {}  input: {:?}\n",
+            self.machine.ip(),
+            self.machine.relative_base(),
+            self.machine.pending_input()
+        )
+    }
+
+    /// Executes a command, returning the text to show, or `None` to quit
+    pub fn execute(&mut self, command: Command) -> Option<String> {
+        let mut output = Vec::new();
+        let result = match command {
+            Command::Step(n) => (0..n)
+                .find_map(|_| self.step(&mut output).transpose())
+                .transpose(),
+            Command::Continue => self.resume(&mut output).map(Some),
+            Command::Break(a) => {
+                self.add_breakpoint(a);
+                return Some(format!("Breakpoint set at {}\n", a));
+            }
+            Command::Delete(a) => {
+                self.remove_breakpoint(a);
+                return Some(String::new());
+            }
+            Command::Watch(a) => {
+                self.add_watchpoint(a);
+                return Some(format!("Watching {}\n", a));
+            }
+            Command::Unwatch(a) => {
+                self.remove_watchpoint(a);
+                return Some(String::new());
+            }
+            Command::Input(v) => {
+                self.machine.push_input(v);
+                return Some(String::new());
+            }
+            Command::Registers => return Some(self.registers()),
+            Command::Memory(a, l) => return Some(self.dump(a, l)),
+            Command::Disassemble(a, n) => {
+                return Some(self.disassemble(a.unwrap_or_else(|| self.machine.ip()), n))
+            }
+            Command::Help => return Some(format!("{}\n", HELP)),
+            Command::Quit => return None,
+        };
+
+        let mut text: String = output.iter().map(|o| format!("Output: {}\n", o)).collect();
+        match result {
+            Ok(Some(Stop::Breakpoint(a))) => text.push_str(&format!("Breakpoint at {}\n", a)),
+            Ok(Some(Stop::Watchpoint { address, old, new })) => {
+                text.push_str(&format!("Watchpoint on {}: {} -> {}\n", address, old, new))
+            }
+            Ok(Some(Stop::NeedsInput)) => text.push_str("Waiting for input\n"),
+            Ok(Some(Stop::Halted)) => text.push_str("Halted\n"),
+            Ok(None) => (),
+            Err(e) => text.push_str(&format!("{}\n", e)),
+        }
+        text.push_str(&self.disassemble(self.machine.ip(), 1));
+        Some(text)
+    }
+
+    /// Reads commands from stdin until `quit` or the end of input
+    pub fn run(&mut self) {
+        let stdin = io::stdin();
+        let mut stdin = stdin.lock();
+        let mut line = String::new();
+
+        print!("{}", self.disassemble(self.machine.ip(), 1));
+        loop {
+            print!("(ic) ");
+            io::stdout().flush().expect("Can't flush stdout");
+            line.clear();
+            if stdin.read_line(&mut line).expect("Can't read from stdin") == 0 {
+                return;
+            }
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            match Command::parse(&line) {
+                Some(command) => match self.execute(command) {
+                    Some(text) => print!("{}", text),
+                    None => return,
+                },
+                None => println!("Invalid command, type \"help\" for a list of commands"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        debugger::{Command, Debugger, Stop},
+        interpreter::Machine,
+    };
+
+    // 0: in [12]; 2: out [12]; 4: add [12], #-1, [12]; 8: jnz [12], #2; 11: hlt
+    fn countdown() -> Debugger {
+        Debugger::new(Machine::new(vec![
+            3, 12, 4, 12, 1001, 12, -1, 12, 1005, 12, 2, 99, 0,
+        ]))
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(Some(Command::Step(1)), Command::parse("s"));
+        assert_eq!(Some(Command::Step(5)), Command::parse("step 5"));
+        assert_eq!(Some(Command::Break(8)), Command::parse("b 8"));
+        assert_eq!(Some(Command::Memory(4, 8)), Command::parse("mem 4"));
+        assert_eq!(
+            Some(Command::Disassemble(None, 1)),
+            Command::parse("disasm")
+        );
+        assert_eq!(Some(Command::Input(-1)), Command::parse("input -1"));
+        assert_eq!(None, Command::parse("break"));
+        assert_eq!(None, Command::parse("step x"));
+        assert_eq!(None, Command::parse("jump 3"));
+    }
+
+    #[test]
+    fn breakpoints() {
+        let mut debugger = countdown();
+        let mut output = Vec::new();
+        assert_eq!(Stop::NeedsInput, debugger.resume(&mut output).unwrap());
+
+        debugger.machine_mut().push_input(2);
+        debugger.add_breakpoint(8);
+        assert_eq!(Stop::Breakpoint(8), debugger.resume(&mut output).unwrap());
+        assert_eq!(Stop::Breakpoint(8), debugger.resume(&mut output).unwrap());
+        assert_eq!(Stop::Halted, debugger.resume(&mut output).unwrap());
+        assert_eq!(vec![2, 1], output);
+    }
+
+    #[test]
+    fn watchpoints() {
+        let mut debugger = countdown();
+        debugger.machine_mut().push_input(2);
+        debugger.add_watchpoint(12);
+
+        let expected = Stop::Watchpoint {
+            address: 12,
+            old: 0,
+            new: 2,
+        };
+        assert_eq!(expected, debugger.resume(&mut Vec::new()).unwrap());
+        assert_eq!(2, debugger.machine().ip());
+    }
+
+    #[test]
+    fn execute() {
+        let mut debugger = countdown();
+        debugger.execute(Command::Input(1));
+        assert_eq!(
+            ">    2: out [12]\n",
+            debugger.execute(Command::Step(1)).unwrap()
+        );
+        assert_eq!(
+            "Output: 1\nHalted\n>   11: hlt\n",
+            debugger.execute(Command::Continue).unwrap()
+        );
+        assert_eq!(
+            "    12: 0 0\n",
+            debugger.execute(Command::Memory(12, 2)).unwrap()
+        );
+        assert_eq!(None, debugger.execute(Command::Quit));
+    }
+}
@@ -0,0 +1,104 @@
+use crate::{
+    interpreter::{Instruction, Parameter},
+    runtime::Memory,
+};
+use std::fmt::{self, Display, Formatter};
+
+impl Display for Parameter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Parameter::Position(p) => write!(f, "[{}]", p),
+            Parameter::Immediate(v) => write!(f, "#{}", v),
+            Parameter::Relative(o) => write!(f, "rb[{}]", o),
+        }
+    }
+}
+
+/// Opcodes along with their mnemonic and number of parameters, shared with the assembler
+pub(crate) const OPCODES: [(i64, &str, usize); 10] = [
+    (1, "add", 3),
+    (2, "mul", 3),
+    (3, "in", 1),
+    (4, "out", 1),
+    (5, "jnz", 2),
+    (6, "jz", 2),
+    (7, "lt", 3),
+    (8, "eq", 3),
+    (9, "arb", 1),
+    (99, "hlt", 0),
+];
+
+impl Display for Instruction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let (opcode, parameters) = match self {
+            Instruction::Add { n1, n2, to } => (1, vec![n1, n2, to]),
+            Instruction::Multiply { n1, n2, to } => (2, vec![n1, n2, to]),
+            Instruction::Input { to } => (3, vec![to]),
+            Instruction::Output { from } => (4, vec![from]),
+            Instruction::JumpIfTrue { test, goto } => (5, vec![test, goto]),
+            Instruction::JumpIfFalse { test, goto } => (6, vec![test, goto]),
+            Instruction::LessThan { n1, n2, to } => (7, vec![n1, n2, to]),
+            Instruction::Equals { n1, n2, to } => (8, vec![n1, n2, to]),
+            Instruction::AdjustRelativeBase { by } => (9, vec![by]),
+            Instruction::Halt => (99, vec![]),
+            Instruction::End => return write!(f, ".end"),
+        };
+        write!(f, "{}", mnemonic(opcode).unwrap())?;
+        for (i, parameter) in parameters.iter().enumerate() {
+            write!(f, "{}{}", if i == 0 { " " } else { ", " }, parameter)?;
+        }
+        Ok(())
+    }
+}
+
+/// Mnemonic of an opcode without parameter modes
+pub fn mnemonic(opcode: i64) -> Option<&'static str> {
+    OPCODES
+        .iter()
+        .find(|(o, _, _)| *o == opcode)
+        .map(|(_, mnemonic, _)| *mnemonic)
+}
+
+/// Disassembles the instruction at `address`, returning its text and the address of the next one
+///
+/// Words that can't be decoded are rendered as `.data` directives.
+pub fn instruction(code: &Memory, address: usize) -> (String, usize) {
+    let mut next = address;
+    match Instruction::from_code(code, &mut next) {
+        Ok(instruction) => (instruction.to_string(), next),
+        Err(_) => (format!(".data {}", code.read(address)), address + 1),
+    }
+}
+
+/// Disassembles a whole program, one instruction per line prefixed with its address
+pub fn disassemble(code: &[i64]) -> String {
+    let memory = Memory::from(code.to_vec());
+    let mut result = String::new();
+    let mut address = 0;
+    while address < code.len() {
+        let (text, next) = instruction(&memory, address);
+        result.push_str(&format!("{:>5}: {}\n", address, text));
+        address = next;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::disasm::disassemble;
+
+    #[test]
+    fn modes() {
+        let code = vec![1001, 4, -3, 4, 109, 2, 21108, 1, 2, 3, 99];
+        let expected =
+            "    0: add [4], #-3, [4]\n    4: arb #2\n    6: eq #1, #2, rb[3]\n   10: hlt\n";
+        assert_eq!(expected, disassemble(&code));
+    }
+
+    #[test]
+    fn data_fallback() {
+        let code = vec![104, 7, 42, 3];
+        let expected = "    0: out #7\n    2: .data 42\n    3: .data 3\n";
+        assert_eq!(expected, disassemble(&code));
+    }
+}
@@ -3,76 +3,155 @@ use std::{
     fmt::{self, Display, Formatter},
 };
 
+use crate::runtime;
+
+/// Errors reported by the library, on top of the ones from the runtime embedded into transpiled
+/// programs
 #[derive(Debug)]
 #[cfg_attr(test, derive(PartialEq))]
 pub enum Error {
-    InvalidInput {
-        token: String,
-        position: usize,
+    Runtime(runtime::Error),
+    MissingOutput {
+        machine: usize,
+    },
+    UnknownMnemonic {
+        mnemonic: String,
+        line: usize,
+        column: usize,
+    },
+    OperandCount {
+        mnemonic: String,
+        expected: usize,
+        found: usize,
+        line: usize,
+        column: usize,
+    },
+    InvalidOperand {
+        operand: String,
+        line: usize,
+        column: usize,
+    },
+    UndefinedLabel {
+        label: String,
+        line: usize,
+        column: usize,
+    },
+    DuplicateLabel {
+        label: String,
+        line: usize,
+        column: usize,
+    },
+    InvalidSnapshot {
+        line: usize,
     },
-    InvalidOpcode {
-        opcode: i64,
-        position: usize,
+    UnsupportedSnapshotVersion {
+        version: String,
     },
-    MissingParameter {
-        parameter: u8,
-        opcode: i64,
-        position: usize,
+    InvalidPhases(String),
+    TooManyPhases {
+        phases: usize,
+        max: usize,
     },
-    NegativePositionalParameter {
-        value: i64,
-        parameter: u8,
-        opcode: i64,
-        position: usize,
+    TooManyItems {
+        items: usize,
+        max: usize,
     },
-    InvalidParameterMode {
-        mode: i64,
-        parameter: u8,
-        opcode: i64,
-        position: usize,
+    GridTooLarge {
+        width: u64,
+        height: u64,
+        max: u64,
     },
 }
 
 impl Display for Error {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
-            Error::InvalidInput { token, position } => {
-                write!(f, "Invalid token \"{}\" at position {}", token, position)
+            Error::Runtime(e) => e.fmt(f),
+            Error::MissingOutput { machine } => {
+                write!(f, "Machine {} stopped without producing an output", machine)
             }
-            Error::InvalidOpcode { opcode, position } => {
-                write!(f, "Invalid opcode \"{}\" at position {}", opcode, position)
-            }
-            Error::MissingParameter {
-                parameter,
-                opcode,
-                position,
+            Error::UnknownMnemonic {
+                mnemonic,
+                line,
+                column,
+            } => write!(
+                f,
+                "Unknown mnemonic \"{}\" at line {}, column {}",
+                mnemonic, line, column
+            ),
+            Error::OperandCount {
+                mnemonic,
+                expected,
+                found,
+                line,
+                column,
+            } => write!(
+                f,
+                "Expected {} operands for \"{}\" but found {} at line {}, column {}",
+                expected, mnemonic, found, line, column
+            ),
+            Error::InvalidOperand {
+                operand,
+                line,
+                column,
             } => write!(
                 f,
-                "Missing parameter {} for opcode \"{}\" at position {}",
-                parameter, opcode, position
+                "Invalid operand \"{}\" at line {}, column {}",
+                operand, line, column
             ),
-            Error::NegativePositionalParameter {
-                value,
-                parameter,
-                opcode,
-                position,
+            Error::UndefinedLabel {
+                label,
+                line,
+                column,
             } => write!(
                 f,
-                "Negative value {} for positional parameter {} for opcode \"{}\" at position {}",
-                value, parameter, opcode, position
+                "Undefined label \"{}\" at line {}, column {}",
+                label, line, column
             ),
-            Error::InvalidParameterMode {
-                mode,
-                parameter,
-                opcode,
-                position,
+            Error::DuplicateLabel {
+                label,
+                line,
+                column,
             } => write!(
                 f,
-                "Invalid parameter mode \"{}\" for parameter {} of opcode \"{}\" at position {}",
-                mode, parameter, opcode, position
+                "Duplicate label \"{}\" at line {}, column {}",
+                label, line, column
+            ),
+            Error::InvalidSnapshot { line } => write!(f, "Invalid snapshot at line {}", line),
+            Error::UnsupportedSnapshotVersion { version } => {
+                write!(f, "Unsupported snapshot version \"{}\"", version)
+            }
+            Error::InvalidPhases(phases) => write!(f, "Invalid phase settings \"{}\"", phases),
+            Error::TooManyPhases { phases, max } => write!(
+                f,
+                "Too many phase settings to try every permutation: {} above {}",
+                phases, max
+            ),
+            Error::TooManyItems { items, max } => {
+                write!(f, "Too many items to brute-force: {} above {}", items, max)
+            }
+            Error::GridTooLarge { width, height, max } => write!(
+                f,
+                "Grid of {} by {} is too large to draw, the limit is {} cells",
+                width, height, max
             ),
         }
     }
 }
 
-impl error::Error for Error {}
+impl From<runtime::Error> for Error {
+    fn from(e: runtime::Error) -> Self {
+        Error::Runtime(e)
+    }
+}
+
+impl error::Error for runtime::Error {}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Runtime(e) => Some(e),
+            _ => None,
+        }
+    }
+}
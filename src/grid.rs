@@ -0,0 +1,404 @@
+use crate::{
+    error::Error,
+    interpreter::{Machine, Status},
+};
+use std::{collections::HashMap, str::FromStr};
+
+/// How the outputs of a program are turned into a grid
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// `x, y, tile` triples, as drawn by the day 13 arcade cabinet
+    Triples,
+    /// Frames of ASCII text, as drawn by the day 17 camera
+    Ascii,
+    /// Panels painted by the day 11 robot
+    Robot,
+}
+
+impl FromStr for Mode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "triples" => Ok(Mode::Triples),
+            "ascii" => Ok(Mode::Ascii),
+            "robot" => Ok(Mode::Robot),
+            _ => Err(format!("Invalid render mode \"{}\"", s)),
+        }
+    }
+}
+
+/// Tiles drawn by a program, indexed by `(x, y)` with `y` growing downwards
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Grid {
+    tiles: HashMap<(i64, i64), i64>,
+}
+
+/// Colors of the tiles of day 11 and 13 style grids, indexed by tile id
+const PALETTE: [[u8; 3]; 5] = [
+    [0, 0, 0],
+    [255, 255, 255],
+    [200, 80, 40],
+    [80, 160, 255],
+    [255, 220, 0],
+];
+
+/// Characters of the tiles of day 11 and 13 style grids, indexed by tile id
+const CHARACTERS: [char; 5] = [' ', '#', '%', '=', 'o'];
+
+/// Most tiles a grid is drawn with, and most pixels of its images
+pub const MAX_AREA: u64 = 1 << 24;
+
+/// Fails unless an area fits within `MAX_AREA`
+fn check_area(width: u64, height: u64) -> Result<(), Error> {
+    if width.saturating_mul(height) > MAX_AREA {
+        return Err(Error::GridTooLarge {
+            width,
+            height,
+            max: MAX_AREA,
+        });
+    }
+    Ok(())
+}
+
+impl Grid {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builds a grid from `x, y, tile` output triples
+    ///
+    /// Triples drawn at `(-1, 0)` are day 13 scores rather than tiles, and the last one is
+    /// returned along with the grid.
+    pub fn from_triples(output: &[i64]) -> (Self, Option<i64>) {
+        let mut grid = Self::new();
+        let mut score = None;
+        for triple in output.chunks_exact(3) {
+            match (triple[0], triple[1]) {
+                (-1, 0) => score = Some(triple[2]),
+                (x, y) => grid.set(x, y, triple[2]),
+            }
+        }
+        (grid, score)
+    }
+
+    /// Builds a grid from the last frame of ASCII output, frames being separated by blank lines
+    ///
+    /// Tiles hold the character codes, and values outside of the ASCII range are ignored.
+    pub fn from_ascii(output: &[i64]) -> Self {
+        let text: String = output
+            .iter()
+            .filter(|v| (0..128).contains(*v))
+            .map(|v| *v as u8 as char)
+            .collect();
+        let frame = text
+            .split("\n\n")
+            .filter(|f| !f.trim().is_empty())
+            .last()
+            .unwrap_or("");
+
+        let mut grid = Self::new();
+        for (y, line) in frame.lines().enumerate() {
+            for (x, c) in line.chars().enumerate() {
+                grid.set(x as i64, y as i64, c as i64);
+            }
+        }
+        grid
+    }
+
+    pub fn get(&self, x: i64, y: i64) -> Option<i64> {
+        self.tiles.get(&(x, y)).copied()
+    }
+
+    pub fn set(&mut self, x: i64, y: i64, tile: i64) {
+        self.tiles.insert((x, y), tile);
+    }
+
+    /// Number of tiles that were drawn at least once
+    pub fn len(&self) -> usize {
+        self.tiles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tiles.is_empty()
+    }
+
+    /// Smallest and largest coordinates of drawn tiles
+    pub fn bounds(&self) -> Option<((i64, i64), (i64, i64))> {
+        let xs = self.tiles.keys().map(|(x, _)| *x);
+        let ys = self.tiles.keys().map(|(_, y)| *y);
+        Some((
+            (xs.clone().min()?, ys.clone().min()?),
+            (xs.max()?, ys.max()?),
+        ))
+    }
+
+    /// Rows of tiles covering the bounds of the grid, with missing tiles set to 0
+    fn rows(&self) -> Result<Vec<Vec<i64>>, Error> {
+        let ((x0, y0), (x1, y1)) = match self.bounds() {
+            Some(bounds) => bounds,
+            None => return Ok(Vec::new()),
+        };
+        check_area(
+            x1.abs_diff(x0).saturating_add(1),
+            y1.abs_diff(y0).saturating_add(1),
+        )?;
+        Ok((y0..=y1)
+            .map(|y| (x0..=x1).map(|x| self.get(x, y).unwrap_or(0)).collect())
+            .collect())
+    }
+
+    /// Renders the grid as text
+    ///
+    /// Tiles holding printable ASCII characters are drawn as themselves when `ascii` is set,
+    /// and tile ids are mapped to characters otherwise.
+    pub fn render(&self, ascii: bool) -> Result<String, Error> {
+        let mut result = String::new();
+        for row in self.rows()? {
+            for tile in row {
+                result.push(match tile {
+                    32..=126 if ascii => tile as u8 as char,
+                    _ if ascii => ' ',
+                    0..=4 => CHARACTERS[tile as usize],
+                    _ => '?',
+                });
+            }
+            result.push('\n');
+        }
+        Ok(result)
+    }
+
+    /// Pixels of the grid, scaled up by `scale`, as RGB rows
+    fn pixels(&self, ascii: bool, scale: usize) -> Result<(usize, usize, Vec<u8>), Error> {
+        let rows = self.rows()?;
+        let width = rows.first().map_or(0, |r| r.len()).saturating_mul(scale);
+        let height = rows.len().saturating_mul(scale);
+        check_area(width as u64, height as u64)?;
+        let mut pixels = Vec::with_capacity(width * height * 3);
+        for row in &rows {
+            let mut line = Vec::with_capacity(width * 3);
+            for tile in row {
+                let color = match *tile {
+                    t if ascii && (t == '.' as i64 || t == ' ' as i64) => PALETTE[0],
+                    t if ascii && t == '#' as i64 => PALETTE[1],
+                    _ if ascii => PALETTE[4],
+                    t @ 0..=4 => PALETTE[t as usize],
+                    _ => [255, 0, 255],
+                };
+                for _ in 0..scale {
+                    line.extend_from_slice(&color);
+                }
+            }
+            for _ in 0..scale {
+                pixels.extend_from_slice(&line);
+            }
+        }
+        Ok((width, height, pixels))
+    }
+
+    /// Encodes the grid as a binary PPM image
+    pub fn ppm(&self, ascii: bool, scale: usize) -> Result<Vec<u8>, Error> {
+        let (width, height, pixels) = self.pixels(ascii, scale)?;
+        let mut result = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+        result.extend(pixels);
+        Ok(result)
+    }
+
+    /// Encodes the grid as a PNG image, using uncompressed deflate blocks
+    pub fn png(&self, ascii: bool, scale: usize) -> Result<Vec<u8>, Error> {
+        let (width, height, pixels) = self.pixels(ascii, scale)?;
+        let mut raw = Vec::with_capacity((width * 3 + 1) * height);
+        for row in pixels.chunks(width.max(1) * 3).filter(|r| !r.is_empty()) {
+            raw.push(0);
+            raw.extend_from_slice(row);
+        }
+
+        let mut header = Vec::new();
+        header.extend_from_slice(&(width as u32).to_be_bytes());
+        header.extend_from_slice(&(height as u32).to_be_bytes());
+        header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+        let mut result = b"\x89PNG\r\n\x1a\n".to_vec();
+        png_chunk(&mut result, b"IHDR", &header);
+        png_chunk(&mut result, b"IDAT", &zlib_stored(&raw));
+        png_chunk(&mut result, b"IEND", &[]);
+        Ok(result)
+    }
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn png_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// Wraps data in a zlib stream made of uncompressed blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut result = vec![0x78, 0x01];
+    let blocks: Vec<&[u8]> = data.chunks(0xffff).collect();
+    if blocks.is_empty() {
+        result.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    for (i, block) in blocks.iter().enumerate() {
+        let last = (i + 1 == blocks.len()) as u8;
+        let length = block.len() as u16;
+        result.push(last);
+        result.extend_from_slice(&length.to_le_bytes());
+        result.extend_from_slice(&(!length).to_le_bytes());
+        result.extend_from_slice(block);
+    }
+
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    result.extend_from_slice(&((b << 16) | a).to_be_bytes());
+    result
+}
+
+/// The day 11 hull-painting robot
+///
+/// The robot starts at the origin facing up. Each time, it reads the color of the panel it is
+/// on, paints it, turns left (0) or right (1) and moves forward one panel.
+#[derive(Debug, Default)]
+pub struct Robot {
+    pub grid: Grid,
+    position: (i64, i64),
+    direction: (i64, i64),
+}
+
+impl Robot {
+    pub fn new() -> Self {
+        Self {
+            grid: Grid::new(),
+            position: (0, 0),
+            direction: (0, -1),
+        }
+    }
+
+    /// Color of the panel under the robot
+    pub fn color(&self) -> i64 {
+        self.grid.get(self.position.0, self.position.1).unwrap_or(0)
+    }
+
+    /// Paints the panel under the robot, then turns and moves
+    pub fn apply(&mut self, color: i64, turn: i64) {
+        self.grid.set(self.position.0, self.position.1, color);
+        let (dx, dy) = self.direction;
+        self.direction = if turn == 0 { (dy, -dx) } else { (-dy, dx) };
+        self.position = (
+            self.position.0 + self.direction.0,
+            self.position.1 + self.direction.1,
+        );
+    }
+
+    /// Runs a painting program until it halts, starting on a panel of the given color
+    pub fn paint(mut self, mut machine: Machine, start: i64) -> Result<Grid, Error> {
+        if start != 0 {
+            self.grid.set(0, 0, start);
+        }
+        let mut outputs = Vec::new();
+        loop {
+            match machine.run_until()? {
+                Status::NeedsInput => machine.push_input(self.color()),
+                Status::Output(o) => {
+                    outputs.push(o);
+                    if outputs.len() == 2 {
+                        self.apply(outputs[0], outputs[1]);
+                        outputs.clear();
+                    }
+                }
+                Status::Halted => return Ok(self.grid),
+                Status::Running => unreachable!(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        error::Error,
+        grid::{crc32, Grid, Robot, MAX_AREA},
+    };
+
+    #[test]
+    fn triples() {
+        let (grid, score) = Grid::from_triples(&[1, 2, 3, 6, 5, 4, -1, 0, 12]);
+        assert_eq!(Some(12), score);
+        assert_eq!(Some(3), grid.get(1, 2));
+        assert_eq!(Some(((1, 2), (6, 5))), grid.bounds());
+    }
+
+    #[test]
+    fn ascii_frames() {
+        let output: Vec<i64> = "..\n..\n\n#.\n.#\n".bytes().map(|b| b as i64).collect();
+        let grid = Grid::from_ascii(&output);
+        assert_eq!("#.\n.#\n", grid.render(true).unwrap());
+    }
+
+    #[test]
+    fn robot() {
+        let mut robot = Robot::new();
+        for (color, turn) in &[(1, 0), (0, 0), (1, 0), (1, 0), (0, 1), (1, 0), (1, 0)] {
+            robot.apply(*color, *turn);
+        }
+        assert_eq!(6, robot.grid.len());
+        assert_eq!("  #\n  #\n## \n", robot.grid.render(false).unwrap());
+    }
+
+    #[test]
+    fn images() {
+        let mut grid = Grid::new();
+        grid.set(0, 0, 1);
+        let ppm = grid.ppm(false, 2).unwrap();
+        assert_eq!(b"P6\n2 2\n255\n", &ppm[..11]);
+        assert_eq!(11 + 2 * 2 * 3, ppm.len());
+
+        let png = grid.png(false, 1).unwrap();
+        assert_eq!(b"\x89PNG\r\n\x1a\n", &png[..8]);
+        assert_eq!(0xcbf4_3926, crc32(b"123456789"));
+    }
+
+    #[test]
+    fn too_large() {
+        let (grid, _) = Grid::from_triples(&[0, 0, 1, i64::MAX, 1, 1]);
+        let expected = Error::GridTooLarge {
+            width: i64::MAX as u64 + 1,
+            height: 2,
+            max: MAX_AREA,
+        };
+        assert_eq!(Err(expected), grid.render(false));
+
+        let (grid, _) = Grid::from_triples(&[0, 0, 1, 0, i64::MIN, 1, 0, i64::MAX, 1]);
+        assert!(grid.render(false).is_err());
+
+        let (grid, _) = Grid::from_triples(&[0, 0, 1, 99, 99, 1]);
+        assert!(grid.render(false).is_ok());
+        let expected = Error::GridTooLarge {
+            width: 100 * 1000,
+            height: 100 * 1000,
+            max: MAX_AREA,
+        };
+        assert_eq!(Err(expected), grid.png(false, 1000));
+    }
+}
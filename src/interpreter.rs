@@ -1,430 +1,266 @@
-use crate::error::Error;
-use std::{
-    convert::TryInto,
-    io::{self, BufRead, Write},
+use crate::{
+    error::Error,
+    io::{AsciiInput, AsciiOutput, IntcodeInput, IntcodeOutput, Prompt},
+    runtime::Memory,
 };
-
-enum Parameter {
-    Position(usize),
-    Immediate(i64),
-}
-
-impl Parameter {
-    fn from_code(
-        code: &[i64],
-        i: &mut usize,
-        mode: i64,
-        n: u8,
-        opcode: i64,
-    ) -> Result<Self, Error> {
-        match code.get(*i) {
-            None => Err(Error::MissingParameter {
-                parameter: n,
-                opcode,
-                position: *i,
-            }),
-            Some(p) => {
-                *i += 1;
-                match mode {
-                    0 => Ok(Parameter::Position((*p).try_into().map_err(|_| {
-                        Error::NegativePositionalParameter {
-                            value: *p,
-                            parameter: n,
-                            opcode,
-                            position: *i,
-                        }
-                    })?)),
-                    1 => Ok(Parameter::Immediate(*p)),
-                    _ => Err(Error::InvalidParameterMode {
-                        mode,
-                        parameter: n,
-                        opcode,
-                        position: *i,
-                    }),
-                }
+use std::collections::VecDeque;
+
+pub(crate) use crate::runtime::{Instruction, Parameter};
+pub use crate::runtime::{Machine, Status};
+
+impl Machine {
+    /// Runs the machine until it halts or `input` runs out, routing inputs and outputs through
+    /// the given handlers
+    ///
+    /// Inputs already queued with `push_input` are consumed before `input` is read from.
+    pub fn run_with<I, O>(&mut self, input: &mut I, output: &mut O) -> Result<Status, Error>
+    where
+        I: IntcodeInput + ?Sized,
+        O: IntcodeOutput + ?Sized,
+    {
+        loop {
+            match self.run_until()? {
+                Status::NeedsInput => match input.read() {
+                    Some(i) => self.push_input(i),
+                    None => return Ok(Status::NeedsInput),
+                },
+                Status::Output(o) => output.write(o),
+                Status::Halted => return Ok(Status::Halted),
+                Status::Running => unreachable!(),
             }
         }
     }
 
-    fn positional_from_code(
-        code: &[i64],
-        i: &mut usize,
-        mode: i64,
-        n: u8,
-        opcode: i64,
-    ) -> Result<Self, Error> {
-        let p = Self::from_code(code, i, mode, n, opcode)?;
-        match p {
-            Parameter::Position(_) => Ok(p),
-            Parameter::Immediate(_) => Err(Error::InvalidParameterMode {
-                mode,
-                parameter: n,
-                opcode,
-                position: *i,
-            }),
-        }
+    /// Runs the machine until it halts, prompting for inputs on stdin and printing outputs to stdout
+    pub fn run(&mut self) -> Result<(), Error> {
+        self.run_with(&mut Prompt, &mut std::io::stdout())?;
+        Ok(())
     }
 
-    fn value(&self, code: &[i64]) -> i64 {
-        match self {
-            Parameter::Position(p) => code[*p],
-            Parameter::Immediate(v) => *v,
-        }
+    /// Runs the machine until it halts, feeding it lines of text from stdin and printing its
+    /// outputs as characters
+    pub fn run_ascii(&mut self) -> Result<(), Error> {
+        let stdin = std::io::stdin();
+        self.run_with(
+            &mut AsciiInput::new(stdin.lock()),
+            &mut AsciiOutput(std::io::stdout()),
+        )?;
+        Ok(())
     }
 
-    fn index(&self) -> Option<usize> {
-        match self {
-            Parameter::Position(p) => Some(*p),
-            Parameter::Immediate(_) => None,
-        }
-    }
-
-    fn arithmetic(
-        code: &[i64],
-        i: &mut usize,
-        opcode: i64,
-        modes_and_opcode: i64,
-    ) -> Result<(Self, Self, Self), Error> {
-        let modes = (
-            modes_and_opcode / 100 % 10,
-            modes_and_opcode / 1000 % 10,
-            modes_and_opcode / 10000 % 10,
-        );
-
-        let n1 = Self::from_code(code, i, modes.0, 0, opcode)?;
-        let n2 = Self::from_code(code, i, modes.1, 1, opcode)?;
-        let to = Self::positional_from_code(code, i, modes.2, 2, opcode)?;
-
-        Ok((n1, n2, to))
-    }
-
-    fn jump(
-        code: &[i64],
-        i: &mut usize,
-        opcode: i64,
-        modes_and_opcode: i64,
-    ) -> Result<(Self, Self), Error> {
-        let modes = (modes_and_opcode / 100 % 10, modes_and_opcode / 1000 % 10);
-
-        let test = Self::from_code(code, i, modes.0, 0, opcode)?;
-        let goto = Self::from_code(code, i, modes.1, 1, opcode)?;
-
-        Ok((test, goto))
+    /// Runs the machine with buffered inputs until it halts or runs out of inputs
+    pub fn eval(mut self, input: Vec<i64>) -> Result<EvalResults, Error> {
+        let provided = input.len();
+        let mut input = VecDeque::from(input);
+        let mut output = Vec::new();
+        let status = self.run_with(&mut input, &mut output)?;
+
+        Ok(EvalResults {
+            used_input: provided - input.len(),
+            output,
+            completed: status == Status::Halted,
+            run_code: self.ip(),
+            relative_base: self.relative_base(),
+            code: self.into_memory(),
+        })
     }
 }
 
-enum Instruction {
-    Add {
-        n1: Parameter,
-        n2: Parameter,
-        to: Parameter,
-    },
-    Multiply {
-        n1: Parameter,
-        n2: Parameter,
-        to: Parameter,
-    },
-    Input {
-        to: Parameter,
-    },
-    Output {
-        from: Parameter,
-    },
-    JumpIfTrue {
-        test: Parameter,
-        goto: Parameter,
-    },
-    JumpIfFalse {
-        test: Parameter,
-        goto: Parameter,
-    },
-    LessThan {
-        n1: Parameter,
-        n2: Parameter,
-        to: Parameter,
-    },
-    Equals {
-        n1: Parameter,
-        n2: Parameter,
-        to: Parameter,
-    },
-    Halt,
-    End,
-}
-
-impl Instruction {
-    fn from_code(code: &[i64], i: &mut usize) -> Result<Self, Error> {
-        let modes_and_opcode = match code.get(*i) {
-            None => return Ok(Instruction::End),
-            Some(n) => {
-                *i += 1;
-                *n
-            }
-        };
-
-        let opcode = modes_and_opcode % 100;
-        match opcode {
-            1 => {
-                let (n1, n2, to) = Parameter::arithmetic(code, i, opcode, modes_and_opcode)?;
-                Ok(Instruction::Add { n1, n2, to })
-            }
-            2 => {
-                let (n1, n2, to) = Parameter::arithmetic(code, i, opcode, modes_and_opcode)?;
-                Ok(Instruction::Multiply { n1, n2, to })
-            }
-            3 => {
-                let mode = modes_and_opcode / 100 % 10;
-                let to = Parameter::positional_from_code(code, i, mode, 0, opcode)?;
-                Ok(Instruction::Input { to })
-            }
-            4 => {
-                let mode = modes_and_opcode / 100 % 10;
-                let from = Parameter::from_code(code, i, mode, 0, opcode)?;
-                Ok(Instruction::Output { from })
-            }
-            5 => {
-                let (test, goto) = Parameter::jump(code, i, opcode, modes_and_opcode)?;
-                Ok(Instruction::JumpIfTrue { test, goto })
-            }
-            6 => {
-                let (test, goto) = Parameter::jump(code, i, opcode, modes_and_opcode)?;
-                Ok(Instruction::JumpIfFalse { test, goto })
-            }
-            7 => {
-                let (n1, n2, to) = Parameter::arithmetic(code, i, opcode, modes_and_opcode)?;
-                Ok(Instruction::LessThan { n1, n2, to })
-            }
-            8 => {
-                let (n1, n2, to) = Parameter::arithmetic(code, i, opcode, modes_and_opcode)?;
-                Ok(Instruction::Equals { n1, n2, to })
-            }
-            99 => Ok(Instruction::Halt),
-            _ => Err(Error::InvalidOpcode {
-                opcode,
-                position: *i,
-            }),
-        }
-    }
+/// Results of running a machine with buffered inputs
+#[derive(Debug)]
+pub struct EvalResults {
+    pub code: Memory,
+    pub output: Vec<i64>,
+    pub completed: bool,
+    pub run_code: usize,
+    pub relative_base: isize,
+    pub used_input: usize,
 }
 
-fn add(code: &mut [i64], n1: Parameter, n2: Parameter, to: Parameter) {
-    let n1 = n1.value(&code);
-    let n2 = n2.value(&code);
-    let to = to.index().unwrap();
-    code[to] = n1 + n2;
+pub fn eval<M: Into<Memory>>(code: M, input: Vec<i64>) -> Result<EvalResults, Error> {
+    Machine::new(code).eval(input)
 }
 
-fn multiply(code: &mut [i64], n1: Parameter, n2: Parameter, to: Parameter) {
-    let n1 = n1.value(&code);
-    let n2 = n2.value(&code);
-    let to = to.index().unwrap();
-    code[to] = n1 * n2;
-}
+#[cfg(test)]
+mod tests {
+    use crate::{
+        error::Error,
+        interpreter::{eval, Machine, Status},
+        io::Iter,
+        parser, runtime,
+    };
+    use std::fs;
 
-fn jump_if_true(
-    code: &mut [i64],
-    i: &mut usize,
-    test: Parameter,
-    goto: Parameter,
-) -> Result<(), Error> {
-    let test = test.value(&code);
-    if test != 0 {
-        let goto = goto.value(&code);
-        let goto = goto
-            .try_into()
-            .map_err(|_| Error::NegativePositionalParameter {
-                value: goto,
-                parameter: 1,
-                opcode: 5,
-                position: *i,
-            })?;
-        *i = goto;
+    fn parse_code() -> Vec<i64> {
+        let contents = fs::read_to_string("resources/test/day5.intcode").unwrap();
+        parser::parse(&contents).unwrap()
     }
-    Ok(())
-}
 
-fn jump_if_false(
-    code: &mut [i64],
-    i: &mut usize,
-    test: Parameter,
-    goto: Parameter,
-) -> Result<(), Error> {
-    let test = test.value(&code);
-    if test == 0 {
-        let goto = goto.value(&code);
-        let goto = goto
-            .try_into()
-            .map_err(|_| Error::NegativePositionalParameter {
-                value: goto,
-                parameter: 1,
-                opcode: 5,
-                position: *i,
-            })?;
-        *i = goto;
+    #[test]
+    fn day5_part1() {
+        let code = parse_code();
+        let expected = vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 13818007];
+        let result = eval(code, vec![1]);
+        assert_eq!(expected, result.unwrap().output);
     }
-    Ok(())
-}
 
-fn less_than(code: &mut [i64], n1: Parameter, n2: Parameter, to: Parameter) {
-    let n1 = n1.value(&code);
-    let n2 = n2.value(&code);
-    let to = to.index().unwrap();
-    if n1 < n2 {
-        code[to] = 1;
-    } else {
-        code[to] = 0;
+    #[test]
+    fn day5_part2() {
+        let code = parse_code();
+        let expected = vec![3176266];
+        let result = eval(code, vec![5]);
+        assert_eq!(expected, result.unwrap().output);
     }
-}
 
-fn equals(code: &mut [i64], n1: Parameter, n2: Parameter, to: Parameter) {
-    let n1 = n1.value(&code);
-    let n2 = n2.value(&code);
-    let to = to.index().unwrap();
-    if n1 == n2 {
-        code[to] = 1;
-    } else {
-        code[to] = 0;
+    #[test]
+    fn day9_large_numbers() {
+        let code = vec![104, 1125899906842624, 99];
+        let expected = vec![1125899906842624];
+        let result = eval(code, vec![]);
+        assert_eq!(expected, result.unwrap().output);
     }
-}
-
-pub fn run(code: &mut [i64], mut i: usize) -> Result<(), Error> {
-    let stdout = io::stdout();
-    let mut stdout = stdout.lock();
-    let stdin = io::stdin();
-    let mut stdin = stdin.lock();
-
-    loop {
-        let instruction = Instruction::from_code(&code, &mut i)?;
-        match instruction {
-            Instruction::Add { n1, n2, to } => add(code, n1, n2, to),
-            Instruction::Multiply { n1, n2, to } => multiply(code, n1, n2, to),
-            Instruction::Input { to } => {
-                let to = to.index().unwrap();
-
-                let mut input = None;
-                let mut buffer = String::new();
 
-                while input.is_none() {
-                    buffer.clear();
-
-                    stdout.write_all(b"> ").expect("Can't write to stdout");
-                    stdout.flush().expect("Can't flush stdout");
-                    stdin.read_line(&mut buffer).expect("Can't read from stdin");
-
-                    match buffer.replace("\n", "").replace("\r", "").parse() {
-                        Ok(i) => input = Some(i),
-                        Err(_) => {
-                            println!("Invalid");
-                            input = None
-                        }
-                    }
+    #[test]
+    fn relative_base() {
+        let code = vec![109, 7, 204, 0, 203, 0, 99, 42];
+        let result = eval(code, vec![3]).unwrap();
+        assert_eq!(vec![42], result.output);
+        assert_eq!(7, result.relative_base);
+        assert_eq!(3, result.code.read(7));
+    }
 
-                    println!();
-                }
+    #[test]
+    fn day9_quine() {
+        let code = vec![
+            109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99,
+        ];
+        let expected = code.clone();
+        let result = eval(code, vec![]);
+        assert_eq!(expected, result.unwrap().output);
+    }
 
-                code[to] = input.unwrap();
-            }
-            Instruction::Output { from } => {
-                let from = from.value(&code);
-                println!("{}", from);
-            }
-            Instruction::JumpIfTrue { test, goto } => jump_if_true(code, &mut i, test, goto)?,
-            Instruction::JumpIfFalse { test, goto } => jump_if_false(code, &mut i, test, goto)?,
-            Instruction::LessThan { n1, n2, to } => less_than(code, n1, n2, to),
-            Instruction::Equals { n1, n2, to } => equals(code, n1, n2, to),
-            Instruction::Halt => break,
-            Instruction::End => break,
-        }
+    #[test]
+    fn sparse_memory() {
+        let code = vec![1101, 1, 2, 1 << 40, 4, 1 << 40, 99];
+        let expected = vec![3];
+        let result = eval(code, vec![]);
+        assert_eq!(expected, result.unwrap().output);
     }
 
-    Ok(())
-}
+    #[test]
+    fn step_halts() {
+        let mut machine = Machine::new(vec![1101, 1, 2, 5, 99, 0]);
+        assert_eq!(Status::Running, machine.step().unwrap());
+        assert_eq!(Status::Halted, machine.step().unwrap());
+        assert_eq!(Status::Halted, machine.step().unwrap());
+        assert_eq!(4, machine.ip());
+        assert_eq!(3, machine.memory().read(5));
+    }
 
-#[derive(Debug)]
-pub struct EvalResults {
-    pub code: Vec<i64>,
-    pub output: Vec<i64>,
-    pub completed: bool,
-    pub run_code: usize,
-    pub used_input: usize,
-}
+    #[test]
+    fn last_write() {
+        let mut machine = Machine::new(vec![109, 3, 21101, 1, 2, 4, 99]);
+        machine.step().unwrap();
+        assert_eq!(None, machine.last_write());
+        machine.step().unwrap();
+        assert_eq!(Some(7), machine.last_write());
+    }
 
-pub fn eval(mut code: Vec<i64>, input: Vec<i64>) -> Result<EvalResults, Error> {
-    let mut output = Vec::new();
-    let mut completed = false;
+    #[test]
+    fn needs_input() {
+        let mut machine = Machine::new(vec![3, 7, 4, 7, 99]);
+        assert_eq!(Status::NeedsInput, machine.run_until().unwrap());
+        assert_eq!(0, machine.ip());
+        machine.push_input(5);
+        assert_eq!(Status::Output(5), machine.run_until().unwrap());
+        assert_eq!(Status::Halted, machine.run_until().unwrap());
+    }
 
-    let mut i = 0;
-    let mut j = 0;
-    loop {
-        let instruction = Instruction::from_code(&code, &mut i)?;
-        match instruction {
-            Instruction::Add { n1, n2, to } => add(&mut code, n1, n2, to),
-            Instruction::Multiply { n1, n2, to } => multiply(&mut code, n1, n2, to),
-            Instruction::Input { to } => match input.get(j) {
-                None => {
-                    i -= 2;
-                    break;
-                }
-                Some(i) => {
-                    j += 1;
-                    let to = to.index().unwrap();
-                    code[to] = *i
+    #[test]
+    fn day7_feedback_loop() {
+        let code = vec![
+            3, 26, 1001, 26, -4, 26, 3, 27, 1002, 27, 2, 27, 1, 27, 26, 27, 4, 27, 1001, 28, -1,
+            28, 1005, 28, 6, 99, 0, 0, 5,
+        ];
+        let mut amplifiers: Vec<Machine> = [9, 8, 7, 6, 5]
+            .iter()
+            .map(|phase| {
+                let mut machine = Machine::new(code.clone());
+                machine.push_input(*phase);
+                machine
+            })
+            .collect();
+
+        let mut signal = 0;
+        'feedback: loop {
+            for amplifier in amplifiers.iter_mut() {
+                amplifier.push_input(signal);
+                match amplifier.run_until().unwrap() {
+                    Status::Output(o) => signal = o,
+                    Status::Halted => break 'feedback,
+                    status => panic!("unexpected {:?}", status),
                 }
-            },
-            Instruction::Output { from } => {
-                let from = from.value(&code);
-                output.push(from);
-            }
-            Instruction::JumpIfTrue { test, goto } => jump_if_true(&mut code, &mut i, test, goto)?,
-            Instruction::JumpIfFalse { test, goto } => {
-                jump_if_false(&mut code, &mut i, test, goto)?
-            }
-            Instruction::LessThan { n1, n2, to } => less_than(&mut code, n1, n2, to),
-            Instruction::Equals { n1, n2, to } => equals(&mut code, n1, n2, to),
-            Instruction::Halt => {
-                completed = true;
-                break;
-            }
-            Instruction::End => {
-                completed = true;
-                break;
             }
         }
+        assert_eq!(139629729, signal);
     }
 
-    Ok(EvalResults {
-        code,
-        output,
-        completed,
-        run_code: i,
-        used_input: j,
-    })
-}
+    #[test]
+    fn run_with_handlers() {
+        let mut machine = Machine::new(vec![3, 11, 3, 12, 1, 11, 12, 13, 4, 13, 99]);
+        let mut outputs = Vec::new();
+        let status = machine
+            .run_with(&mut Iter(vec![2, 3].into_iter()), &mut |o| outputs.push(o))
+            .unwrap();
+        assert_eq!(Status::Halted, status);
+        assert_eq!(vec![5], outputs);
+    }
 
-#[cfg(test)]
-mod tests {
-    use crate::{interpreter::eval, parser};
-    use std::fs;
+    #[test]
+    fn address_out_of_bounds() {
+        let code = vec![109, -5, 204, 2, 99];
+        let expected = Error::Runtime(runtime::Error::AddressOutOfBounds {
+            address: -3,
+            opcode: 4,
+            position: 2,
+        });
+        assert_eq!(expected, eval(code, vec![]).unwrap_err());
+    }
 
-    fn parse_code() -> Vec<i64> {
-        let contents = fs::read_to_string("resources/test/day5.intcode").unwrap();
-        let code = parser::parse(&contents).unwrap();
-        code
+    #[test]
+    fn arithmetic_overflow() {
+        let code = vec![1102, i64::MAX, 2, 0, 99];
+        let expected = Error::Runtime(runtime::Error::ArithmeticOverflow {
+            opcode: 2,
+            position: 0,
+        });
+        assert_eq!(expected, eval(code, vec![]).unwrap_err());
     }
 
     #[test]
-    fn day5_part1() {
-        let code = parse_code();
-        let expected = vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 13818007];
-        let result = eval(code, vec![1]);
-        assert_eq!(expected, result.unwrap().output);
+    fn jump_out_of_bounds() {
+        let code = vec![1105, 1, -1, 99];
+        let expected = Error::Runtime(runtime::Error::JumpOutOfBounds {
+            target: -1,
+            opcode: 5,
+            position: 0,
+        });
+        assert_eq!(expected, eval(code, vec![]).unwrap_err());
+
+        let code = vec![1106, 0, 100, 99];
+        let expected = Error::Runtime(runtime::Error::JumpOutOfBounds {
+            target: 100,
+            opcode: 6,
+            position: 0,
+        });
+        assert_eq!(expected, eval(code, vec![]).unwrap_err());
     }
 
     #[test]
-    fn day5_part2() {
-        let code = parse_code();
-        let expected = vec![3176266];
-        let result = eval(code, vec![5]);
-        assert_eq!(expected, result.unwrap().output);
+    fn jump_to_end() {
+        // Jumping right past the end of the program halts it like running off its end does
+        let results = eval(vec![104, 1, 1105, 1, 5], vec![]).unwrap();
+        assert!(results.completed);
+        assert_eq!(vec![1], results.output);
     }
 }
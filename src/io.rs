@@ -0,0 +1,300 @@
+use crate::runtime::Error;
+use std::{
+    collections::VecDeque,
+    io::{self, BufRead, Write},
+    sync::mpsc::{Receiver, Sender, SyncSender},
+};
+
+/// A source of inputs for Intcode input instructions
+pub trait IntcodeInput {
+    /// Returns the next input, or `None` if no input is currently available
+    fn read(&mut self) -> Option<i64>;
+}
+
+/// A sink for values produced by Intcode output instructions
+pub trait IntcodeOutput {
+    fn write(&mut self, value: i64);
+}
+
+/// Interactively prompts for inputs on stdin
+pub struct Prompt;
+
+impl IntcodeInput for Prompt {
+    fn read(&mut self) -> Option<i64> {
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        let stdin = io::stdin();
+        let mut stdin = stdin.lock();
+
+        let mut buffer = String::new();
+        loop {
+            buffer.clear();
+
+            stdout.write_all(b"> ").expect("Can't write to stdout");
+            stdout.flush().expect("Can't flush stdout");
+            if stdin.read_line(&mut buffer).expect("Can't read from stdin") == 0 {
+                return None;
+            }
+
+            match buffer.replace("\n", "").replace("\r", "").parse() {
+                Ok(i) => {
+                    println!();
+                    return Some(i);
+                }
+                Err(_) => {
+                    println!("Invalid");
+                    println!();
+                }
+            }
+        }
+    }
+}
+
+/// Reads inputs separated by commas and/or whitespace without prompting
+///
+/// Reading stops at the end of input or at the first invalid value, which is kept as an error.
+pub struct Batch<R> {
+    reader: R,
+    pending: VecDeque<i64>,
+    /// Number of values parsed so far
+    parsed: usize,
+    error: Option<Error>,
+}
+
+impl<R: BufRead> Batch<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            pending: VecDeque::new(),
+            parsed: 0,
+            error: None,
+        }
+    }
+
+    /// The invalid value reading stopped at, if any
+    pub fn into_error(self) -> Option<Error> {
+        self.error
+    }
+}
+
+impl<R: BufRead> IntcodeInput for Batch<R> {
+    fn read(&mut self) -> Option<i64> {
+        let mut buffer = String::new();
+        while self.pending.is_empty() {
+            if self.error.is_some() {
+                return None;
+            }
+            buffer.clear();
+            if self
+                .reader
+                .read_line(&mut buffer)
+                .expect("Can't read input")
+                == 0
+            {
+                return None;
+            }
+
+            // Values before an invalid one are still fed to the program
+            for token in buffer
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|t| !t.is_empty())
+            {
+                match token.parse() {
+                    Ok(value) => self.pending.push_back(value),
+                    Err(_) => {
+                        self.error = Some(Error::InvalidInput {
+                            token: token.to_owned(),
+                            position: self.parsed,
+                        });
+                        break;
+                    }
+                }
+                self.parsed += 1;
+            }
+        }
+        self.pending.pop_front()
+    }
+}
+
+/// Reads lines of text, feeding the code of each character followed by a newline
+pub struct AsciiInput<R> {
+    reader: R,
+    pending: VecDeque<i64>,
+}
+
+impl<R: BufRead> AsciiInput<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            pending: VecDeque::new(),
+        }
+    }
+}
+
+impl<R: BufRead> IntcodeInput for AsciiInput<R> {
+    fn read(&mut self) -> Option<i64> {
+        if self.pending.is_empty() {
+            io::stdout().flush().expect("Can't flush stdout");
+            let mut buffer = String::new();
+            if self
+                .reader
+                .read_line(&mut buffer)
+                .expect("Can't read input")
+                == 0
+            {
+                return None;
+            }
+            let line = buffer.trim_end_matches(['\n', '\r']);
+            self.pending.extend(line.chars().map(|c| c as i64));
+            self.pending.push_back(10);
+        }
+        self.pending.pop_front()
+    }
+}
+
+/// Writes outputs below 128 as characters and anything else as a number on its own line
+pub struct AsciiOutput<W>(pub W);
+
+impl<W: Write> IntcodeOutput for AsciiOutput<W> {
+    fn write(&mut self, value: i64) {
+        match value {
+            0..=127 => write!(self.0, "{}", value as u8 as char),
+            _ => writeln!(self.0, "{}", value),
+        }
+        .expect("Can't write output");
+    }
+}
+
+impl IntcodeOutput for io::Stdout {
+    fn write(&mut self, value: i64) {
+        writeln!(self, "{}", value).expect("Can't write output");
+    }
+}
+
+impl IntcodeOutput for Vec<i64> {
+    fn write(&mut self, value: i64) {
+        self.push(value);
+    }
+}
+
+impl IntcodeInput for VecDeque<i64> {
+    fn read(&mut self) -> Option<i64> {
+        self.pop_front()
+    }
+}
+
+impl IntcodeOutput for VecDeque<i64> {
+    fn write(&mut self, value: i64) {
+        self.push_back(value);
+    }
+}
+
+/// Reads inputs from an iterator until it is exhausted
+pub struct Iter<I>(pub I);
+
+impl<I: Iterator<Item = i64>> IntcodeInput for Iter<I> {
+    fn read(&mut self) -> Option<i64> {
+        self.0.next()
+    }
+}
+
+/// Blocks on the channel until an input is received or every sender is dropped
+impl IntcodeInput for Receiver<i64> {
+    fn read(&mut self) -> Option<i64> {
+        self.recv().ok()
+    }
+}
+
+/// Outputs sent after the receiver is dropped are discarded
+impl IntcodeOutput for Sender<i64> {
+    fn write(&mut self, value: i64) {
+        self.send(value).ok();
+    }
+}
+
+impl IntcodeOutput for SyncSender<i64> {
+    fn write(&mut self, value: i64) {
+        self.send(value).ok();
+    }
+}
+
+impl<F: FnMut() -> Option<i64>> IntcodeInput for F {
+    fn read(&mut self) -> Option<i64> {
+        self()
+    }
+}
+
+impl<F: FnMut(i64)> IntcodeOutput for F {
+    fn write(&mut self, value: i64) {
+        self(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        io::{AsciiInput, AsciiOutput, Batch, IntcodeInput, IntcodeOutput, Iter},
+        runtime::Error,
+    };
+    use std::{collections::VecDeque, sync::mpsc};
+
+    #[test]
+    fn vec_deque() {
+        let mut input = VecDeque::from(vec![1, 2]);
+        assert_eq!(Some(1), input.read());
+        assert_eq!(Some(2), input.read());
+        assert_eq!(None, input.read());
+    }
+
+    #[test]
+    fn iter() {
+        let mut input = Iter(1..3);
+        assert_eq!(Some(1), input.read());
+        assert_eq!(Some(2), input.read());
+        assert_eq!(None, input.read());
+    }
+
+    #[test]
+    fn batch() {
+        let mut input = Batch::new("1, 2\n\n3 x 4\n".as_bytes());
+        assert_eq!(Some(1), input.read());
+        assert_eq!(Some(2), input.read());
+        assert_eq!(Some(3), input.read());
+        assert_eq!(None, input.read());
+        let expected = Error::InvalidInput {
+            token: "x".to_owned(),
+            position: 3,
+        };
+        assert_eq!(Some(expected), input.into_error());
+    }
+
+    #[test]
+    fn ascii() {
+        let mut input = AsciiInput::new("ab\r\n".as_bytes());
+        let codes: Vec<Option<i64>> = (0..4).map(|_| input.read()).collect();
+        assert_eq!(vec![Some(97), Some(98), Some(10), None], codes);
+
+        let mut output = AsciiOutput(Vec::new());
+        for value in &[72, 105, 10, 1234] {
+            output.write(*value);
+        }
+        assert_eq!(b"Hi\n1234\n", &output.0[..]);
+    }
+
+    #[test]
+    fn channel() {
+        let (mut tx, mut rx) = mpsc::channel();
+        tx.write(1);
+        drop(tx);
+        assert_eq!(Some(1), rx.read());
+        assert_eq!(None, rx.read());
+    }
+
+    #[test]
+    fn closure() {
+        let mut outputs = Vec::new();
+        let mut output = |v| outputs.push(v * 2);
+        output.write(2);
+        assert_eq!(vec![4], outputs);
+    }
+}
@@ -0,0 +1,25 @@
+//! AoC 2019 Intcode compiler, interpreter and transpiler
+
+pub mod assembler;
+pub mod chain;
+pub mod debugger;
+pub mod disasm;
+pub mod error;
+pub mod grid;
+pub mod interpreter;
+pub mod io;
+pub mod network;
+pub mod parser;
+pub mod profile;
+pub mod program;
+pub mod runtime;
+pub mod session;
+pub mod snapshot;
+pub mod trace;
+pub mod transpiler;
+
+pub use error::Error;
+pub use interpreter::{EvalResults, Machine, Status};
+pub use io::{IntcodeInput, IntcodeOutput};
+pub use program::Program;
+pub use runtime::Memory;
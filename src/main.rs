@@ -1,16 +1,26 @@
-use std::io::Write;
+use intcode_compiler::{
+    assembler, chain,
+    debugger::Debugger,
+    disasm,
+    grid::{self, Grid, Robot},
+    io::{AsciiInput, AsciiOutput, Batch, IntcodeInput, IntcodeOutput, Prompt},
+    network::{IdleNat, Network},
+    parser, profile,
+    session::Session,
+    snapshot::Snapshot,
+    trace::{self, TraceWriter},
+    transpiler::{self, Backend},
+    Error, Machine, Program, Status,
+};
+use std::io::{self, BufWriter, Write};
 use std::{
+    collections::VecDeque,
     fs,
     path::{Path, PathBuf},
     process::{self, Command, Stdio},
 };
 use structopt::StructOpt;
 
-mod error;
-mod interpreter;
-mod parser;
-mod transpiler;
-
 fn read_to_string<P: AsRef<Path>>(path: P) -> String {
     fs::read_to_string(path).unwrap_or_else(|e| {
         println!("{}", e);
@@ -26,6 +36,60 @@ enum Opt {
         /// Intcode file to run
         #[structopt(name = "FILE")]
         file: PathBuf,
+
+        #[structopt(flatten)]
+        options: RunOptions,
+    },
+
+    /// Runs an Intcode program with buffered inputs and saves its state once they run out
+    Snapshot {
+        /// Intcode file to run
+        #[structopt(name = "FILE")]
+        file: PathBuf,
+
+        /// File to read inputs from, separated by commas and/or whitespace
+        #[structopt(short, long, name = "INPUT")]
+        input: Option<PathBuf>,
+
+        /// Inputs to pass to the program after the ones from the input file, like `1,2,3`
+        #[structopt(long, name = "VALUES", allow_hyphen_values = true)]
+        input_values: Option<String>,
+
+        /// File to write the snapshot to
+        #[structopt(short, long, name = "OUTPUT")]
+        output: PathBuf,
+    },
+
+    /// Resumes running a program from a snapshot
+    Resume {
+        /// Snapshot file to resume from
+        #[structopt(name = "SNAPSHOT")]
+        snapshot: PathBuf,
+
+        /// File to save a new snapshot to if the program stops waiting for input
+        #[structopt(short, long, name = "OUTPUT")]
+        output: Option<PathBuf>,
+
+        #[structopt(flatten)]
+        options: RunOptions,
+    },
+
+    /// Steps through an Intcode program with an interactive debugger
+    Debug {
+        /// Intcode file to debug
+        #[structopt(name = "FILE")]
+        file: PathBuf,
+    },
+
+    /// Plays an ASCII program, such as the day 25 adventure, in a shell with history and snapshots
+    Session {
+        /// Intcode file to run
+        #[structopt(name = "FILE")]
+        file: PathBuf,
+
+        /// Script of commands to replay before reading from stdin
+        #[structopt(short, long, name = "SCRIPT")]
+        script: Option<PathBuf>,
     },
 
     /// Compiles an Intcode program to a standalone binary
@@ -42,100 +106,629 @@ enum Opt {
         #[structopt(short, long, name = "OUTPUT")]
         output: Option<PathBuf>,
 
-        /// Transpiles the Intcode program to Rust and prints in without compiling
+        /// Transpiles the Intcode program and prints the source without compiling it
         #[structopt(short = "T", long)]
         transpile_only: bool,
 
-        /// LLVM optimisation level
+        /// Language to transpile to: `rust` to build with rustc, `c` to build with cc, `llvm`
+        /// to build with clang or llc, `native` to write a Linux x86-64 executable directly, or
+        /// `wat` or `js` to write a WebAssembly text or JavaScript module that leaves I/O to its
+        /// host
+        #[structopt(short, long, name = "BACKEND", default_value = "rust")]
+        backend: transpiler::Backend,
+
+        /// Makes the binary read lines of text and print outputs below 128 as characters, which
+        /// doesn't apply to modules
+        #[structopt(short, long)]
+        ascii: bool,
+
+        /// Optimisation level passed to the compiler
         #[structopt(short = "O", long = "opt-level", name = "LEVEL")]
         optimisation_level: Option<char>,
     },
+
+    /// Runs an Intcode program with buffered inputs and reports where it spends its time
+    Profile {
+        /// Intcode file to profile
+        #[structopt(name = "FILE")]
+        file: PathBuf,
+
+        /// Inputs to pass to the program, formatted the same way as Intcode
+        #[structopt(short, long, name = "INPUT")]
+        input: Option<PathBuf>,
+
+        /// Number of hottest addresses and loops to report
+        #[structopt(short = "n", long, default_value = "10")]
+        top: usize,
+
+        /// File to write call stacks to, in the folded format used by flamegraph tools
+        #[structopt(long, name = "FOLDED")]
+        folded: Option<PathBuf>,
+    },
+
+    /// Runs an Intcode program with buffered inputs and draws what it outputs
+    Render {
+        /// Intcode file to run
+        #[structopt(name = "FILE")]
+        file: PathBuf,
+
+        /// File to read inputs from, separated by commas and/or whitespace
+        #[structopt(short, long, name = "INPUT")]
+        input: Option<PathBuf>,
+
+        /// Inputs to pass to the program after the ones from the input file, like `1,2,3`
+        #[structopt(long, name = "VALUES", allow_hyphen_values = true)]
+        input_values: Option<String>,
+
+        /// How to read outputs: `triples`, `ascii` frames or the day 11 `robot` protocol
+        #[structopt(short, long, name = "MODE", default_value = "triples")]
+        mode: grid::Mode,
+
+        /// Color of the panel the robot starts on
+        #[structopt(long, name = "COLOR", default_value = "0")]
+        start: i64,
+
+        /// Image file to write, as PNG if it ends in `.png` and as PPM otherwise
+        #[structopt(short, long, name = "OUTPUT")]
+        output: Option<PathBuf>,
+
+        /// Size in pixels of each tile of the image
+        #[structopt(long, name = "SCALE", default_value = "8")]
+        scale: usize,
+    },
+
+    /// Assembles Intcode assembly to the comma-separated Intcode format
+    Asm {
+        /// Assembly file to assemble
+        #[structopt(name = "FILE")]
+        file: PathBuf,
+
+        /// File to write the Intcode program to
+        #[structopt(short, long, name = "OUTPUT")]
+        output: Option<PathBuf>,
+    },
+
+    /// Disassembles an Intcode program
+    Disasm {
+        /// Intcode file to disassemble
+        #[structopt(name = "FILE")]
+        file: PathBuf,
+    },
+
+    /// Runs copies of an Intcode program as a chain of amplifiers
+    Chain {
+        /// Intcode file to run
+        #[structopt(name = "FILE")]
+        file: PathBuf,
+
+        /// Phase settings, either comma-separated or as an inclusive range like `5-9`
+        #[structopt(short, long, name = "PHASES", default_value = "0-4")]
+        phases: String,
+
+        /// Tries every permutation of the phase settings and reports the best one
+        #[structopt(short = "P", long)]
+        permute: bool,
+
+        /// Feeds the output of the last amplifier back into the first one
+        #[structopt(short, long)]
+        feedback: bool,
+    },
+
+    /// Runs copies of an Intcode program as a packet-switched network
+    Network {
+        /// Intcode file to run
+        #[structopt(name = "FILE")]
+        file: PathBuf,
+
+        /// Number of machines in the network
+        #[structopt(short, long, default_value = "50")]
+        nodes: usize,
+
+        /// Address of the NAT
+        #[structopt(long, default_value = "255")]
+        nat: i64,
+    },
+}
+
+/// Input, output and tracing options shared by the commands running a program
+#[derive(StructOpt)]
+struct RunOptions {
+    /// File to read inputs from, separated by commas and/or whitespace
+    #[structopt(short, long, name = "INPUT")]
+    input: Option<PathBuf>,
+
+    /// Inputs to pass to the program after the ones from the input file, like `1,2,3`
+    #[structopt(long, name = "VALUES", allow_hyphen_values = true)]
+    input_values: Option<String>,
+
+    /// Reads further inputs from stdin without prompting
+    #[structopt(short, long)]
+    batch: bool,
+
+    /// Reads lines of text from stdin and prints outputs below 128 as characters
+    #[structopt(short, long, conflicts_with = "batch")]
+    ascii: bool,
+
+    /// File to record every executed instruction to
+    #[structopt(long, name = "TRACE")]
+    trace: Option<PathBuf>,
+
+    /// Format of the trace, either `text` or `json` for JSON Lines
+    #[structopt(long, name = "FORMAT", default_value = "text")]
+    trace_format: trace::Format,
+}
+
+/// Reads the inputs given in an input file and on the command line
+fn read_inputs(
+    input: Option<PathBuf>,
+    input_values: Option<String>,
+) -> Result<VecDeque<i64>, Error> {
+    let mut inputs = VecDeque::new();
+    if let Some(input) = input {
+        inputs.extend(parser::parse_values(&read_to_string(input))?);
+    }
+    if let Some(values) = input_values {
+        inputs.extend(parser::parse_values(&values)?);
+    }
+    Ok(inputs)
+}
+
+/// Runs a machine until it halts or stdin runs out, printing `pending` outputs first
+fn run_machine(
+    machine: &mut Machine,
+    pending: &[i64],
+    options: RunOptions,
+) -> Result<Status, Error> {
+    for i in read_inputs(options.input, options.input_values)? {
+        machine.push_input(i);
+    }
+
+    let mut output: Box<dyn IntcodeOutput> = if options.ascii {
+        Box::new(AsciiOutput(io::stdout()))
+    } else {
+        Box::new(io::stdout())
+    };
+    for o in pending {
+        output.write(*o);
+    }
+
+    let trace = options.trace_format;
+    let mut tracer = options.trace.map(|path| {
+        let file = fs::File::create(path).unwrap_or_else(|e| {
+            println!("{}", e);
+            process::exit(3);
+        });
+        TraceWriter::new(BufWriter::new(file), trace)
+    });
+    let mut run = |input: &mut dyn IntcodeInput| match &mut tracer {
+        None => machine.run_with(input, &mut *output),
+        Some(tracer) => machine.run_traced(input, &mut *output, tracer),
+    };
+
+    let stdin = io::stdin();
+    if options.ascii {
+        run(&mut AsciiInput::new(stdin.lock()))
+    } else if options.batch {
+        let mut input = Batch::new(stdin.lock());
+        let status = run(&mut input)?;
+        match input.into_error() {
+            Some(e) => Err(e.into()),
+            None => Ok(status),
+        }
+    } else {
+        run(&mut Prompt)
+    }
+}
+
+/// Runs a compiler with `source` on its stdin, exiting if it fails
+fn build(command: &mut Command, source: &str) {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .unwrap_or_else(|e| {
+            println!("{}", e);
+            process::exit(4);
+        });
+    child
+        .stdin
+        .as_mut()
+        .unwrap()
+        .write_all(source.as_bytes())
+        .unwrap_or_else(|e| {
+            println!("{}", e);
+            process::exit(4);
+        });
+    let status = child.wait().unwrap_or_else(|e| {
+        println!("{}", e);
+        process::exit(4);
+    });
+
+    if !status.success() {
+        process::exit(status.code().unwrap());
+    }
+}
+
+/// Major version of an LLVM tool, if it can be run
+fn llvm_version(tool: &str) -> Option<u32> {
+    let output = Command::new(tool).arg("--version").output().ok()?;
+    let output = String::from_utf8_lossy(&output.stdout);
+    let version = output.split("version ").nth(1)?;
+    version.split('.').next()?.trim().parse().ok()
+}
+
+/// Builds LLVM IR with clang, or with llc and cc, writing the IR next to the output and failing if
+/// neither is available
+///
+/// LLVM 14 only understands the opaque pointers used by the IR when asked to.
+fn build_llvm(source: &str, output: &Path, optimisation_level: char) {
+    let output_path = output.to_str().unwrap();
+    if let Some(version) = llvm_version("clang") {
+        let mut command = Command::new("clang");
+        command.args([
+            "-x",
+            "ir",
+            "-",
+            &format!("-O{}", optimisation_level),
+            "-o",
+            output_path,
+        ]);
+        if version < 15 {
+            command.args(["-Xclang", "-opaque-pointers"]);
+        }
+        build(&mut command, source);
+    } else if let Some(version) = llvm_version("llc") {
+        let level = match optimisation_level {
+            's' | 'z' => '2',
+            l => l,
+        };
+        let object = output.with_extension("o");
+        let mut command = Command::new("llc");
+        command
+            .args(["-filetype=obj", "-relocation-model=pic", "-o"])
+            .arg(&object)
+            .arg(format!("-O{}", level));
+        if version < 15 {
+            command.arg("-opaque-pointers");
+        }
+        build(&mut command, source);
+        build(
+            Command::new("cc").arg(&object).args(["-o", output_path]),
+            "",
+        );
+        fs::remove_file(&object).ok();
+    } else {
+        let ir = output.with_extension("ll");
+        write_file(&ir, source.to_owned());
+        eprintln!(
+            "Neither clang nor llc was found, wrote the LLVM IR to {}",
+            ir.display()
+        );
+        process::exit(4);
+    }
+}
+
+fn write_file<P: AsRef<Path>>(path: P, contents: String) {
+    fs::write(path, contents).unwrap_or_else(|e| {
+        println!("{}", e);
+        process::exit(3);
+    })
+}
+
+/// Writes a binary and makes it executable
+fn write_executable(path: &Path, contents: Vec<u8>) {
+    fs::write(path, contents).unwrap_or_else(|e| {
+        println!("{}", e);
+        process::exit(3);
+    });
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o755)).unwrap_or_else(|e| {
+            println!("{}", e);
+            process::exit(3);
+        });
+    }
 }
 
 impl Opt {
-    fn run(self) -> Result<(), error::Error> {
+    fn run(self) -> Result<(), Error> {
         match self {
-            Opt::Run { file } => {
+            Opt::Run { file, options } => {
+                let contents = read_to_string(file);
+                let program = Program::parse(&contents)?;
+                run_machine(&mut program.machine(), &[], options)?;
+            }
+            Opt::Snapshot {
+                file,
+                input,
+                input_values,
+                output,
+            } => {
+                let contents = read_to_string(file);
+                let program = Program::parse(&contents)?;
+                let mut machine = program.machine();
+                let mut outputs = Vec::new();
+                let status =
+                    machine.run_with(&mut read_inputs(input, input_values)?, &mut outputs)?;
+
+                let mut snapshot = machine.save();
+                snapshot.output = outputs;
+                write_file(&output, snapshot.to_string());
+                match status {
+                    Status::Halted => println!("Program halted at {}", snapshot.ip),
+                    _ => println!("Program waiting for input at {}", snapshot.ip),
+                }
+            }
+            Opt::Resume {
+                snapshot,
+                output,
+                options,
+            } => {
+                let contents = read_to_string(snapshot);
+                let snapshot: Snapshot = contents.parse()?;
+                let mut machine = Machine::load(&snapshot);
+                let status = run_machine(&mut machine, &snapshot.output, options)?;
+                if let (Some(output), Status::NeedsInput) = (output, status) {
+                    write_file(output, machine.save().to_string());
+                }
+            }
+            Opt::Debug { file } => {
                 let contents = read_to_string(file);
-                let mut code = parser::parse(&contents)?;
-                interpreter::run(&mut code, 0)?;
+                let program = Program::parse(&contents)?;
+                Debugger::new(program.machine()).run();
+            }
+            Opt::Session { file, script } => {
+                let contents = read_to_string(file);
+                let program = Program::parse(&contents)?;
+                let mut session = Session::new(program.machine());
+                print!("{}", session.start()?);
+                if let Some(script) = script {
+                    match session.replay(&read_to_string(script))? {
+                        Some(text) => print!("{}", text),
+                        None => return Ok(()),
+                    }
+                }
+                session.run()?;
             }
             Opt::Compile {
                 file,
                 input,
                 output,
                 transpile_only,
+                backend,
+                ascii,
                 optimisation_level,
             } => {
+                if transpile_only && backend == Backend::Native {
+                    eprintln!("The native backend emits machine code without any source");
+                    process::exit(2);
+                }
+                if ascii && (backend == Backend::Wat || backend == Backend::Js) {
+                    eprintln!("--ascii only applies to binaries, modules leave I/O to their host");
+                    process::exit(2);
+                }
+
+                let optimisation_level = match optimisation_level {
+                    None => 'z',
+                    Some(l) => match l {
+                        '0'..='3' | 's' | 'z' => optimisation_level.unwrap(),
+                        _ => {
+                            eprintln!("Invalid optimisation level");
+                            process::exit(2);
+                        }
+                    },
+                };
+
                 let contents = read_to_string(&file);
-                let code = parser::parse(&contents)?;
+                let program = Program::parse(&contents)?;
                 let input = match input {
                     None => vec![],
                     Some(i) => {
                         let contents = read_to_string(i);
-                        parser::parse(&contents)?
+                        Program::parse(&contents)?.into_code()
                     }
                 };
 
-                let transpiled = transpiler::transpile(code, input)?;
-                if transpile_only {
-                    print!("{}", transpiled);
-                    return Ok(());
-                }
-
                 let output = output.unwrap_or_else(|| {
                     PathBuf::from({
                         let file_stem = file.file_stem().unwrap().to_str().unwrap();
-                        if cfg!(windows) {
+                        if backend == Backend::Wat {
+                            format!("{}.wat", file_stem)
+                        } else if backend == Backend::Js {
+                            format!("{}.mjs", file_stem)
+                        } else if cfg!(windows) {
                             format!("{}.exe", file_stem)
                         } else {
                             file_stem.to_owned()
                         }
                     })
                 });
-                let optimisation_level = match optimisation_level {
-                    None => 'z',
-                    Some(l) => match l {
-                        '0'..='3' | 's' | 'z' => optimisation_level.unwrap(),
-                        _ => {
-                            println!("Invalid optimisation level");
-                            process::exit(2);
+
+                let transpiled = match backend {
+                    Backend::Rust => transpiler::transpile(program.into_code(), input, ascii)?,
+                    Backend::C => transpiler::transpile_c(program.into_code(), input, ascii)?,
+                    Backend::Llvm => transpiler::transpile_llvm(program.into_code(), input, ascii)?,
+                    Backend::Wat => transpiler::transpile_wat(program.into_code(), input)?,
+                    Backend::Js => transpiler::transpile_js(program.into_code(), input)?,
+                    Backend::Native => {
+                        let binary =
+                            transpiler::transpile_native(program.into_code(), input, ascii)?;
+                        write_executable(&output, binary);
+                        return Ok(());
+                    }
+                };
+                if transpile_only {
+                    print!("{}", transpiled);
+                    return Ok(());
+                }
+
+                let output_path = output.to_str().unwrap();
+                match backend {
+                    Backend::Rust => build(
+                        Command::new("rustc").args([
+                            "-",
+                            "--edition",
+                            "2018",
+                            "-C",
+                            &format!("opt-level={}", optimisation_level),
+                            "-o",
+                            output_path,
+                        ]),
+                        &transpiled,
+                    ),
+                    Backend::C => {
+                        // Older compilers don't know about -Oz
+                        let level = if optimisation_level == 'z' {
+                            's'
+                        } else {
+                            optimisation_level
+                        };
+                        build(
+                            Command::new("cc").args([
+                                "-std=c99",
+                                &format!("-O{}", level),
+                                "-x",
+                                "c",
+                                "-",
+                                "-o",
+                                output_path,
+                            ]),
+                            &transpiled,
+                        )
+                    }
+                    Backend::Llvm => build_llvm(&transpiled, &output, optimisation_level),
+                    Backend::Native => unreachable!("native binaries are written directly"),
+                    Backend::Wat | Backend::Js => write_file(&output, transpiled),
+                }
+            }
+            Opt::Profile {
+                file,
+                input,
+                top,
+                folded,
+            } => {
+                let contents = read_to_string(file);
+                let program = Program::parse(&contents)?;
+                let input = match input {
+                    None => vec![],
+                    Some(i) => {
+                        let contents = read_to_string(i);
+                        Program::parse(&contents)?.into_code()
+                    }
+                };
+
+                let profile = profile::profile(&program, input)?;
+                if !profile.output.is_empty() {
+                    let output: Vec<String> =
+                        profile.output.iter().map(|o| o.to_string()).collect();
+                    println!("Output: {}\n", output.join(","));
+                }
+                print!("{}", profile.report(top));
+
+                if let Some(folded) = folded {
+                    write_file(folded, profile.folded());
+                }
+            }
+            Opt::Render {
+                file,
+                input,
+                input_values,
+                mode,
+                start,
+                output,
+                scale,
+            } => {
+                let contents = read_to_string(file);
+                let program = Program::parse(&contents)?;
+                let mut machine = program.machine();
+                let mut inputs = read_inputs(input, input_values)?;
+
+                let grid = if mode == grid::Mode::Robot {
+                    for i in inputs {
+                        machine.push_input(i);
+                    }
+                    let grid = Robot::new().paint(machine, start)?;
+                    println!("Panels painted: {}", grid.len());
+                    grid
+                } else {
+                    let mut outputs = Vec::new();
+                    machine.run_with(&mut inputs, &mut outputs)?;
+                    if mode == grid::Mode::Ascii {
+                        Grid::from_ascii(&outputs)
+                    } else {
+                        let (grid, score) = Grid::from_triples(&outputs);
+                        if let Some(score) = score {
+                            println!("Score: {}", score);
                         }
-                    },
+                        grid
+                    }
                 };
+                let ascii = mode == grid::Mode::Ascii;
+                print!("{}", grid.render(ascii)?);
 
-                let mut child = Command::new("rustc")
-                    .args(&[
-                        "-",
-                        "-o",
-                        output.to_str().unwrap(),
-                        "-C",
-                        &format!("opt-level={}", optimisation_level),
-                    ])
-                    .stdin(Stdio::piped())
-                    .stdout(Stdio::inherit())
-                    .stderr(Stdio::inherit())
-                    .spawn()
-                    .unwrap_or_else(|e| {
-                        println!("{}", e);
-                        process::exit(4);
-                    });
-                child
-                    .stdin
-                    .as_mut()
-                    .unwrap()
-                    .write_all(transpiled.as_bytes())
-                    .unwrap_or_else(|e| {
+                if let Some(output) = output {
+                    let image = match output.extension() {
+                        Some(e) if e == "png" => grid.png(ascii, scale)?,
+                        _ => grid.ppm(ascii, scale)?,
+                    };
+                    fs::write(output, image).unwrap_or_else(|e| {
                         println!("{}", e);
-                        process::exit(4);
+                        process::exit(3);
                     });
-                let status = child.wait().unwrap_or_else(|e| {
-                    println!("{}", e);
-                    process::exit(4);
-                });
+                }
+            }
+            Opt::Asm { file, output } => {
+                let contents = read_to_string(file);
+                let code = assembler::assemble(&contents)?;
+                let code: Vec<String> = code.iter().map(|i| i.to_string()).collect();
+                let code = code.join(",");
 
-                if !status.success() {
-                    process::exit(status.code().unwrap());
+                match output {
+                    None => println!("{}", code),
+                    Some(output) => write_file(output, code + "\n"),
+                }
+            }
+            Opt::Disasm { file } => {
+                let contents = read_to_string(file);
+                let program = Program::parse(&contents)?;
+                print!("{}", disasm::disassemble(program.code()));
+            }
+            Opt::Chain {
+                file,
+                phases,
+                permute,
+                feedback,
+            } => {
+                let contents = read_to_string(file);
+                let program = Program::parse(&contents)?;
+                let phases = chain::parse_phases(&phases)?;
+
+                if permute {
+                    let (signal, phases) = chain::best(&program, &phases, feedback)?;
+                    let phases: Vec<String> = phases.iter().map(|p| p.to_string()).collect();
+                    println!("Best phases: {}", phases.join(","));
+                    println!("Signal: {}", signal);
+                } else {
+                    let signal = chain::signal(&program, &phases, feedback)?;
+                    println!("Signal: {}", signal);
+                }
+            }
+            Opt::Network { file, nodes, nat } => {
+                let contents = read_to_string(file);
+                let program = Program::parse(&contents)?;
+
+                let mut network = Network::new(&program, nodes);
+                let mut idle_nat = IdleNat::new(nat);
+                network.run(&mut idle_nat)?;
+
+                match idle_nat.received.first() {
+                    Some(p) => println!("First packet to {}: X={} Y={}", nat, p.x, p.y),
+                    None => println!("No packet sent to {}", nat),
+                }
+                if let Some(y) = idle_nat.repeated() {
+                    println!("First Y value delivered twice in a row: {}", y);
                 }
             }
         }
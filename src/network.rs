@@ -0,0 +1,291 @@
+use crate::{
+    error::Error,
+    interpreter::{Machine, Status},
+    program::Program,
+};
+use std::collections::VecDeque;
+
+/// A packet sent from one machine to another
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Packet {
+    pub destination: i64,
+    pub x: i64,
+    pub y: i64,
+}
+
+/// Results of running every machine of a network once
+#[derive(Debug, Default)]
+pub struct Tick {
+    /// Packets sent to addresses outside the network
+    pub external: Vec<Packet>,
+    /// Whether every queue was empty and no machine sent anything
+    pub idle: bool,
+}
+
+/// What a network should do after notifying its NAT
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Control {
+    Continue,
+    Send(Packet),
+    Stop,
+}
+
+/// Hook handling packets sent outside the network and waking it up when it is idle
+pub trait Nat {
+    fn receive(&mut self, packet: Packet) -> Control;
+    fn idle(&mut self) -> Control;
+}
+
+/// The day 23 NAT
+///
+/// Remembers the last packet sent to its address and delivers it to address 0 whenever the
+/// network is idle, stopping once the same Y value is delivered twice in a row.
+#[derive(Debug)]
+pub struct IdleNat {
+    address: i64,
+    last: Option<Packet>,
+    pub received: Vec<Packet>,
+    pub delivered: Vec<Packet>,
+}
+
+impl IdleNat {
+    pub fn new(address: i64) -> Self {
+        Self {
+            address,
+            last: None,
+            received: Vec::new(),
+            delivered: Vec::new(),
+        }
+    }
+
+    /// The first Y value delivered to address 0 twice in a row
+    pub fn repeated(&self) -> Option<i64> {
+        self.delivered
+            .windows(2)
+            .find(|w| w[0].y == w[1].y)
+            .map(|w| w[1].y)
+    }
+}
+
+impl Nat for IdleNat {
+    fn receive(&mut self, packet: Packet) -> Control {
+        if packet.destination == self.address {
+            self.received.push(packet);
+            self.last = Some(packet);
+        }
+        Control::Continue
+    }
+
+    fn idle(&mut self) -> Control {
+        let packet = match self.last {
+            None => return Control::Stop,
+            Some(last) => Packet {
+                destination: 0,
+                ..last
+            },
+        };
+        self.delivered.push(packet);
+        if self.repeated().is_some() {
+            Control::Stop
+        } else {
+            Control::Send(packet)
+        }
+    }
+}
+
+struct Node {
+    machine: Machine,
+    queue: VecDeque<(i64, i64)>,
+    outgoing: Vec<i64>,
+    halted: bool,
+}
+
+/// Copies of a program communicating through packet queues
+pub struct Network {
+    nodes: Vec<Node>,
+}
+
+impl Network {
+    /// Creates a network of `size` machines, each receiving its address as its first input
+    pub fn new(program: &Program, size: usize) -> Self {
+        let nodes = (0..size)
+            .map(|address| {
+                let mut machine = program.machine();
+                machine.push_input(address as i64);
+                Node {
+                    machine,
+                    queue: VecDeque::new(),
+                    outgoing: Vec::new(),
+                    halted: false,
+                }
+            })
+            .collect();
+        Self { nodes }
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Queues a packet for its destination, returning it back if the address is outside the network
+    pub fn send(&mut self, packet: Packet) -> Option<Packet> {
+        if packet.destination < 0 || packet.destination as usize >= self.nodes.len() {
+            return Some(packet);
+        }
+        self.nodes[packet.destination as usize]
+            .queue
+            .push_back((packet.x, packet.y));
+        None
+    }
+
+    /// Delivers at most one packet to every machine, or -1 if its queue is empty, and runs them
+    /// until they need more input
+    pub fn tick(&mut self) -> Result<Tick, Error> {
+        let mut idle = true;
+        let mut sent = Vec::new();
+
+        for node in self.nodes.iter_mut().filter(|n| !n.halted) {
+            match node.queue.pop_front() {
+                Some((x, y)) => {
+                    idle = false;
+                    node.machine.push_input(x);
+                    node.machine.push_input(y);
+                }
+                None => node.machine.push_input(-1),
+            }
+
+            loop {
+                match node.machine.run_until()? {
+                    Status::Output(o) => {
+                        node.outgoing.push(o);
+                        if node.outgoing.len() == 3 {
+                            sent.push(Packet {
+                                destination: node.outgoing[0],
+                                x: node.outgoing[1],
+                                y: node.outgoing[2],
+                            });
+                            node.outgoing.clear();
+                        }
+                    }
+                    Status::NeedsInput => break,
+                    Status::Halted => {
+                        node.halted = true;
+                        break;
+                    }
+                    Status::Running => unreachable!(),
+                }
+            }
+        }
+
+        if !sent.is_empty() {
+            idle = false;
+        }
+        let external = sent.into_iter().filter_map(|p| self.send(p)).collect();
+        Ok(Tick { external, idle })
+    }
+
+    /// Runs the network until the NAT stops it
+    pub fn run<N: Nat + ?Sized>(&mut self, nat: &mut N) -> Result<(), Error> {
+        loop {
+            let tick = self.tick()?;
+
+            let mut controls: VecDeque<Control> =
+                tick.external.into_iter().map(|p| nat.receive(p)).collect();
+            if tick.idle {
+                controls.push_back(nat.idle());
+            }
+
+            // Packets the NAT sends outside the network go back to it
+            while let Some(control) = controls.pop_front() {
+                match control {
+                    Control::Continue => (),
+                    Control::Send(packet) => {
+                        if let Some(packet) = self.send(packet) {
+                            controls.push_back(nat.receive(packet));
+                        }
+                    }
+                    Control::Stop => return Ok(()),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        network::{Control, IdleNat, Nat, Network, Packet},
+        program::Program,
+    };
+
+    // Node 0 sends (7, 8) to node 1, and every node forwards the packets it receives to 255
+    // with its own address as X
+    fn program() -> Program {
+        Program::new(vec![
+            3, 100, 1008, 100, 0, 101, 1006, 101, 18, 104, 1, 104, 7, 104, 8, 1105, 1, 18, 3, 102,
+            1008, 102, -1, 103, 1005, 103, 18, 3, 104, 104, 255, 4, 100, 4, 104, 1105, 1, 18,
+        ])
+    }
+
+    #[test]
+    fn routing() {
+        let mut network = Network::new(&program(), 2);
+        let tick = network.tick().unwrap();
+        assert!(tick.external.is_empty());
+        assert!(!tick.idle);
+
+        let tick = network.tick().unwrap();
+        let expected = vec![Packet {
+            destination: 255,
+            x: 1,
+            y: 8,
+        }];
+        assert_eq!(expected, tick.external);
+    }
+
+    #[test]
+    fn nat() {
+        let mut network = Network::new(&program(), 2);
+        let mut nat = IdleNat::new(255);
+        network.run(&mut nat).unwrap();
+
+        assert_eq!(1, nat.received[0].x);
+        assert_eq!(8, nat.received[0].y);
+        assert_eq!(Some(8), nat.repeated());
+    }
+
+    #[test]
+    fn nat_sends_outside() {
+        // Sends a packet to itself once the network is idle, then stops when it comes back
+        struct Echo {
+            sent: bool,
+        }
+
+        impl Nat for Echo {
+            fn receive(&mut self, packet: Packet) -> Control {
+                if packet.destination == 256 {
+                    Control::Stop
+                } else {
+                    Control::Continue
+                }
+            }
+
+            fn idle(&mut self) -> Control {
+                assert!(!self.sent, "the packet sent outside the network was lost");
+                self.sent = true;
+                Control::Send(Packet {
+                    destination: 256,
+                    x: 0,
+                    y: 0,
+                })
+            }
+        }
+
+        let mut network = Network::new(&program(), 2);
+        network.run(&mut Echo { sent: false }).unwrap();
+    }
+}
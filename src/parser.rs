@@ -1,4 +1,4 @@
-use crate::error::Error;
+use crate::runtime::Error;
 
 pub fn parse(input: &str) -> Result<Vec<i64>, Error> {
     input
@@ -22,9 +22,27 @@ pub fn parse(input: &str) -> Result<Vec<i64>, Error> {
         })
 }
 
+/// Parses values separated by commas and/or whitespace, like inputs given on the command line
+pub fn parse_values(input: &str) -> Result<Vec<i64>, Error> {
+    input
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|s| !s.is_empty())
+        .enumerate()
+        .map(|(i, s)| {
+            s.parse().map_err(|_| Error::InvalidInput {
+                token: s.to_owned(),
+                position: i,
+            })
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::{error::Error::InvalidInput, parser::parse};
+    use crate::{
+        parser::{parse, parse_values},
+        runtime::Error::InvalidInput,
+    };
 
     #[test]
     fn valid() {
@@ -42,4 +60,10 @@ mod tests {
         };
         assert_eq!(expected, parse(input).unwrap_err());
     }
+
+    #[test]
+    fn values() {
+        let input = "1, 2\n-3 4,,5\n";
+        assert_eq!(vec![1, 2, -3, 4, 5], parse_values(input).unwrap());
+    }
 }
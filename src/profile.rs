@@ -0,0 +1,217 @@
+use crate::{disasm, error::Error, interpreter::Status, program::Program, runtime::Memory};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+/// A call into a function, as guessed from the instructions around a taken jump
+struct Frame {
+    entry: usize,
+    return_address: usize,
+}
+
+/// Execution counts gathered while running a program
+#[derive(Debug, Default)]
+pub struct Profile {
+    pub output: Vec<i64>,
+    /// Whether the program halted rather than running out of inputs
+    pub completed: bool,
+    /// Final state of the memory
+    pub memory: Memory,
+    pub total: u64,
+    /// Executions of each instruction, by address
+    pub addresses: BTreeMap<usize, u64>,
+    /// Executions of each opcode, without parameter modes
+    pub opcodes: BTreeMap<i64, u64>,
+    /// Backward jumps taken to each target
+    pub loops: BTreeMap<usize, u64>,
+    /// Instructions executed under each call stack, as function entry points
+    pub stacks: HashMap<Vec<usize>, u64>,
+}
+
+/// Entries sorted by descending count, then by key
+fn hottest<K: Copy + Ord>(counts: &BTreeMap<K, u64>, n: usize) -> Vec<(K, u64)> {
+    let mut counts: Vec<(K, u64)> = counts.iter().map(|(k, c)| (*k, *c)).collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    counts.truncate(n);
+    counts
+}
+
+impl Profile {
+    pub fn hottest_addresses(&self, n: usize) -> Vec<(usize, u64)> {
+        hottest(&self.addresses, n)
+    }
+
+    pub fn hottest_loops(&self, n: usize) -> Vec<(usize, u64)> {
+        hottest(&self.loops, n)
+    }
+
+    /// Call stacks in the folded format understood by flamegraph tools
+    ///
+    /// Frames are named after the address of the function they enter, under a `main` root.
+    pub fn folded(&self) -> String {
+        let mut stacks: Vec<String> = self
+            .stacks
+            .iter()
+            .map(|(stack, count)| {
+                let mut frames = vec!["main".to_owned()];
+                frames.extend(stack.iter().map(|entry| format!("fn_{}", entry)));
+                format!("{} {}\n", frames.join(";"), count)
+            })
+            .collect();
+        stacks.sort();
+        stacks.concat()
+    }
+
+    /// Human-readable summary showing the `n` hottest addresses and loops
+    pub fn report(&self, n: usize) -> String {
+        let percent = |count: u64| count as f64 * 100.0 / self.total.max(1) as f64;
+        let mut result = format!("Total instructions: {}\n", self.total);
+        if !self.completed {
+            result.push_str("Stopped waiting for input\n");
+        }
+
+        result.push_str("\nOpcodes:\n");
+        for (opcode, count) in hottest(&self.opcodes, self.opcodes.len()) {
+            let name = disasm::mnemonic(opcode).unwrap_or("?");
+            result.push_str(&format!(
+                "{:>5} {:>12} {:>6.2}%\n",
+                name,
+                count,
+                percent(count)
+            ));
+        }
+
+        result.push_str("\nHottest addresses:\n");
+        for (address, count) in self.hottest_addresses(n) {
+            let (text, _) = disasm::instruction(&self.memory, address);
+            result.push_str(&format!(
+                "{:>5}: {:>12} {:>6.2}%  {}\n",
+                address,
+                count,
+                percent(count),
+                text
+            ));
+        }
+
+        result.push_str("\nHottest loops:\n");
+        for (target, count) in self.hottest_loops(n) {
+            result.push_str(&format!("{:>5}: {:>12} iterations\n", target, count));
+        }
+        result
+    }
+}
+
+/// Runs a program with buffered inputs until it halts or runs out of inputs, counting every
+/// executed instruction
+///
+/// Calls are guessed from taken jumps following a constant write of the address right after the
+/// jump, and returns from jumps back to such an address.
+pub fn profile(program: &Program, input: Vec<i64>) -> Result<Profile, Error> {
+    let mut machine = program.machine();
+    let mut input = VecDeque::from(input);
+    let mut profile = Profile::default();
+    let mut frames: Vec<Frame> = Vec::new();
+    let mut stack: Vec<usize> = Vec::new();
+    // Instructions executed since the stack last changed, counted together to avoid looking it up
+    // for every instruction
+    let mut under_stack: u64 = 0;
+    let mut written: Option<i64> = None;
+
+    loop {
+        let ip = machine.ip();
+        let word = machine.memory().read(ip);
+        let status = match machine.step()? {
+            Status::NeedsInput => match input.pop_front() {
+                Some(i) => {
+                    machine.push_input(i);
+                    continue;
+                }
+                None => break,
+            },
+            status => status,
+        };
+
+        profile.total += 1;
+        *profile.addresses.entry(ip).or_default() += 1;
+        *profile.opcodes.entry(word % 100).or_default() += 1;
+        under_stack += 1;
+
+        match status {
+            Status::Output(o) => profile.output.push(o),
+            Status::Halted => {
+                profile.completed = true;
+                break;
+            }
+            _ => (),
+        }
+
+        let next = machine.ip();
+        let jumped = (word % 100 == 5 || word % 100 == 6) && next != ip + 3;
+        if jumped {
+            if next <= ip {
+                *profile.loops.entry(next).or_default() += 1;
+            }
+            let depth = frames.len();
+            match frames.iter().rposition(|f| f.return_address == next) {
+                Some(depth) => frames.truncate(depth),
+                None if written == Some(ip as i64 + 3) => frames.push(Frame {
+                    entry: next,
+                    return_address: ip + 3,
+                }),
+                None => (),
+            }
+            // Frames are only ever pushed or popped, so the stack changes with its depth
+            if frames.len() != depth {
+                *profile.stacks.entry(stack).or_default() += under_stack;
+                under_stack = 0;
+                stack = frames.iter().map(|f| f.entry).collect();
+            }
+        }
+        // Only writes of constants, like `add #ret, #0, rb[1]`, push return addresses
+        let constant = (word % 100 == 1 || word % 100 == 2) && word / 100 % 100 == 11;
+        written = machine
+            .last_write()
+            .filter(|_| constant)
+            .map(|address| machine.memory().read(address));
+    }
+
+    if under_stack > 0 {
+        *profile.stacks.entry(stack).or_default() += under_stack;
+    }
+    profile.memory = machine.into_memory();
+    Ok(profile)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{assembler::assemble, profile::profile, program::Program};
+
+    #[test]
+    fn counts() {
+        // 0: in [12]; 2: out [12]; 4: add [12], #-1, [12]; 8: jnz [12], #2; 11: hlt
+        let program = Program::new(vec![3, 12, 4, 12, 1001, 12, -1, 12, 1005, 12, 2, 99, 0]);
+        let profile = profile(&program, vec![3]).unwrap();
+
+        assert!(profile.completed);
+        assert_eq!(vec![3, 2, 1], profile.output);
+        assert_eq!(1 + 3 * 3 + 1, profile.total);
+        assert_eq!(Some(&3), profile.addresses.get(&8));
+        assert_eq!(Some(&3), profile.opcodes.get(&1));
+        assert_eq!(vec![(2, 2)], profile.hottest_loops(5));
+    }
+
+    #[test]
+    fn folded_stacks() {
+        let source = "
+                    arb #stack
+                    add #after, #0, rb[0]
+                    jz #0, #double
+            after:  hlt
+            double: add [value], [value], [value]
+                    jz #0, rb[0]
+            value:  .data 1
+            stack:  .data 0
+        ";
+        let program = Program::new(assemble(source).unwrap());
+        let profile = profile(&program, vec![]).unwrap();
+        assert_eq!("main 4\nmain;fn_10 2\n", profile.folded());
+    }
+}
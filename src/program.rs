@@ -0,0 +1,73 @@
+use crate::{
+    error::Error,
+    interpreter::{EvalResults, Machine},
+    parser,
+};
+use std::str::FromStr;
+
+/// A parsed Intcode program
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    code: Vec<i64>,
+}
+
+impl Program {
+    pub fn new(code: Vec<i64>) -> Self {
+        Self { code }
+    }
+
+    pub fn parse(input: &str) -> Result<Self, Error> {
+        Ok(Self::new(parser::parse(input)?))
+    }
+
+    pub fn code(&self) -> &[i64] {
+        &self.code
+    }
+
+    pub fn into_code(self) -> Vec<i64> {
+        self.code
+    }
+
+    /// Creates a fresh machine loaded with this program
+    pub fn machine(&self) -> Machine {
+        Machine::new(self.code.clone())
+    }
+
+    /// Runs this program with buffered inputs until it halts or runs out of inputs
+    pub fn eval(&self, input: Vec<i64>) -> Result<EvalResults, Error> {
+        self.machine().eval(input)
+    }
+}
+
+impl FromStr for Program {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl From<Vec<i64>> for Program {
+    fn from(code: Vec<i64>) -> Self {
+        Self::new(code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::program::Program;
+
+    #[test]
+    fn parse() {
+        let program: Program = "1,0,0,0,99".parse().unwrap();
+        assert_eq!(&[1, 0, 0, 0, 99], program.code());
+    }
+
+    #[test]
+    fn eval() {
+        let program = Program::new(vec![3, 0, 4, 0, 99]);
+        let result = program.eval(vec![42]).unwrap();
+        assert_eq!(vec![42], result.output);
+        assert!(result.completed);
+    }
+}
@@ -0,0 +1,702 @@
+//! Core of the Intcode virtual machine
+//!
+//! This module is self-contained and only depends on `core` and `alloc`, so that it can be
+//! embedded whole into the programs emitted by the transpiler or used from `#![no_std]` crates.
+
+extern crate alloc;
+
+use alloc::{
+    collections::{BTreeMap, VecDeque},
+    string::String,
+    vec::Vec,
+};
+use core::{
+    convert::TryInto,
+    fmt::{self, Display, Formatter},
+};
+
+#[derive(Debug)]
+#[cfg_attr(test, derive(PartialEq))]
+pub enum Error {
+    InvalidInput {
+        token: String,
+        position: usize,
+    },
+    InvalidOpcode {
+        opcode: i64,
+        position: usize,
+    },
+    MissingParameter {
+        parameter: u8,
+        opcode: i64,
+        position: usize,
+    },
+    NegativePositionalParameter {
+        value: i64,
+        parameter: u8,
+        opcode: i64,
+        position: usize,
+    },
+    InvalidParameterMode {
+        mode: i64,
+        parameter: u8,
+        opcode: i64,
+        position: usize,
+    },
+    AddressOutOfBounds {
+        address: i64,
+        opcode: i64,
+        position: usize,
+    },
+    ArithmeticOverflow {
+        opcode: i64,
+        position: usize,
+    },
+    JumpOutOfBounds {
+        target: i64,
+        opcode: i64,
+        position: usize,
+    },
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidInput { token, position } => {
+                write!(f, "Invalid token \"{}\" at position {}", token, position)
+            }
+            Error::InvalidOpcode { opcode, position } => {
+                write!(f, "Invalid opcode \"{}\" at position {}", opcode, position)
+            }
+            Error::MissingParameter {
+                parameter,
+                opcode,
+                position,
+            } => write!(
+                f,
+                "Missing parameter {} for opcode \"{}\" at position {}",
+                parameter, opcode, position
+            ),
+            Error::NegativePositionalParameter {
+                value,
+                parameter,
+                opcode,
+                position,
+            } => write!(
+                f,
+                "Negative value {} for positional parameter {} for opcode \"{}\" at position {}",
+                value, parameter, opcode, position
+            ),
+            Error::InvalidParameterMode {
+                mode,
+                parameter,
+                opcode,
+                position,
+            } => write!(
+                f,
+                "Invalid parameter mode \"{}\" for parameter {} of opcode \"{}\" at position {}",
+                mode, parameter, opcode, position
+            ),
+            Error::AddressOutOfBounds {
+                address,
+                opcode,
+                position,
+            } => write!(
+                f,
+                "Address {} out of bounds for opcode \"{}\" at position {}",
+                address, opcode, position
+            ),
+            Error::ArithmeticOverflow { opcode, position } => write!(
+                f,
+                "Arithmetic overflow for opcode \"{}\" at position {}",
+                opcode, position
+            ),
+            Error::JumpOutOfBounds {
+                target,
+                opcode,
+                position,
+            } => write!(
+                f,
+                "Jump target {} out of bounds for opcode \"{}\" at position {}",
+                target, opcode, position
+            ),
+        }
+    }
+}
+
+const DENSE_LIMIT: usize = 1 << 20;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Memory {
+    dense: Vec<i64>,
+    sparse: BTreeMap<usize, i64>,
+}
+
+impl Memory {
+    pub fn get(&self, address: usize) -> Option<i64> {
+        match self.dense.get(address) {
+            Some(v) => Some(*v),
+            None => self.sparse.get(&address).copied(),
+        }
+    }
+
+    pub fn read(&self, address: usize) -> i64 {
+        self.get(address).unwrap_or(0)
+    }
+
+    pub fn write(&mut self, address: usize, value: i64) {
+        if address < self.dense.len() {
+            self.dense[address] = value;
+        } else if address < DENSE_LIMIT {
+            self.dense.resize(address + 1, 0);
+            self.dense[address] = value;
+        } else {
+            self.sparse.insert(address, value);
+        }
+    }
+
+    pub fn dense(&self) -> &[i64] {
+        &self.dense
+    }
+
+    pub fn sparse(&self) -> Vec<(usize, i64)> {
+        self.sparse.iter().map(|(a, v)| (*a, *v)).collect()
+    }
+}
+
+impl From<Vec<i64>> for Memory {
+    fn from(dense: Vec<i64>) -> Self {
+        Self {
+            dense,
+            sparse: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub(crate) enum Parameter {
+    Position(usize),
+    Immediate(i64),
+    Relative(isize),
+}
+
+impl Parameter {
+    fn from_code(
+        code: &Memory,
+        i: &mut usize,
+        mode: i64,
+        n: u8,
+        opcode: i64,
+    ) -> Result<Self, Error> {
+        match code.get(*i) {
+            None => Err(Error::MissingParameter {
+                parameter: n,
+                opcode,
+                position: *i,
+            }),
+            Some(p) => {
+                *i += 1;
+                match mode {
+                    0 => Ok(Parameter::Position(p.try_into().map_err(|_| {
+                        Error::NegativePositionalParameter {
+                            value: p,
+                            parameter: n,
+                            opcode,
+                            position: *i,
+                        }
+                    })?)),
+                    1 => Ok(Parameter::Immediate(p)),
+                    2 => Ok(Parameter::Relative(p as isize)),
+                    _ => Err(Error::InvalidParameterMode {
+                        mode,
+                        parameter: n,
+                        opcode,
+                        position: *i,
+                    }),
+                }
+            }
+        }
+    }
+
+    fn positional_from_code(
+        code: &Memory,
+        i: &mut usize,
+        mode: i64,
+        n: u8,
+        opcode: i64,
+    ) -> Result<Self, Error> {
+        let p = Self::from_code(code, i, mode, n, opcode)?;
+        match p {
+            Parameter::Position(_) | Parameter::Relative(_) => Ok(p),
+            Parameter::Immediate(_) => Err(Error::InvalidParameterMode {
+                mode,
+                parameter: n,
+                opcode,
+                position: *i,
+            }),
+        }
+    }
+
+    fn value(&self, code: &Memory, rb: isize) -> Result<i64, Fault> {
+        match self {
+            Parameter::Immediate(v) => Ok(*v),
+            _ => Ok(code.read(self.index(rb)?)),
+        }
+    }
+
+    fn index(&self, rb: isize) -> Result<usize, Fault> {
+        match self {
+            Parameter::Position(p) => Ok(*p),
+            Parameter::Immediate(_) => unreachable!("immediate parameters have no address"),
+            Parameter::Relative(o) => {
+                let address = rb.checked_add(*o).ok_or(Fault::Overflow)?;
+                address
+                    .try_into()
+                    .map_err(|_| Fault::Address(address as i64))
+            }
+        }
+    }
+
+    fn arithmetic(
+        code: &Memory,
+        i: &mut usize,
+        opcode: i64,
+        modes_and_opcode: i64,
+    ) -> Result<(Self, Self, Self), Error> {
+        let modes = (
+            modes_and_opcode / 100 % 10,
+            modes_and_opcode / 1000 % 10,
+            modes_and_opcode / 10000 % 10,
+        );
+
+        let n1 = Self::from_code(code, i, modes.0, 0, opcode)?;
+        let n2 = Self::from_code(code, i, modes.1, 1, opcode)?;
+        let to = Self::positional_from_code(code, i, modes.2, 2, opcode)?;
+
+        Ok((n1, n2, to))
+    }
+
+    fn jump(
+        code: &Memory,
+        i: &mut usize,
+        opcode: i64,
+        modes_and_opcode: i64,
+    ) -> Result<(Self, Self), Error> {
+        let modes = (modes_and_opcode / 100 % 10, modes_and_opcode / 1000 % 10);
+
+        let test = Self::from_code(code, i, modes.0, 0, opcode)?;
+        let goto = Self::from_code(code, i, modes.1, 1, opcode)?;
+
+        Ok((test, goto))
+    }
+}
+
+pub(crate) enum Instruction {
+    Add {
+        n1: Parameter,
+        n2: Parameter,
+        to: Parameter,
+    },
+    Multiply {
+        n1: Parameter,
+        n2: Parameter,
+        to: Parameter,
+    },
+    Input {
+        to: Parameter,
+    },
+    Output {
+        from: Parameter,
+    },
+    JumpIfTrue {
+        test: Parameter,
+        goto: Parameter,
+    },
+    JumpIfFalse {
+        test: Parameter,
+        goto: Parameter,
+    },
+    LessThan {
+        n1: Parameter,
+        n2: Parameter,
+        to: Parameter,
+    },
+    Equals {
+        n1: Parameter,
+        n2: Parameter,
+        to: Parameter,
+    },
+    AdjustRelativeBase {
+        by: Parameter,
+    },
+    Halt,
+    End,
+}
+
+impl Instruction {
+    pub(crate) fn from_code(code: &Memory, i: &mut usize) -> Result<Self, Error> {
+        let modes_and_opcode = match code.get(*i) {
+            None => return Ok(Instruction::End),
+            Some(n) => {
+                *i += 1;
+                n
+            }
+        };
+
+        let opcode = modes_and_opcode % 100;
+        match opcode {
+            1 => {
+                let (n1, n2, to) = Parameter::arithmetic(code, i, opcode, modes_and_opcode)?;
+                Ok(Instruction::Add { n1, n2, to })
+            }
+            2 => {
+                let (n1, n2, to) = Parameter::arithmetic(code, i, opcode, modes_and_opcode)?;
+                Ok(Instruction::Multiply { n1, n2, to })
+            }
+            3 => {
+                let mode = modes_and_opcode / 100 % 10;
+                let to = Parameter::positional_from_code(code, i, mode, 0, opcode)?;
+                Ok(Instruction::Input { to })
+            }
+            4 => {
+                let mode = modes_and_opcode / 100 % 10;
+                let from = Parameter::from_code(code, i, mode, 0, opcode)?;
+                Ok(Instruction::Output { from })
+            }
+            5 => {
+                let (test, goto) = Parameter::jump(code, i, opcode, modes_and_opcode)?;
+                Ok(Instruction::JumpIfTrue { test, goto })
+            }
+            6 => {
+                let (test, goto) = Parameter::jump(code, i, opcode, modes_and_opcode)?;
+                Ok(Instruction::JumpIfFalse { test, goto })
+            }
+            7 => {
+                let (n1, n2, to) = Parameter::arithmetic(code, i, opcode, modes_and_opcode)?;
+                Ok(Instruction::LessThan { n1, n2, to })
+            }
+            8 => {
+                let (n1, n2, to) = Parameter::arithmetic(code, i, opcode, modes_and_opcode)?;
+                Ok(Instruction::Equals { n1, n2, to })
+            }
+            9 => {
+                let mode = modes_and_opcode / 100 % 10;
+                let by = Parameter::from_code(code, i, mode, 0, opcode)?;
+                Ok(Instruction::AdjustRelativeBase { by })
+            }
+            99 => Ok(Instruction::Halt),
+            _ => Err(Error::InvalidOpcode {
+                opcode,
+                position: *i,
+            }),
+        }
+    }
+}
+
+/// A runtime failure, turned into an `Error` once the failing instruction is known
+enum Fault {
+    Address(i64),
+    Overflow,
+    Jump(i64),
+}
+
+impl Fault {
+    fn at(self, opcode: i64, position: usize) -> Error {
+        match self {
+            Fault::Address(address) => Error::AddressOutOfBounds {
+                address,
+                opcode,
+                position,
+            },
+            Fault::Overflow => Error::ArithmeticOverflow { opcode, position },
+            Fault::Jump(target) => Error::JumpOutOfBounds {
+                target,
+                opcode,
+                position,
+            },
+        }
+    }
+}
+
+fn add(
+    code: &mut Memory,
+    rb: isize,
+    n1: Parameter,
+    n2: Parameter,
+    to: Parameter,
+) -> Result<(), Fault> {
+    let n1 = n1.value(code, rb)?;
+    let n2 = n2.value(code, rb)?;
+    let to = to.index(rb)?;
+    code.write(to, n1.checked_add(n2).ok_or(Fault::Overflow)?);
+    Ok(())
+}
+
+fn multiply(
+    code: &mut Memory,
+    rb: isize,
+    n1: Parameter,
+    n2: Parameter,
+    to: Parameter,
+) -> Result<(), Fault> {
+    let n1 = n1.value(code, rb)?;
+    let n2 = n2.value(code, rb)?;
+    let to = to.index(rb)?;
+    code.write(to, n1.checked_mul(n2).ok_or(Fault::Overflow)?);
+    Ok(())
+}
+
+/// Jumps to `goto`, which must be in memory or right past its dense part, where the program
+/// halts as if it ran off its end
+fn jump(code: &Memory, i: &mut usize, goto: i64) -> Result<(), Fault> {
+    match goto.try_into() {
+        Ok(goto) if code.get(goto).is_some() || goto == code.dense().len() => {
+            *i = goto;
+            Ok(())
+        }
+        _ => Err(Fault::Jump(goto)),
+    }
+}
+
+fn jump_if_true(
+    code: &mut Memory,
+    i: &mut usize,
+    rb: isize,
+    test: Parameter,
+    goto: Parameter,
+) -> Result<(), Fault> {
+    let test = test.value(code, rb)?;
+    if test != 0 {
+        let goto = goto.value(code, rb)?;
+        jump(code, i, goto)?;
+    }
+    Ok(())
+}
+
+fn jump_if_false(
+    code: &mut Memory,
+    i: &mut usize,
+    rb: isize,
+    test: Parameter,
+    goto: Parameter,
+) -> Result<(), Fault> {
+    let test = test.value(code, rb)?;
+    if test == 0 {
+        let goto = goto.value(code, rb)?;
+        jump(code, i, goto)?;
+    }
+    Ok(())
+}
+
+fn less_than(
+    code: &mut Memory,
+    rb: isize,
+    n1: Parameter,
+    n2: Parameter,
+    to: Parameter,
+) -> Result<(), Fault> {
+    let n1 = n1.value(code, rb)?;
+    let n2 = n2.value(code, rb)?;
+    let to = to.index(rb)?;
+    if n1 < n2 {
+        code.write(to, 1);
+    } else {
+        code.write(to, 0);
+    }
+    Ok(())
+}
+
+fn equals(
+    code: &mut Memory,
+    rb: isize,
+    n1: Parameter,
+    n2: Parameter,
+    to: Parameter,
+) -> Result<(), Fault> {
+    let n1 = n1.value(code, rb)?;
+    let n2 = n2.value(code, rb)?;
+    let to = to.index(rb)?;
+    if n1 == n2 {
+        code.write(to, 1);
+    } else {
+        code.write(to, 0);
+    }
+    Ok(())
+}
+
+fn adjust_relative_base(code: &Memory, rb: &mut isize, by: Parameter) -> Result<(), Fault> {
+    let by = by.value(code, *rb)?;
+    *rb = (by as isize).checked_add(*rb).ok_or(Fault::Overflow)?;
+    Ok(())
+}
+
+/// What a machine is waiting on after executing instructions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// The last instruction completed without producing anything
+    Running,
+    /// The machine is blocked on an input instruction and its input queue is empty
+    NeedsInput,
+    /// The machine produced an output value
+    Output(i64),
+    /// The machine reached a halt instruction or the end of its memory
+    Halted,
+}
+
+/// An Intcode virtual machine owning its memory, instruction pointer and relative base
+#[derive(Debug, Clone)]
+pub struct Machine {
+    code: Memory,
+    ip: usize,
+    relative_base: isize,
+    input: VecDeque<i64>,
+    last_write: Option<usize>,
+}
+
+impl Machine {
+    pub fn new<M: Into<Memory>>(code: M) -> Self {
+        Self::with_state(code, 0, 0)
+    }
+
+    /// Creates a machine resuming at the given instruction pointer and relative base
+    pub fn with_state<M: Into<Memory>>(code: M, ip: usize, relative_base: isize) -> Self {
+        Self {
+            code: code.into(),
+            ip,
+            relative_base,
+            input: VecDeque::new(),
+            last_write: None,
+        }
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.code
+    }
+
+    pub fn memory_mut(&mut self) -> &mut Memory {
+        &mut self.code
+    }
+
+    pub fn into_memory(self) -> Memory {
+        self.code
+    }
+
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+
+    pub fn relative_base(&self) -> isize {
+        self.relative_base
+    }
+
+    pub fn set_ip(&mut self, ip: usize) {
+        self.ip = ip;
+    }
+
+    pub fn set_relative_base(&mut self, relative_base: isize) {
+        self.relative_base = relative_base;
+    }
+
+    /// Address written to by the last executed instruction, if any
+    pub fn last_write(&self) -> Option<usize> {
+        self.last_write
+    }
+
+    /// Queues a value to be consumed by the next input instruction
+    pub fn push_input(&mut self, value: i64) {
+        self.input.push_back(value);
+    }
+
+    pub fn pending_input(&self) -> &VecDeque<i64> {
+        &self.input
+    }
+
+    /// Executes a single instruction
+    ///
+    /// When the input queue is empty, input instructions are not executed and the instruction
+    /// pointer is left on them, so stepping again after pushing an input resumes execution.
+    pub fn step(&mut self) -> Result<Status, Error> {
+        let start = self.ip;
+        let code = &mut self.code;
+        let i = &mut self.ip;
+        let rb = &mut self.relative_base;
+
+        self.last_write = None;
+        let opcode = code.read(start) % 100;
+        let instruction = Instruction::from_code(code, i)?;
+        let written = match instruction {
+            Instruction::Add { to, .. }
+            | Instruction::Multiply { to, .. }
+            | Instruction::Input { to }
+            | Instruction::LessThan { to, .. }
+            | Instruction::Equals { to, .. } => to.index(*rb).ok(),
+            _ => None,
+        };
+        let result = match instruction {
+            Instruction::Add { n1, n2, to } => add(code, *rb, n1, n2, to),
+            Instruction::Multiply { n1, n2, to } => multiply(code, *rb, n1, n2, to),
+            Instruction::Input { to } => match self.input.pop_front() {
+                None => {
+                    *i = start;
+                    return Ok(Status::NeedsInput);
+                }
+                Some(input) => to.index(*rb).map(|to| code.write(to, input)),
+            },
+            Instruction::Output { from } => match from.value(code, *rb) {
+                Ok(from) => return Ok(Status::Output(from)),
+                Err(f) => Err(f),
+            },
+            Instruction::JumpIfTrue { test, goto } => jump_if_true(code, i, *rb, test, goto),
+            Instruction::JumpIfFalse { test, goto } => jump_if_false(code, i, *rb, test, goto),
+            Instruction::LessThan { n1, n2, to } => less_than(code, *rb, n1, n2, to),
+            Instruction::Equals { n1, n2, to } => equals(code, *rb, n1, n2, to),
+            Instruction::AdjustRelativeBase { by } => adjust_relative_base(code, rb, by),
+            Instruction::Halt | Instruction::End => {
+                *i = start;
+                return Ok(Status::Halted);
+            }
+        };
+        result.map_err(|f| f.at(opcode, start))?;
+        self.last_write = written;
+
+        Ok(Status::Running)
+    }
+
+    /// Executes instructions until the machine needs an input, produces an output or halts
+    pub fn run_until(&mut self) -> Result<Status, Error> {
+        loop {
+            match self.step()? {
+                Status::Running => continue,
+                status => return Ok(status),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::runtime::Memory;
+
+    #[test]
+    fn read_past_end() {
+        let memory = Memory::from(vec![1, 2, 3]);
+        assert_eq!(None, memory.get(10));
+        assert_eq!(0, memory.read(10));
+    }
+
+    #[test]
+    fn write_extends() {
+        let mut memory = Memory::from(vec![1, 2, 3]);
+        memory.write(5, 6);
+        assert_eq!(&[1, 2, 3, 0, 0, 6], memory.dense());
+    }
+
+    #[test]
+    fn write_sparse() {
+        let mut memory = Memory::from(vec![1, 2, 3]);
+        memory.write(1 << 40, 7);
+        assert_eq!(&[1, 2, 3], memory.dense());
+        assert_eq!(vec![(1 << 40, 7)], memory.sparse());
+        assert_eq!(7, memory.read(1 << 40));
+    }
+}
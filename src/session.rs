@@ -0,0 +1,326 @@
+use crate::{
+    error::Error,
+    interpreter::{Machine, Status},
+    io::{AsciiOutput, IntcodeOutput},
+};
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, BufRead},
+};
+
+static HELP: &str = "\
+!history          list the commands sent so far
+!N                send command number N from the history again
+!save NAME        snapshot the machine under NAME
+!load NAME        restore the snapshot NAME
+!snapshots        list snapshots
+!replay FILE      run every line of FILE as if it was typed, skipping `#` comments
+!bruteforce DIR   try every combination of held items until moving DIR gets through
+!help             show this message
+!quit             exit the shell
+Any other line is sent to the program.";
+
+/// A snapshot of a session
+#[derive(Clone)]
+struct Snapshot {
+    machine: Machine,
+    /// Output shown right before the snapshot was taken
+    output: String,
+}
+
+/// An interactive session with an ASCII program, such as the day 25 text adventure
+pub struct Session {
+    machine: Machine,
+    history: Vec<String>,
+    snapshots: BTreeMap<String, Snapshot>,
+    /// Output of the last command
+    output: String,
+}
+
+/// Most held items `brute_force` tries every combination of
+pub const MAX_ITEMS: usize = 16;
+
+/// Order in which to toggle items so that every combination is visited, one change at a time
+fn toggles(items: usize) -> impl Iterator<Item = usize> {
+    (1..1u64 << items).map(|i| i.trailing_zeros() as usize)
+}
+
+/// Items listed in the output of an `inv` command
+fn inventory(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .skip_while(|l| !l.starts_with("Items in your inventory"))
+        .filter_map(|l| l.strip_prefix("- "))
+        .map(|l| l.to_owned())
+        .collect()
+}
+
+impl Session {
+    pub fn new(machine: Machine) -> Self {
+        Self {
+            machine,
+            history: Vec::new(),
+            snapshots: BTreeMap::new(),
+            output: String::new(),
+        }
+    }
+
+    pub fn history(&self) -> &[String] {
+        &self.history
+    }
+
+    pub fn output(&self) -> &str {
+        &self.output
+    }
+
+    /// Runs the program until it needs an input, returning what it printed
+    pub fn start(&mut self) -> Result<String, Error> {
+        self.resume()
+    }
+
+    fn resume(&mut self) -> Result<String, Error> {
+        let mut output = AsciiOutput(Vec::new());
+        while let Status::Output(o) = self.machine.run_until()? {
+            output.write(o);
+        }
+        self.output = String::from_utf8_lossy(&output.0).into_owned();
+        Ok(self.output.clone())
+    }
+
+    /// Sends a line of text to the program, returning what it printed in response
+    pub fn send(&mut self, command: &str) -> Result<String, Error> {
+        self.history.push(command.to_owned());
+        for c in command.chars() {
+            self.machine.push_input(c as i64);
+        }
+        self.machine.push_input(10);
+        self.resume()
+    }
+
+    pub fn save(&mut self, name: &str) {
+        let snapshot = Snapshot {
+            machine: self.machine.clone(),
+            output: self.output.clone(),
+        };
+        self.snapshots.insert(name.to_owned(), snapshot);
+    }
+
+    /// Restores a snapshot, returning the output shown when it was taken
+    pub fn load(&mut self, name: &str) -> Option<String> {
+        let snapshot = self.snapshots.get(name)?.clone();
+        self.machine = snapshot.machine;
+        self.output = snapshot.output;
+        Some(self.output.clone())
+    }
+
+    pub fn snapshots(&self) -> Vec<&str> {
+        self.snapshots.keys().map(|k| k.as_str()).collect()
+    }
+
+    /// Tries every combination of the held items until moving in `direction` doesn't get the
+    /// droid ejected back to the checkpoint, returning the output of the successful move
+    pub fn brute_force(&mut self, direction: &str) -> Result<Option<String>, Error> {
+        let items = inventory(&self.send("inv")?);
+        if items.len() > MAX_ITEMS {
+            return Err(Error::TooManyItems {
+                items: items.len(),
+                max: MAX_ITEMS,
+            });
+        }
+        let mut held = vec![true; items.len()];
+
+        let output = self.send(direction)?;
+        if !output.contains("Alert!") {
+            return Ok(Some(output));
+        }
+        for toggle in toggles(items.len()) {
+            let verb = if held[toggle] { "drop" } else { "take" };
+            self.send(&format!("{} {}", verb, items[toggle]))?;
+            held[toggle] = !held[toggle];
+
+            let output = self.send(direction)?;
+            if !output.contains("Alert!") {
+                return Ok(Some(output));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Runs a line typed in the shell, returning the text to show, or `None` to quit
+    pub fn execute(&mut self, line: &str) -> Result<Option<String>, Error> {
+        let line = line.trim();
+        let command = match line.strip_prefix('!') {
+            None => return self.send(line).map(Some),
+            Some(command) => command,
+        };
+
+        let mut words = command.splitn(2, ' ');
+        let text = match (words.next().unwrap_or(""), words.next().map(str::trim)) {
+            ("history", None) => self
+                .history
+                .iter()
+                .enumerate()
+                .map(|(n, c)| format!("{:>4}  {}\n", n + 1, c))
+                .collect(),
+            ("save", Some(name)) => {
+                self.save(name);
+                format!("Saved {}\n", name)
+            }
+            ("load", Some(name)) => match self.load(name) {
+                Some(output) => output,
+                None => format!("No snapshot named {}\n", name),
+            },
+            ("snapshots", None) => self
+                .snapshots()
+                .iter()
+                .map(|s| format!("{}\n", s))
+                .collect(),
+            ("replay", Some(file)) => match fs::read_to_string(file) {
+                Ok(script) => return self.replay(&script),
+                Err(e) => format!("{}\n", e),
+            },
+            ("bruteforce", Some(direction)) => match self.brute_force(direction) {
+                Ok(Some(output)) => output,
+                Ok(None) => "No combination of items got through\n".to_owned(),
+                Err(e @ Error::TooManyItems { .. }) => format!("{}\n", e),
+                Err(e) => return Err(e),
+            },
+            ("help", None) => format!("{}\n", HELP),
+            ("quit", None) => return Ok(None),
+            (n, None) => match n.parse::<usize>().ok().and_then(|n| n.checked_sub(1)) {
+                Some(n) if n < self.history.len() => {
+                    let command = self.history[n].clone();
+                    return self.send(&command).map(Some);
+                }
+                _ => "Invalid command, type \"!help\" for a list of commands\n".to_owned(),
+            },
+            _ => "Invalid command, type \"!help\" for a list of commands\n".to_owned(),
+        };
+        Ok(Some(text))
+    }
+
+    /// Runs every line of a script as if it was typed, skipping blank lines and `#` comments
+    pub fn replay(&mut self, script: &str) -> Result<Option<String>, Error> {
+        let mut text = String::new();
+        let lines = script
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'));
+        for line in lines {
+            match self.execute(line)? {
+                Some(output) => text.push_str(&output),
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(text))
+    }
+
+    /// Reads lines from stdin until `!quit` or the end of input
+    pub fn run(&mut self) -> Result<(), Error> {
+        let stdin = io::stdin();
+        for line in stdin.lock().lines() {
+            let line = line.expect("Can't read from stdin");
+            match self.execute(&line)? {
+                Some(text) => print!("{}", text),
+                None => return Ok(()),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        assembler::assemble,
+        interpreter::Machine,
+        session::{inventory, toggles, Session, MAX_ITEMS},
+    };
+
+    // Prints how many lines it has read so far, as a digit
+    fn counter() -> Session {
+        let source = "
+            loop: in [c]
+                  eq [c], #10, [t]
+                  jz [t], #loop
+                  add [n], #1, [n]
+                  add [n], #48, [d]
+                  out [d]
+                  out #10
+                  jz #0, #loop
+            c:    .data 0
+            t:    .data 0
+            n:    .data 0
+            d:    .data 0
+        ";
+        Session::new(Machine::new(assemble(source).unwrap()))
+    }
+
+    #[test]
+    fn snapshots() {
+        let mut session = counter();
+        assert_eq!("", session.start().unwrap());
+        assert_eq!("1\n", session.send("north").unwrap());
+        session.save("start");
+        assert_eq!("2\n", session.send("south").unwrap());
+
+        assert_eq!(Some("1\n".to_owned()), session.load("start"));
+        assert_eq!(Some("2\n".to_owned()), session.execute("!2").unwrap());
+        assert_eq!(vec!["north", "south", "south"], session.history());
+        assert_eq!(None, session.execute("!quit").unwrap());
+    }
+
+    #[test]
+    fn replay() {
+        let mut session = counter();
+        session.start().unwrap();
+        let script = "north\n    # indented comment\n\n  south  \n";
+        assert_eq!(Some("1\n2\n".to_owned()), session.replay(script).unwrap());
+        assert_eq!(vec!["north", "south"], session.history());
+    }
+
+    #[test]
+    fn every_combination() {
+        let mut held = 0u64;
+        let mut seen = vec![held];
+        for toggle in toggles(3) {
+            held ^= 1 << toggle;
+            seen.push(held);
+        }
+        seen.sort_unstable();
+        assert_eq!((0..8).collect::<Vec<u64>>(), seen);
+    }
+
+    #[test]
+    fn too_many_items() {
+        // Lists more items than can be brute-forced after every line
+        let mut text = "Items in your inventory:\n".to_owned();
+        for item in 0..=MAX_ITEMS {
+            text.push_str(&format!("- item {}\n", item));
+        }
+        let mut source = "loop: in [c]\n eq [c], #10, [t]\n jz [t], #loop\n".to_owned();
+        for c in text.bytes() {
+            source.push_str(&format!(" out #{}\n", c));
+        }
+        source.push_str(" jz #0, #loop\nc: .data 0\nt: .data 0\n");
+
+        let mut session = Session::new(Machine::new(assemble(&source).unwrap()));
+        session.start().unwrap();
+        let expected = format!(
+            "Too many items to brute-force: {} above {}\n",
+            MAX_ITEMS + 1,
+            MAX_ITEMS
+        );
+        assert_eq!(
+            Some(expected),
+            session.execute("!bruteforce north").unwrap()
+        );
+    }
+
+    #[test]
+    fn items() {
+        let output = "\nItems in your inventory:\n- mug\n- easter egg\n\nCommand?\n";
+        assert_eq!(vec!["mug", "easter egg"], inventory(output));
+    }
+}
@@ -0,0 +1,179 @@
+use crate::{error::Error, interpreter::Machine, runtime::Memory};
+use std::{
+    fmt::{self, Display, Formatter},
+    str::FromStr,
+};
+
+/// Version written in the header of new snapshots
+pub const VERSION: u32 = 1;
+
+const HEADER: &str = "intcode-snapshot";
+
+/// The full state of a machine, along with the outputs it produced that weren't consumed yet
+///
+/// Snapshots are stored as text: a `intcode-snapshot VERSION` header followed by one
+/// `key: values` line per field, where lists are comma-separated and sparse memory cells are
+/// written `address=value`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub memory: Memory,
+    pub ip: usize,
+    pub relative_base: isize,
+    pub input: Vec<i64>,
+    pub output: Vec<i64>,
+}
+
+fn join<T: ToString>(values: &[T]) -> String {
+    let values: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+    values.join(",")
+}
+
+fn split<T: FromStr>(values: &str) -> Option<Vec<T>> {
+    values
+        .split(',')
+        .filter(|v| !v.is_empty())
+        .map(|v| v.parse().ok())
+        .collect()
+}
+
+impl Display for Snapshot {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let sparse: Vec<String> = self
+            .memory
+            .sparse()
+            .iter()
+            .map(|(a, v)| format!("{}={}", a, v))
+            .collect();
+        writeln!(f, "{} {}", HEADER, VERSION)?;
+        writeln!(f, "ip: {}", self.ip)?;
+        writeln!(f, "relative base: {}", self.relative_base)?;
+        writeln!(f, "input: {}", join(&self.input))?;
+        writeln!(f, "output: {}", join(&self.output))?;
+        writeln!(f, "memory: {}", join(self.memory.dense()))?;
+        writeln!(f, "sparse: {}", sparse.join(","))
+    }
+}
+
+impl FromStr for Snapshot {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut lines = s.lines().enumerate();
+        match lines.next().map(|(_, l)| l.split_once(' ')) {
+            Some(Some((HEADER, version))) if version == VERSION.to_string() => (),
+            Some(Some((HEADER, version))) => {
+                return Err(Error::UnsupportedSnapshotVersion {
+                    version: version.to_owned(),
+                })
+            }
+            _ => return Err(Error::InvalidSnapshot { line: 1 }),
+        }
+
+        let mut snapshot = Snapshot {
+            memory: Memory::default(),
+            ip: 0,
+            relative_base: 0,
+            input: Vec::new(),
+            output: Vec::new(),
+        };
+        let mut sparse = Vec::new();
+        for (n, line) in lines.filter(|(_, l)| !l.trim().is_empty()) {
+            let invalid = || Error::InvalidSnapshot { line: n + 1 };
+            let (key, values) = line.split_once(':').ok_or_else(invalid)?;
+            let values = values.trim();
+            match key {
+                "ip" => snapshot.ip = values.parse().map_err(|_| invalid())?,
+                "relative base" => {
+                    snapshot.relative_base = values.parse().map_err(|_| invalid())?
+                }
+                "input" => snapshot.input = split(values).ok_or_else(invalid)?,
+                "output" => snapshot.output = split(values).ok_or_else(invalid)?,
+                "memory" => snapshot.memory = Memory::from(split(values).ok_or_else(invalid)?),
+                "sparse" => {
+                    sparse = values
+                        .split(',')
+                        .filter(|c| !c.is_empty())
+                        .map(|c| {
+                            let (address, value) = c.split_once('=')?;
+                            Some((address.parse().ok()?, value.parse().ok()?))
+                        })
+                        .collect::<Option<Vec<(usize, i64)>>>()
+                        .ok_or_else(invalid)?
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        for (address, value) in sparse {
+            snapshot.memory.write(address, value);
+        }
+        Ok(snapshot)
+    }
+}
+
+impl Machine {
+    /// Captures the state of the machine, with no pending outputs
+    pub fn save(&self) -> Snapshot {
+        Snapshot {
+            memory: self.memory().clone(),
+            ip: self.ip(),
+            relative_base: self.relative_base(),
+            input: self.pending_input().iter().copied().collect(),
+            output: Vec::new(),
+        }
+    }
+
+    /// Creates a machine in the state captured by a snapshot
+    pub fn load(snapshot: &Snapshot) -> Self {
+        let mut machine =
+            Machine::with_state(snapshot.memory.clone(), snapshot.ip, snapshot.relative_base);
+        for input in &snapshot.input {
+            machine.push_input(*input);
+        }
+        machine
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        error::Error,
+        interpreter::{Machine, Status},
+        snapshot::Snapshot,
+    };
+
+    #[test]
+    fn round_trip() {
+        // in [9]; in [10]; out [9]; hlt
+        let mut machine = Machine::new(vec![3, 9, 3, 10, 4, 9, 99]);
+        machine.push_input(7);
+        assert_eq!(Status::NeedsInput, machine.run_until().unwrap());
+        machine.memory_mut().write(1 << 40, 3);
+        machine.push_input(8);
+
+        let mut snapshot = machine.save();
+        snapshot.output = vec![1, 2];
+        let text = snapshot.to_string();
+        assert!(text.starts_with("intcode-snapshot 1\nip: 2\n"));
+        assert_eq!(snapshot, text.parse().unwrap());
+
+        let mut machine = Machine::load(&text.parse().unwrap());
+        assert_eq!(Status::Output(7), machine.run_until().unwrap());
+        assert_eq!(8, machine.memory().read(10));
+        assert_eq!(3, machine.memory().read(1 << 40));
+    }
+
+    #[test]
+    fn invalid() {
+        let expected = Error::UnsupportedSnapshotVersion {
+            version: "2".to_owned(),
+        };
+        assert_eq!(
+            expected,
+            "intcode-snapshot 2\n".parse::<Snapshot>().unwrap_err()
+        );
+
+        let expected = Error::InvalidSnapshot { line: 3 };
+        let text = "intcode-snapshot 1\nip: 0\nrelative base: x\n";
+        assert_eq!(expected, text.parse::<Snapshot>().unwrap_err());
+    }
+}
@@ -0,0 +1,281 @@
+use crate::{
+    error::Error,
+    interpreter::{Instruction, Machine, Parameter, Status},
+    io::{IntcodeInput, IntcodeOutput},
+    runtime::Memory,
+};
+use std::{convert::TryInto, io::Write, str::FromStr};
+
+/// A memory cell written to by an instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Written {
+    pub address: usize,
+    pub value: i64,
+}
+
+/// Record of a single executed instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trace {
+    pub ip: usize,
+    /// The raw first word of the instruction, including parameter modes
+    pub opcode: i64,
+    /// Disassembled instruction
+    pub instruction: String,
+    /// Raw parameter words
+    pub operands: Vec<i64>,
+    /// Resolved parameters: the value read for inputs, the address for destinations
+    pub values: Vec<i64>,
+    pub write: Option<Written>,
+}
+
+impl Trace {
+    /// Human-readable form, aligned on the disassembled instruction
+    pub fn text(&self) -> String {
+        let mut result = format!(
+            "{:>5}: {:<28} ; {:?}",
+            self.ip, self.instruction, self.values
+        );
+        if let Some(write) = self.write {
+            result.push_str(&format!(" [{}] <- {}", write.address, write.value));
+        }
+        result
+    }
+
+    /// JSON object on a single line
+    pub fn json(&self) -> String {
+        let list = |values: &[i64]| {
+            let values: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+            format!("[{}]", values.join(","))
+        };
+        let mut result = format!(
+            "{{\"ip\":{},\"opcode\":{},\"instruction\":\"{}\",\"operands\":{},\"values\":{}",
+            self.ip,
+            self.opcode,
+            self.instruction,
+            list(&self.operands),
+            list(&self.values)
+        );
+        match self.write {
+            Some(write) => result.push_str(&format!(
+                ",\"write\":{{\"address\":{},\"value\":{}}}}}",
+                write.address, write.value
+            )),
+            None => result.push_str(",\"write\":null}"),
+        }
+        result
+    }
+}
+
+/// Hook called with every instruction executed by `Machine::run_traced`
+pub trait Tracer {
+    fn trace(&mut self, trace: &Trace);
+}
+
+impl<F: FnMut(&Trace)> Tracer for F {
+    fn trace(&mut self, trace: &Trace) {
+        self(trace)
+    }
+}
+
+impl Tracer for Vec<Trace> {
+    fn trace(&mut self, trace: &Trace) {
+        self.push(trace.clone());
+    }
+}
+
+/// Output format of a `TraceWriter`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            _ => Err(format!("Invalid trace format \"{}\"", s)),
+        }
+    }
+}
+
+/// Writes traces one per line
+pub struct TraceWriter<W: Write> {
+    writer: W,
+    format: Format,
+}
+
+impl<W: Write> TraceWriter<W> {
+    pub fn new(writer: W, format: Format) -> Self {
+        Self { writer, format }
+    }
+}
+
+impl<W: Write> Tracer for TraceWriter<W> {
+    fn trace(&mut self, trace: &Trace) {
+        let line = match self.format {
+            Format::Text => trace.text(),
+            Format::Json => trace.json(),
+        };
+        writeln!(self.writer, "{}", line).expect("Can't write trace");
+    }
+}
+
+/// Parameters of an instruction, along with whether they are written to
+fn parameters(instruction: &Instruction) -> Vec<(Parameter, bool)> {
+    match *instruction {
+        Instruction::Add { n1, n2, to }
+        | Instruction::Multiply { n1, n2, to }
+        | Instruction::LessThan { n1, n2, to }
+        | Instruction::Equals { n1, n2, to } => vec![(n1, false), (n2, false), (to, true)],
+        Instruction::Input { to } => vec![(to, true)],
+        Instruction::Output { from: p } | Instruction::AdjustRelativeBase { by: p } => {
+            vec![(p, false)]
+        }
+        Instruction::JumpIfTrue { test, goto } | Instruction::JumpIfFalse { test, goto } => {
+            vec![(test, false), (goto, false)]
+        }
+        Instruction::Halt | Instruction::End => Vec::new(),
+    }
+}
+
+fn resolve(code: &Memory, rb: isize, parameter: Parameter, destination: bool) -> i64 {
+    let address = match parameter {
+        Parameter::Immediate(v) => return v,
+        Parameter::Position(p) => p,
+        Parameter::Relative(o) => match rb.checked_add(o).map(|a| a.try_into()) {
+            Some(Ok(a)) => a,
+            _ => return 0,
+        },
+    };
+    if destination {
+        address as i64
+    } else {
+        code.read(address)
+    }
+}
+
+impl Machine {
+    /// Executes a single instruction, also returning its trace unless it is blocked on an input
+    pub fn trace_step(&mut self) -> Result<(Status, Option<Trace>), Error> {
+        let ip = self.ip();
+        let code = self.memory();
+        let mut next = ip;
+        let instruction = Instruction::from_code(code, &mut next)?;
+        let values = parameters(&instruction)
+            .into_iter()
+            .map(|(p, d)| resolve(code, self.relative_base(), p, d))
+            .collect();
+        let opcode = code.read(ip);
+        let operands = (ip + 1..next).map(|a| code.read(a)).collect();
+        let instruction = instruction.to_string();
+
+        let status = self.step()?;
+        if status == Status::NeedsInput {
+            return Ok((status, None));
+        }
+        let write = self.last_write().map(|address| Written {
+            address,
+            value: self.memory().read(address),
+        });
+        let trace = Trace {
+            ip,
+            opcode,
+            instruction,
+            operands,
+            values,
+            write,
+        };
+        Ok((status, Some(trace)))
+    }
+
+    /// Same as `run_with`, calling `tracer` with every executed instruction
+    pub fn run_traced<I, O, T>(
+        &mut self,
+        input: &mut I,
+        output: &mut O,
+        tracer: &mut T,
+    ) -> Result<Status, Error>
+    where
+        I: IntcodeInput + ?Sized,
+        O: IntcodeOutput + ?Sized,
+        T: Tracer + ?Sized,
+    {
+        loop {
+            let (status, trace) = self.trace_step()?;
+            if let Some(trace) = trace {
+                tracer.trace(&trace);
+            }
+            match status {
+                Status::Running => (),
+                Status::NeedsInput => match input.read() {
+                    Some(i) => self.push_input(i),
+                    None => return Ok(Status::NeedsInput),
+                },
+                Status::Output(o) => output.write(o),
+                Status::Halted => return Ok(Status::Halted),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        interpreter::{Machine, Status},
+        trace::{Trace, Written},
+    };
+    use std::collections::VecDeque;
+
+    #[test]
+    fn traces() {
+        let mut machine = Machine::new(vec![3, 9, 1001, 9, 5, 9, 4, 9, 99, 0]);
+        let mut traces: Vec<Trace> = Vec::new();
+        let mut output = Vec::new();
+        let status = machine
+            .run_traced(&mut VecDeque::from(vec![2]), &mut output, &mut traces)
+            .unwrap();
+        assert_eq!(Status::Halted, status);
+        assert_eq!(vec![7], output);
+
+        let ips: Vec<usize> = traces.iter().map(|t| t.ip).collect();
+        assert_eq!(vec![0, 2, 6, 8], ips);
+
+        let add = &traces[1];
+        assert_eq!(1001, add.opcode);
+        assert_eq!(vec![9, 5, 9], add.operands);
+        assert_eq!(vec![2, 5, 9], add.values);
+        let write = Written {
+            address: 9,
+            value: 7,
+        };
+        assert_eq!(Some(write), add.write);
+    }
+
+    #[test]
+    fn formats() {
+        let trace = Trace {
+            ip: 2,
+            opcode: 1001,
+            instruction: "add [9], #5, [9]".to_owned(),
+            operands: vec![9, 5, 9],
+            values: vec![2, 5, 9],
+            write: Some(Written {
+                address: 9,
+                value: 7,
+            }),
+        };
+        assert_eq!(
+            "    2: add [9], #5, [9]             ; [2, 5, 9] [9] <- 7",
+            trace.text()
+        );
+        assert_eq!(
+            "{\"ip\":2,\"opcode\":1001,\"instruction\":\"add [9], #5, [9]\",\
+             \"operands\":[9,5,9],\"values\":[2,5,9],\"write\":{\"address\":9,\"value\":7}}",
+            trace.json()
+        );
+    }
+}
@@ -1,10 +1,78 @@
-use crate::{error::Error, interpreter};
+use crate::{
+    error::Error,
+    interpreter::{self, Instruction, Parameter},
+    runtime::Memory,
+};
+use std::{collections::BTreeMap, str::FromStr};
+
+mod c;
+mod cfg;
+mod js;
+mod llvm;
+mod native;
+mod wat;
+
+pub use c::transpile_c;
+use cfg::{blocks_and_coverage, coverage_ranges, Exit, Store};
+pub use js::transpile_js;
+pub use llvm::transpile_llvm;
+pub use native::transpile_native;
+pub use wat::transpile_wat;
+
+/// Language a program is translated to before being compiled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// Rust source built with `rustc`
+    Rust,
+    /// C99 source built with the system `cc`
+    C,
+    /// Textual LLVM IR built with `clang`, or `llc` and `cc`
+    Llvm,
+    /// x86-64 machine code written straight to a static Linux executable
+    Native,
+    /// WebAssembly text format, written out as is for the host to assemble and embed
+    Wat,
+    /// JavaScript ES module, written out as is for the host to import
+    Js,
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rust" => Ok(Backend::Rust),
+            "c" => Ok(Backend::C),
+            "llvm" => Ok(Backend::Llvm),
+            "native" => Ok(Backend::Native),
+            "wat" => Ok(Backend::Wat),
+            "js" => Ok(Backend::Js),
+            _ => Err(format!("Invalid backend \"{}\"", s)),
+        }
+    }
+}
 
 static MAIN: &str = include_str!("../resources/main.rs");
-static ERROR: &str = include_str!("./error.rs");
-static INTERPRETER: &str = include_str!("./interpreter.rs");
+static RUNTIME: &str = include_str!("./runtime.rs");
+static IO: &str = include_str!("./io.rs");
+
+/// Wraps the source of one of the crate's modules so it can be embedded whole
+fn transpile_module(name: &str, source: &str) -> String {
+    format!("#[allow(dead_code)]\nmod {} {{\n{}}}\n", name, source)
+}
+
+fn transpile_output(output: &[i64], ascii: bool) -> String {
+    if ascii {
+        let mut text = String::new();
+        for value in output {
+            match value {
+                0..=127 => text.push(*value as u8 as char),
+                _ => text.push_str(&format!("{}\n", value)),
+            }
+        }
+        return format!("print!(\"{{}}\", {:?});", text);
+    }
 
-fn transpile_output(output: &[i64]) -> String {
     let output = output
         .iter()
         .map(|i| i.to_string())
@@ -13,64 +81,286 @@ fn transpile_output(output: &[i64]) -> String {
     format!("println!({:?});", output)
 }
 
-fn transpile_code(code: &[i64]) -> String {
-    let len = code.len();
-    format!("let mut code: [i64; {}] = {:?};", len, code)
+fn transpile_io(ascii: bool) -> &'static str {
+    if ascii {
+        "let stdin = std::io::stdin();\n    \
+         let mut input = io::AsciiInput::new(stdin.lock());\n    \
+         let mut output = io::AsciiOutput(std::io::stdout());"
+    } else {
+        "let mut input = io::Prompt;\n    let mut output = std::io::stdout();"
+    }
+}
+
+fn transpile_code(code: &Memory) -> String {
+    let sparse = code.sparse();
+    let binding = if sparse.is_empty() {
+        "code"
+    } else {
+        "mut code"
+    };
+    let mut result = format!("let {} = Memory::from(vec!{:?});", binding, code.dense());
+    for (address, value) in sparse {
+        result.push_str(&format!("\n    code.write({}, {});", address, value));
+    }
+    result
 }
 
 fn transpile_iterator(i: usize) -> String {
     format!("let i: usize = {};", i)
 }
 
-pub fn transpile(code: Vec<i64>, input: Vec<i64>) -> Result<String, Error> {
-    let mut result = MAIN.to_owned();
-    let eval_results = interpreter::eval(code, input)?;
+fn transpile_relative_base(rb: isize) -> String {
+    format!("let rb: isize = {};", rb)
+}
 
-    if !eval_results.output.is_empty() {
-        result = result.replace("// output", &transpile_output(&eval_results.output));
-    } else {
-        result = result.replace("    // output\n", "");
+/// Expression reading the value of a parameter
+fn transpile_value(parameter: &Parameter, opcode: i64, at: usize) -> String {
+    match parameter {
+        Parameter::Position(p) => format!("m.read({})", p),
+        Parameter::Immediate(v) => format!("{}i64", v),
+        Parameter::Relative(o) => format!("m.read(relative(rb, {}, {}, {})?)", o, opcode, at),
     }
+}
+
+/// Renders the instructions of a block as Rust statements
+struct Translation<'a> {
+    cfg: cfg::Translation<'a>,
+}
+
+impl Translation<'_> {
+    /// Statements storing `value` at the address of `to`
+    fn write(&self, to: &Parameter, value: &str, opcode: i64, at: usize, next: usize) -> String {
+        let leave = format!("pc = {}; continue;", next);
+        match self.cfg.store(to, next) {
+            Store::Data(address) => format!("m.write({}, {});", address, value),
+            Store::Code {
+                address,
+                leave: false,
+                ..
+            } => format!(
+                "m.write({}, {}); invalidate(&mut valid, {});",
+                address, value, address
+            ),
+            Store::Code { address, .. } => format!(
+                "m.write({}, {}); invalidate(&mut valid, {}); {}",
+                address, value, address, leave
+            ),
+            Store::Relative(o) => format!(
+                "let to = relative(rb, {}, {}, {})?; m.write(to, {}); \
+                 invalidate(&mut valid, to); if !valid[{}] {{ {} }}",
+                o, opcode, at, value, self.cfg.index, leave
+            ),
+        }
+    }
+
+    fn arithmetic(
+        &self,
+        (n1, n2, to): (&Parameter, &Parameter, &Parameter),
+        operation: &str,
+        opcode: i64,
+        at: usize,
+        next: usize,
+    ) -> String {
+        format!(
+            "let n1 = {}; let n2 = {}; let v = {}; {}",
+            transpile_value(n1, opcode, at),
+            transpile_value(n2, opcode, at),
+            operation,
+            self.write(to, "v", opcode, at, next)
+        )
+    }
+
+    fn jump(&self, test: &Parameter, goto: &Parameter, condition: &str, at: usize) -> String {
+        let opcode = if condition == "!=" { 5 } else { 6 };
+        let taken = match self.cfg.target(goto) {
+            Some(g) => format!("pc = {}; continue;", g),
+            None => format!(
+                "pc = target(m, {}, {}, {})?; continue;",
+                transpile_value(goto, opcode, at),
+                opcode,
+                at
+            ),
+        };
+        format!(
+            "if {} {} 0 {{ {} }}",
+            transpile_value(test, opcode, at),
+            condition,
+            taken
+        )
+    }
+
+    fn instruction(&self, instruction: &Instruction, at: usize, next: usize) -> String {
+        match instruction {
+            Instruction::Add { n1, n2, to } => self.arithmetic(
+                (n1, n2, to),
+                &format!("checked(n1.checked_add(n2), 1, {})?", at),
+                1,
+                at,
+                next,
+            ),
+            Instruction::Multiply { n1, n2, to } => self.arithmetic(
+                (n1, n2, to),
+                &format!("checked(n1.checked_mul(n2), 2, {})?", at),
+                2,
+                at,
+                next,
+            ),
+            Instruction::Input { to } => format!(
+                "let v = match input.read() {{ Some(v) => v, None => return Ok(()) }}; {}",
+                self.write(to, "v", 3, at, next)
+            ),
+            Instruction::Output { from } => {
+                format!("output.write({});", transpile_value(from, 4, at))
+            }
+            Instruction::JumpIfTrue { test, goto } => self.jump(test, goto, "!=", at),
+            Instruction::JumpIfFalse { test, goto } => self.jump(test, goto, "==", at),
+            Instruction::LessThan { n1, n2, to } => {
+                self.arithmetic((n1, n2, to), "(n1 < n2) as i64", 7, at, next)
+            }
+            Instruction::Equals { n1, n2, to } => {
+                self.arithmetic((n1, n2, to), "(n1 == n2) as i64", 8, at, next)
+            }
+            Instruction::AdjustRelativeBase { by } => format!(
+                "rb = ({} as isize).checked_add(rb).ok_or(Error::ArithmeticOverflow \
+                 {{ opcode: 9, position: {} }})?;",
+                transpile_value(by, 9, at),
+                at
+            ),
+            Instruction::Halt | Instruction::End => "return Ok(());".to_owned(),
+        }
+    }
+
+    fn block(&self) -> String {
+        let mut result = format!(
+            "            {} if valid[{}] => {{\n                let m = machine.memory_mut();\n",
+            self.cfg.block.start, self.cfg.index
+        );
+        for (at, instruction, next) in &self.cfg.block.instructions {
+            let statement = self.instruction(instruction, *at, *next);
+            // Statements binding temporaries get their own scope
+            if statement.starts_with("let ") {
+                result.push_str(&format!("                {{ {} }}\n", statement));
+            } else {
+                result.push_str(&format!("                {}\n", statement));
+            }
+        }
+        match self.cfg.block.exit {
+            Exit::Next(next) | Exit::Jump(next) | Exit::Fallback(next) => {
+                result.push_str(&format!("                pc = {};\n", next))
+            }
+            Exit::Halt => (),
+        }
+        result.push_str("            }\n");
+        result
+    }
+}
+
+/// Translates the code reachable from `entry` into the arms of the `match pc` state machine,
+/// along with the table of blocks covering each address and the number of blocks
+fn transpile_blocks(code: &Memory, entry: usize) -> (String, String, usize) {
+    let (blocks, covered) = blocks_and_coverage(code, entry);
+    let arms = cfg::translations(&blocks, &covered, code)
+        .map(|cfg| Translation { cfg }.block())
+        .collect::<Vec<String>>()
+        .join("");
+
+    (arms, transpile_invalidate(&covered), blocks.len())
+}
+
+/// Arms of the `match address` mapping code addresses to the blocks covering them
+fn transpile_invalidate(covered: &BTreeMap<usize, Vec<usize>>) -> String {
+    coverage_ranges(covered)
+        .iter()
+        .map(|(start, end, blocks)| format!("{}..={} => &{:?},", start, end, blocks))
+        .collect::<Vec<String>>()
+        .join("\n        ")
+}
+
+/// Translates a program into the source of an equivalent Rust program
+///
+/// In ASCII mode, the emitted program reads lines of text and prints outputs as characters. It
+/// must be compiled with the 2018 edition.
+pub fn transpile(code: Vec<i64>, input: Vec<i64>, ascii: bool) -> Result<String, Error> {
+    let eval_results = interpreter::eval(code, input)?;
+    let output = if eval_results.output.is_empty() {
+        String::new()
+    } else {
+        transpile_output(&eval_results.output, ascii)
+    };
 
     if eval_results.completed {
-        let mut result: Vec<&str> = result.split('\n').collect();
-        result.truncate(2);
-        result.push("}\n");
-        return Ok(result.join("\n"));
+        return Ok(format!("fn main() {{\n    {}\n}}\n", output));
     }
 
-    result = result
+    let (blocks, invalidate, count) = transpile_blocks(&eval_results.code, eval_results.run_code);
+    let main = MAIN
+        .replace("            // blocks\n", &blocks)
+        .replace("// invalidate", &invalidate)
+        .replace("// valid", &format!("let mut valid = [true; {}];", count))
+        .replace("// output", &output)
+        .replace("// io", transpile_io(ascii))
         .replace("// code", &transpile_code(&eval_results.code))
-        .replace("// iterator", &transpile_iterator(eval_results.run_code));
-
-    let mut inter: Vec<&str> = INTERPRETER.split('\n').collect();
-    inter.truncate(342);
-    inter.remove(0);
-    let mut err = ERROR.to_owned();
-    err.push('\n');
-    err.push_str(&inter.join("\n"));
-    err.push('\n');
-    err.push_str(&result);
-    result = err;
-
-    Ok(result)
+        .replace("// iterator", &transpile_iterator(eval_results.run_code))
+        .replace(
+            "// relative base",
+            &transpile_relative_base(eval_results.relative_base),
+        );
+
+    Ok(format!(
+        "{}\n{}\n{}",
+        transpile_module("runtime", RUNTIME),
+        transpile_module("io", IO),
+        main
+    ))
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::transpiler::{transpile_code, transpile_iterator, transpile_output};
+    use crate::interpreter;
+    use crate::runtime::Memory;
+    use crate::transpiler::{
+        transpile, transpile_blocks, transpile_code, transpile_invalidate, transpile_iterator,
+        transpile_output, transpile_relative_base, RUNTIME,
+    };
+    use std::{
+        collections::BTreeMap,
+        env,
+        io::Write,
+        process::{Command, Stdio},
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    /// Distinguishes the binaries of tests running in parallel
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
 
     #[test]
     fn output() {
         let output = vec![1, 2, 3];
         let expected = "println!(\"1\\n2\\n3\");".to_owned();
-        assert_eq!(expected, transpile_output(&output));
+        assert_eq!(expected, transpile_output(&output, false));
+    }
+
+    #[test]
+    fn ascii_output() {
+        let output = vec![72, 105, 10, 1234];
+        let expected = "print!(\"{}\", \"Hi\\n1234\\n\");".to_owned();
+        assert_eq!(expected, transpile_output(&output, true));
     }
 
     #[test]
     fn code() {
-        let code = vec![1, 2, 3];
-        let expected = "let mut code: [i64; 3] = [1, 2, 3];".to_owned();
+        let code = Memory::from(vec![1, 2, 3]);
+        let expected = "let code = Memory::from(vec![1, 2, 3]);".to_owned();
+        assert_eq!(expected, transpile_code(&code));
+    }
+
+    #[test]
+    fn sparse_code() {
+        let mut code = Memory::from(vec![1, 2, 3]);
+        code.write(1 << 40, 4);
+        let expected =
+            "let mut code = Memory::from(vec![1, 2, 3]);\n    code.write(1099511627776, 4);"
+                .to_owned();
         assert_eq!(expected, transpile_code(&code));
     }
 
@@ -80,4 +370,105 @@ mod tests {
         let expected = "let i: usize = 0;".to_owned();
         assert_eq!(expected, transpile_iterator(i));
     }
+
+    #[test]
+    fn relative_base() {
+        let rb = -3;
+        let expected = "let rb: isize = -3;".to_owned();
+        assert_eq!(expected, transpile_relative_base(rb));
+    }
+
+    #[test]
+    fn blocks() {
+        // 0: in [12]; 2: out [12]; 4: add [12], #-1, [12]; 8: jnz [12], #2; 11: hlt
+        let code = Memory::from(vec![3, 12, 4, 12, 1001, 12, -1, 12, 1005, 12, 2, 99, 0]);
+        let (arms, _, count) = transpile_blocks(&code, 0);
+        assert_eq!(3, count);
+        assert!(arms.contains("2 if valid[1] => {"));
+        assert!(arms.contains("output.write(m.read(12));"));
+        assert!(arms.contains("if m.read(12) != 0 { pc = 2; continue; }"));
+        assert!(arms.contains("pc = 11;"));
+    }
+
+    #[test]
+    fn invalidate() {
+        let mut covered = BTreeMap::new();
+        covered.insert(0, vec![0]);
+        covered.insert(1, vec![0]);
+        covered.insert(2, vec![0, 1]);
+        covered.insert(3, vec![1]);
+        let expected = "0..=1 => &[0],\n        2..=2 => &[0, 1],\n        3..=3 => &[1],";
+        assert_eq!(expected, transpile_invalidate(&covered));
+    }
+
+    #[test]
+    fn runtime_is_no_std() {
+        assert!(!RUNTIME.contains("std::"));
+    }
+
+    /// Compiles the transpiled program and runs it with `input`, returning the values it prints
+    fn compile_and_run(code: Vec<i64>, input: &[u8]) -> Vec<i64> {
+        let source = transpile(code, vec![], false).unwrap();
+
+        let binary = env::temp_dir().join(format!(
+            "intcode_transpiled_{}_{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let mut rustc = Command::new("rustc")
+            .args(["-", "--edition", "2018", "-o"])
+            .arg(&binary)
+            .stdin(Stdio::piped())
+            .spawn()
+            .unwrap();
+        rustc
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(source.as_bytes())
+            .unwrap();
+        assert!(rustc.wait().unwrap().success());
+
+        let mut program = Command::new(&binary)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        program.stdin.take().unwrap().write_all(input).unwrap();
+        let output = program.wait_with_output().unwrap();
+        std::fs::remove_file(&binary).ok();
+        assert!(output.status.success());
+
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .lines()
+            .filter_map(|l| l.parse().ok())
+            .collect()
+    }
+
+    #[test]
+    fn emitted_program_compiles() {
+        // Counts down from its input
+        let code = vec![3, 12, 4, 12, 1001, 12, -1, 12, 1005, 12, 2, 99, 0];
+        assert_eq!(vec![3, 2, 1], compile_and_run(code, b"3\n"));
+    }
+
+    #[test]
+    fn jump_into_grown_memory() {
+        // Grows memory with an input, writes `out #7; hlt` past the end of the image and jumps
+        // there
+        let code = vec![
+            3, 30, 1101, 0, 104, 20, 1101, 0, 7, 21, 1101, 0, 99, 22, 1105, 1, 20, 99,
+        ];
+        let expected = interpreter::eval(code.clone(), vec![5]).unwrap().output;
+        assert_eq!(vec![7], expected);
+        assert_eq!(expected, compile_and_run(code, b"5\n"));
+    }
+
+    #[test]
+    fn jump_to_end() {
+        // Echoes its input, then jumps right past its end if it isn't zero
+        let code = vec![3, 1, 4, 1, 1005, 1, 7];
+        assert_eq!(vec![5], compile_and_run(code, b"5\n"));
+    }
 }
@@ -0,0 +1,410 @@
+use crate::{
+    error::Error,
+    interpreter::{self, Instruction, Parameter},
+    runtime::Memory,
+};
+use std::collections::BTreeMap;
+
+use super::cfg::{self, coverage_ranges, Exit, Store};
+
+static MAIN: &str = include_str!("../../resources/main.c");
+
+/// Integer literal, spelling out `i64::MIN` since its absolute value has no C type
+fn literal(value: i64) -> String {
+    if value == i64::MIN {
+        "(-9223372036854775807 - 1)".to_owned()
+    } else {
+        value.to_string()
+    }
+}
+
+/// String literal escaping everything but printable ASCII
+fn string(text: &str) -> String {
+    let mut result = "\"".to_owned();
+    for byte in text.bytes() {
+        match byte {
+            b'"' | b'\\' => result.push_str(&format!("\\{}", byte as char)),
+            b'\n' => result.push_str("\\n"),
+            b' '..=b'~' => result.push(byte as char),
+            _ => result.push_str(&format!("\\{:03o}", byte)),
+        }
+    }
+    result.push('"');
+    result
+}
+
+fn transpile_output(output: &[i64], ascii: bool) -> String {
+    let mut text = String::new();
+    for value in output {
+        match value {
+            0..=127 if ascii => text.push(*value as u8 as char),
+            _ => text.push_str(&format!("{}\n", value)),
+        }
+    }
+    format!("fputs({}, stdout);", string(&text))
+}
+
+fn transpile_io(ascii: bool) -> String {
+    format!("static const int ascii = {};", ascii as u8)
+}
+
+fn transpile_code(code: &Memory) -> String {
+    let mut result = String::new();
+    if !code.dense().is_empty() {
+        let lines: Vec<String> = code
+            .dense()
+            .chunks(16)
+            .map(|values| {
+                let values: Vec<String> = values.iter().map(|v| literal(*v)).collect();
+                values.join(", ")
+            })
+            .collect();
+        result.push_str(&format!(
+            "static const int64_t image[] = {{\n        {}\n    }};\n    \
+             dense = allocate(NULL, sizeof(image));\n    \
+             memcpy(dense, image, sizeof(image));\n    \
+             dense_length = dense_capacity = sizeof(image) / sizeof(image[0]);",
+            lines.join(",\n        ")
+        ));
+    }
+    for (address, value) in code.sparse() {
+        result.push_str(&format!(
+            "\n    mem_write({}, {});",
+            address,
+            literal(value)
+        ));
+    }
+    result
+}
+
+/// Body of the `invalidate` function, clearing the validity flags of the blocks covering an
+/// address by going through the covered ranges in ascending order
+fn transpile_invalidate(covered: &BTreeMap<usize, Vec<usize>>) -> String {
+    coverage_ranges(covered)
+        .iter()
+        .map(|(start, end, blocks)| {
+            let clear: Vec<String> = blocks
+                .iter()
+                .map(|b| format!("valid[{}] = 0;", b))
+                .collect();
+            let clear = match start {
+                0 => clear.join(" "),
+                _ => format!("if (address >= {}) {{ {} }}", start, clear.join(" ")),
+            };
+            format!(
+                "if (address <= {}) {{\n        {}\n        return;\n    }}",
+                end, clear
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n    ")
+}
+
+/// Expression reading the value of a parameter
+fn transpile_value(parameter: &Parameter, opcode: i64, at: usize) -> String {
+    match parameter {
+        Parameter::Position(p) => format!("mem_read({})", p),
+        Parameter::Immediate(v) => literal(*v),
+        Parameter::Relative(o) => {
+            format!(
+                "mem_read(relative(rb, {}, {}, {}))",
+                literal(*o as i64),
+                opcode,
+                at
+            )
+        }
+    }
+}
+
+/// Renders the instructions of a block as C statements
+struct Translation<'a> {
+    cfg: cfg::Translation<'a>,
+}
+
+impl Translation<'_> {
+    /// Statements storing `value` at the address of `to`
+    fn write(&self, to: &Parameter, value: &str, opcode: i64, at: usize, next: usize) -> String {
+        let leave = format!("pc = {}; continue;", next);
+        match self.cfg.store(to, next) {
+            Store::Data(address) => format!("mem_write({}, {});", address, value),
+            Store::Code {
+                address,
+                leave: false,
+                ..
+            } => format!(
+                "mem_write({}, {}); invalidate({});",
+                address, value, address
+            ),
+            Store::Code { address, .. } => format!(
+                "mem_write({}, {}); invalidate({}); {}",
+                address, value, address, leave
+            ),
+            Store::Relative(o) => format!(
+                "uint64_t to = relative(rb, {}, {}, {}); mem_write(to, {}); invalidate(to); \
+                 if (!valid[{}]) {{ {} }}",
+                literal(o as i64),
+                opcode,
+                at,
+                value,
+                self.cfg.index,
+                leave
+            ),
+        }
+    }
+
+    fn arithmetic(
+        &self,
+        (n1, n2, to): (&Parameter, &Parameter, &Parameter),
+        operation: &str,
+        opcode: i64,
+        at: usize,
+        next: usize,
+    ) -> String {
+        format!(
+            "int64_t n1 = {}; int64_t n2 = {}; int64_t v = {}; {}",
+            transpile_value(n1, opcode, at),
+            transpile_value(n2, opcode, at),
+            operation,
+            self.write(to, "v", opcode, at, next)
+        )
+    }
+
+    fn jump(&self, test: &Parameter, goto: &Parameter, condition: &str, at: usize) -> String {
+        let opcode = if condition == "!=" { 5 } else { 6 };
+        let taken = match self.cfg.target(goto) {
+            Some(g) => format!("pc = {}; continue;", g),
+            None => format!(
+                "pc = target({}, {}, {}); continue;",
+                transpile_value(goto, opcode, at),
+                opcode,
+                at
+            ),
+        };
+        format!(
+            "if ({} {} 0) {{ {} }}",
+            transpile_value(test, opcode, at),
+            condition,
+            taken
+        )
+    }
+
+    fn instruction(&self, instruction: &Instruction, at: usize, next: usize) -> String {
+        match instruction {
+            Instruction::Add { n1, n2, to } => self.arithmetic(
+                (n1, n2, to),
+                &format!("checked_add(n1, n2, 1, {})", at),
+                1,
+                at,
+                next,
+            ),
+            Instruction::Multiply { n1, n2, to } => self.arithmetic(
+                (n1, n2, to),
+                &format!("checked_mul(n1, n2, 2, {})", at),
+                2,
+                at,
+                next,
+            ),
+            Instruction::Input { to } => format!(
+                "int64_t v; if (!read_input(&v)) return; {}",
+                self.write(to, "v", 3, at, next)
+            ),
+            Instruction::Output { from } => {
+                format!("write_output({});", transpile_value(from, 4, at))
+            }
+            Instruction::JumpIfTrue { test, goto } => self.jump(test, goto, "!=", at),
+            Instruction::JumpIfFalse { test, goto } => self.jump(test, goto, "==", at),
+            Instruction::LessThan { n1, n2, to } => {
+                self.arithmetic((n1, n2, to), "n1 < n2", 7, at, next)
+            }
+            Instruction::Equals { n1, n2, to } => {
+                self.arithmetic((n1, n2, to), "n1 == n2", 8, at, next)
+            }
+            Instruction::AdjustRelativeBase { by } => format!(
+                "rb = checked_add({}, rb, 9, {});",
+                transpile_value(by, 9, at),
+                at
+            ),
+            Instruction::Halt | Instruction::End => "return;".to_owned(),
+        }
+    }
+
+    fn block(&self) -> String {
+        let mut result = format!(
+            "        case {}:\n            if (!valid[{}]) goto fallback;\n",
+            self.cfg.block.start, self.cfg.index
+        );
+        for (at, instruction, next) in &self.cfg.block.instructions {
+            let statement = self.instruction(instruction, *at, *next);
+            // Statements declaring temporaries get their own scope
+            if statement.starts_with("int64_t ") || statement.starts_with("uint64_t ") {
+                result.push_str(&format!("            {{ {} }}\n", statement));
+            } else {
+                result.push_str(&format!("            {}\n", statement));
+            }
+        }
+        match self.cfg.block.exit {
+            Exit::Next(next) | Exit::Jump(next) | Exit::Fallback(next) => result.push_str(
+                &format!("            pc = {};\n            continue;\n", next),
+            ),
+            Exit::Halt => (),
+        }
+        result
+    }
+}
+
+/// Translates the code reachable from `entry` into the cases of the `switch (pc)` state
+/// machine, along with the body of `invalidate` and the number of blocks
+fn transpile_blocks(code: &Memory, entry: usize) -> (String, String, usize) {
+    let (blocks, covered) = cfg::blocks_and_coverage(code, entry);
+    let cases = cfg::translations(&blocks, &covered, code)
+        .map(|cfg| Translation { cfg }.block())
+        .collect::<Vec<String>>()
+        .join("");
+
+    (cases, transpile_invalidate(&covered), blocks.len())
+}
+
+/// Translates a program into the source of an equivalent C99 program
+///
+/// The emitted program only depends on the C standard library, and behaves like the one emitted
+/// by `transpile`.
+pub fn transpile_c(code: Vec<i64>, input: Vec<i64>, ascii: bool) -> Result<String, Error> {
+    let eval_results = interpreter::eval(code, input)?;
+    let output = if eval_results.output.is_empty() {
+        String::new()
+    } else {
+        transpile_output(&eval_results.output, ascii)
+    };
+
+    if eval_results.completed {
+        return Ok(format!(
+            "#include <stdio.h>\n\nint main(void) {{\n    {}\n    return 0;\n}}\n",
+            output
+        ));
+    }
+
+    let (blocks, invalidate, count) = transpile_blocks(&eval_results.code, eval_results.run_code);
+    Ok(MAIN
+        .replace("            // blocks\n", &blocks)
+        .replace("// invalidate", &invalidate)
+        .replace(
+            "// valid",
+            &format!("static unsigned char valid[{}];", count.max(1)),
+        )
+        .replace("// output", &output)
+        .replace("// io", &transpile_io(ascii))
+        .replace("// code", &transpile_code(&eval_results.code))
+        .replace(
+            "// iterator",
+            &format!("uint64_t i = {};", eval_results.run_code),
+        )
+        .replace(
+            "// relative base",
+            &format!(
+                "int64_t rb = {};",
+                literal(eval_results.relative_base as i64)
+            ),
+        ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::runtime::Memory;
+    use crate::transpiler::c::{
+        literal, transpile_blocks, transpile_c, transpile_code, transpile_output,
+    };
+    use std::{
+        env,
+        io::Write,
+        process::{Command, Stdio},
+    };
+
+    #[test]
+    fn output() {
+        assert_eq!(
+            "fputs(\"1\\n-2\\n\", stdout);",
+            transpile_output(&[1, -2], false)
+        );
+        assert_eq!(
+            "fputs(\"H\\\"\\001\\n1234\\n\", stdout);",
+            transpile_output(&[72, 34, 1, 10, 1234], true)
+        );
+    }
+
+    #[test]
+    fn code() {
+        let mut code = Memory::from(vec![1, i64::MIN]);
+        code.write(1 << 40, 4);
+        let code = transpile_code(&code);
+        assert!(code.starts_with(
+            "static const int64_t image[] = {\n        1, (-9223372036854775807 - 1)\n    };"
+        ));
+        assert!(code.ends_with("\n    mem_write(1099511627776, 4);"));
+        assert_eq!("-3", literal(-3));
+    }
+
+    #[test]
+    fn blocks() {
+        // 0: in [12]; 2: out [12]; 4: add [12], #-1, [12]; 8: jnz [12], #2; 11: hlt
+        let code = Memory::from(vec![3, 12, 4, 12, 1001, 12, -1, 12, 1005, 12, 2, 99, 0]);
+        let (cases, invalidate, count) = transpile_blocks(&code, 0);
+        assert_eq!(3, count);
+        assert!(cases.contains("case 2:\n            if (!valid[1]) goto fallback;"));
+        assert!(cases.contains("write_output(mem_read(12));"));
+        assert!(cases.contains("if (mem_read(12) != 0) { pc = 2; continue; }"));
+        assert!(cases.contains("pc = 11;\n            continue;"));
+        assert!(invalidate.starts_with(
+            "if (address <= 1) {\n        valid[0] = 0;\n        return;\n    }\n    \
+             if (address <= 10) {\n        if (address >= 2) { valid[1] = 0; }"
+        ));
+    }
+
+    #[test]
+    fn emitted_program_compiles() {
+        // Counts down from its input, then jumps out of bounds
+        let code = vec![3, 14, 4, 14, 1001, 14, -1, 14, 1005, 14, 2, 1105, 1, -1, 0];
+        let source = transpile_c(code, vec![], false).unwrap();
+
+        let binary = env::temp_dir().join(format!("intcode_c_{}", std::process::id()));
+        let mut cc = Command::new("cc")
+            .args([
+                "-std=c99",
+                "-Wall",
+                "-Werror",
+                "-Wno-unused-function",
+                "-x",
+                "c",
+                "-",
+            ])
+            .arg("-o")
+            .arg(&binary)
+            .stdin(Stdio::piped())
+            .spawn()
+            .unwrap();
+        cc.stdin
+            .take()
+            .unwrap()
+            .write_all(source.as_bytes())
+            .unwrap();
+        assert!(cc.wait().unwrap().success());
+
+        let mut program = Command::new(&binary)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        program.stdin.take().unwrap().write_all(b"x\n3\n").unwrap();
+        let output = program.wait_with_output().unwrap();
+        std::fs::remove_file(&binary).ok();
+
+        assert_eq!(Some(1), output.status.code());
+        let output = String::from_utf8(output.stdout).unwrap();
+        let values: Vec<&str> = output
+            .lines()
+            .filter(|l| l.parse::<i64>().is_ok())
+            .collect();
+        assert_eq!(vec!["3", "2", "1"], values);
+        assert!(output.contains("Invalid"));
+        assert!(output.ends_with("Jump target -1 out of bounds for opcode \"5\" at position 11\n"));
+    }
+}
@@ -0,0 +1,298 @@
+use crate::{
+    interpreter::{Instruction, Parameter},
+    runtime::Memory,
+};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+/// How control leaves a basic block
+pub(crate) enum Exit {
+    /// Falls through into the block at the given address
+    Next(usize),
+    /// Ends with a conditional jump, falling through to the given address when it isn't taken
+    Jump(usize),
+    Halt,
+    /// The word at the given address can't be decoded and is left to the fallback dispatcher
+    Fallback(usize),
+}
+
+/// A straight-line sequence of instructions with a single entry point
+pub(crate) struct Block {
+    pub start: usize,
+    /// Instructions along with their address and the address right after them
+    pub instructions: Vec<(usize, Instruction, usize)>,
+    pub exit: Exit,
+}
+
+impl Block {
+    /// Address right after the last word of the block
+    pub fn end(&self) -> usize {
+        match self.instructions.last() {
+            Some((_, _, next)) => *next,
+            None => self.start,
+        }
+    }
+}
+
+fn decode(code: &Memory, address: usize) -> Option<(Instruction, usize)> {
+    let mut next = address;
+    let instruction = Instruction::from_code(code, &mut next).ok()?;
+    Some((instruction, next))
+}
+
+fn target(code: &Memory, value: i64) -> Option<usize> {
+    if value >= 0 && code.get(value as usize).is_some() {
+        Some(value as usize)
+    } else {
+        None
+    }
+}
+
+/// Addresses control can statically reach from an instruction, and whether it can fall through
+fn successors(code: &Memory, instruction: &Instruction) -> (Vec<usize>, bool) {
+    match instruction {
+        Instruction::JumpIfTrue { test, goto } | Instruction::JumpIfFalse { test, goto } => {
+            let taken = match (instruction, test) {
+                (Instruction::JumpIfTrue { .. }, Parameter::Immediate(t)) => Some(*t != 0),
+                (Instruction::JumpIfFalse { .. }, Parameter::Immediate(t)) => Some(*t == 0),
+                _ => None,
+            };
+            let targets = match goto {
+                Parameter::Immediate(g) if taken != Some(false) => {
+                    target(code, *g).into_iter().collect()
+                }
+                _ => Vec::new(),
+            };
+            (targets, taken != Some(true))
+        }
+        // Return addresses pushed before calls, like `add #ret, #0, rb[1]`
+        Instruction::Add {
+            n1: Parameter::Immediate(a),
+            n2: Parameter::Immediate(b),
+            ..
+        } if *a == 0 || *b == 0 => (target(code, a + b).into_iter().collect(), true),
+        Instruction::Multiply {
+            n1: Parameter::Immediate(a),
+            n2: Parameter::Immediate(b),
+            ..
+        } if *a == 1 || *b == 1 => (target(code, a * b).into_iter().collect(), true),
+        Instruction::Halt | Instruction::End => (Vec::new(), false),
+        _ => (Vec::new(), true),
+    }
+}
+
+/// Splits the code reachable from `entry` into basic blocks, sorted by address
+///
+/// Block leaders are the entry point, immediate jump targets, the instructions following
+/// conditional jumps, and immediate return addresses. Anything else reached at runtime through
+/// computed jumps is left to the fallback dispatcher.
+pub(crate) fn blocks(code: &Memory, entry: usize) -> Vec<Block> {
+    let mut leaders = BTreeSet::new();
+    leaders.insert(entry);
+
+    let mut visited = HashSet::new();
+    let mut pending = vec![entry];
+    while let Some(address) = pending.pop() {
+        if !visited.insert(address) {
+            continue;
+        }
+        let (instruction, next) = match decode(code, address) {
+            Some(d) => d,
+            None => continue,
+        };
+
+        let (targets, falls_through) = successors(code, &instruction);
+        let is_jump = matches!(
+            instruction,
+            Instruction::JumpIfTrue { .. } | Instruction::JumpIfFalse { .. }
+        );
+        for target in targets {
+            leaders.insert(target);
+            pending.push(target);
+        }
+        if falls_through {
+            if is_jump {
+                leaders.insert(next);
+            }
+            pending.push(next);
+        }
+    }
+
+    leaders
+        .iter()
+        .map(|leader| {
+            let mut instructions = Vec::new();
+            let mut address = *leader;
+            let exit = loop {
+                let (instruction, next) = match decode(code, address) {
+                    Some(d) => d,
+                    None => break Exit::Fallback(address),
+                };
+                let exit = match instruction {
+                    Instruction::JumpIfTrue { .. } | Instruction::JumpIfFalse { .. } => {
+                        Some(Exit::Jump(next))
+                    }
+                    Instruction::Halt | Instruction::End => Some(Exit::Halt),
+                    _ if leaders.contains(&next) => Some(Exit::Next(next)),
+                    _ => None,
+                };
+                instructions.push((address, instruction, next));
+                match exit {
+                    Some(exit) => break exit,
+                    None => address = next,
+                }
+            };
+            Block {
+                start: *leader,
+                instructions,
+                exit,
+            }
+        })
+        .collect()
+}
+
+/// Non-empty blocks reachable from `entry`, along with the blocks covering each address
+pub(crate) fn blocks_and_coverage(
+    code: &Memory,
+    entry: usize,
+) -> (Vec<Block>, BTreeMap<usize, Vec<usize>>) {
+    let blocks: Vec<Block> = blocks(code, entry)
+        .into_iter()
+        .filter(|b| !b.instructions.is_empty())
+        .collect();
+
+    let mut covered: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    for (index, block) in blocks.iter().enumerate() {
+        for address in block.start..block.end() {
+            covered.entry(address).or_default().push(index);
+        }
+    }
+    (blocks, covered)
+}
+
+/// Inclusive ranges of consecutive addresses covered by the same blocks
+///
+/// Backends invalidate blocks by walking these ranges in ascending order, stopping at the first
+/// one that doesn't end before the address.
+pub(crate) fn coverage_ranges(
+    covered: &BTreeMap<usize, Vec<usize>>,
+) -> Vec<(usize, usize, &Vec<usize>)> {
+    let mut ranges: Vec<(usize, usize, &Vec<usize>)> = Vec::new();
+    for (address, blocks) in covered {
+        match ranges.last_mut() {
+            Some((_, end, b)) if *end + 1 == *address && *b == blocks => *end = *address,
+            _ => ranges.push((*address, *address, blocks)),
+        }
+    }
+    ranges
+}
+
+/// Where a write goes, as far as compiled code is concerned
+pub(crate) enum Store<'a> {
+    /// A fixed address that holds no compiled code
+    Data(usize),
+    /// A fixed address inside the given blocks, which must be invalidated
+    ///
+    /// `leave` is set when the address holds one of the upcoming instructions of the block being
+    /// translated, which must then be left for the dispatcher.
+    Code {
+        address: usize,
+        blocks: &'a [usize],
+        leave: bool,
+    },
+    /// An offset from the relative base, after which the block must be left if the write
+    /// invalidated it
+    Relative(isize),
+}
+
+/// Decisions shared by every backend when translating the instructions of a block
+pub(crate) struct Translation<'a> {
+    /// Index of the block in the validity flags
+    pub index: usize,
+    pub block: &'a Block,
+    /// Blocks covering each address of compiled code
+    covered: &'a BTreeMap<usize, Vec<usize>>,
+    code: &'a Memory,
+}
+
+impl<'a> Translation<'a> {
+    /// Where a write to `to` by the instruction ending at `next` goes
+    pub fn store(&self, to: &Parameter, next: usize) -> Store<'a> {
+        match to {
+            Parameter::Position(p) => match self.covered.get(p) {
+                Some(blocks) => Store::Code {
+                    address: *p,
+                    blocks,
+                    leave: *p >= next && *p < self.block.end(),
+                },
+                None => Store::Data(*p),
+            },
+            Parameter::Relative(o) => Store::Relative(*o),
+            Parameter::Immediate(_) => unreachable!("immediate parameters have no address"),
+        }
+    }
+
+    /// Address a taken jump to `goto` can go to without checking it at run time
+    ///
+    /// Only immediate targets inside the image qualify: memory never shrinks, while targets past
+    /// it may become valid once it grows.
+    pub fn target(&self, goto: &Parameter) -> Option<usize> {
+        match goto {
+            Parameter::Immediate(g) => target(self.code, *g),
+            _ => None,
+        }
+    }
+}
+
+/// Translation contexts of `blocks`, in order
+pub(crate) fn translations<'a>(
+    blocks: &'a [Block],
+    covered: &'a BTreeMap<usize, Vec<usize>>,
+    code: &'a Memory,
+) -> impl Iterator<Item = Translation<'a>> {
+    blocks
+        .iter()
+        .enumerate()
+        .map(move |(index, block)| Translation {
+            index,
+            block,
+            covered,
+            code,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        runtime::Memory,
+        transpiler::cfg::{blocks, Exit},
+    };
+
+    #[test]
+    fn loop_blocks() {
+        // 0: in [12]; 2: out [12]; 4: add [12], #-1, [12]; 8: jnz [12], #2; 11: hlt
+        let code = Memory::from(vec![3, 12, 4, 12, 1001, 12, -1, 12, 1005, 12, 2, 99, 0]);
+        let blocks = blocks(&code, 0);
+        let starts: Vec<usize> = blocks.iter().map(|b| b.start).collect();
+        assert_eq!(vec![0, 2, 11], starts);
+
+        assert!(matches!(blocks[0].exit, Exit::Next(2)));
+        assert!(matches!(blocks[1].exit, Exit::Jump(11)));
+        assert_eq!(11, blocks[1].end());
+        assert!(matches!(blocks[2].exit, Exit::Halt));
+    }
+
+    #[test]
+    fn unconditional_jump_skips_data() {
+        // 0: jnz #1, #4; 3: .data 0; 4: hlt
+        let code = Memory::from(vec![1105, 1, 4, 0, 99]);
+        let starts: Vec<usize> = blocks(&code, 0).iter().map(|b| b.start).collect();
+        assert_eq!(vec![0, 4], starts);
+    }
+
+    #[test]
+    fn undecodable() {
+        let code = Memory::from(vec![104, 1, 0]);
+        let blocks = blocks(&code, 0);
+        assert!(matches!(blocks[0].exit, Exit::Fallback(2)));
+    }
+}
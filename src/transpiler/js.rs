@@ -0,0 +1,348 @@
+use crate::{
+    error::Error,
+    interpreter::{self, Instruction, Parameter},
+    runtime::Memory,
+};
+use std::collections::BTreeMap;
+
+use super::cfg::{self, coverage_ranges, Exit, Store};
+
+static MAIN: &str = include_str!("../../resources/main.js");
+
+/// BigInt literals, wrapped sixteen per line
+fn literals(values: &[i64], indent: &str) -> String {
+    let lines: Vec<String> = values
+        .chunks(16)
+        .map(|values| {
+            let values: Vec<String> = values.iter().map(|v| format!("{}n", v)).collect();
+            values.join(", ")
+        })
+        .collect();
+    if lines.is_empty() {
+        "[]".to_owned()
+    } else {
+        format!(
+            "[\n{}  {},\n{}]",
+            indent,
+            lines.join(&format!(",\n{}  ", indent)),
+            indent
+        )
+    }
+}
+
+fn transpile_output(output: &[i64]) -> String {
+    format!("const OUTPUT = {};", literals(output, ""))
+}
+
+fn transpile_code(code: &Memory) -> String {
+    let sparse: Vec<String> = code
+        .sparse()
+        .iter()
+        .map(|(address, value)| format!("[{}n, {}n]", address, value))
+        .collect();
+    format!(
+        "const IMAGE = {};\nconst SPARSE = [{}];",
+        literals(code.dense(), ""),
+        sparse.join(", ")
+    )
+}
+
+/// Body of `invalidate`, clearing the validity flags of the blocks covering an address
+fn transpile_invalidate(covered: &BTreeMap<usize, Vec<usize>>) -> String {
+    coverage_ranges(covered)
+        .iter()
+        .map(|(start, end, blocks)| {
+            let clear: Vec<String> = blocks
+                .iter()
+                .map(|b| format!("valid[{}] = 0;", b))
+                .collect();
+            let clear = match start {
+                0 => clear.join(" "),
+                _ => format!("if (address >= {}n) {{ {} }}", start, clear.join(" ")),
+            };
+            format!(
+                "if (address <= {}n) {{\n      {}\n      return;\n    }}",
+                end, clear
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n    ")
+}
+
+/// Expression reading the value of a parameter
+fn transpile_value(parameter: &Parameter, opcode: i64, at: usize) -> String {
+    match parameter {
+        Parameter::Position(p) => format!("memRead({}n)", p),
+        Parameter::Immediate(v) => format!("{}n", v),
+        Parameter::Relative(o) => format!("memRead(relative({}n, {}, {}))", o, opcode, at),
+    }
+}
+
+/// Renders the instructions of a block as JavaScript statements
+struct Translation<'a> {
+    cfg: cfg::Translation<'a>,
+}
+
+impl Translation<'_> {
+    /// Statements storing `value` at the address of `to`
+    fn write(&self, to: &Parameter, value: &str, opcode: i64, at: usize, next: usize) -> String {
+        let leave = format!("pc = {}n; continue;", next);
+        match self.cfg.store(to, next) {
+            Store::Data(address) => format!("memWrite({}n, {});", address, value),
+            Store::Code {
+                address,
+                leave: false,
+                ..
+            } => format!(
+                "memWrite({}n, {}); invalidate({}n);",
+                address, value, address
+            ),
+            Store::Code { address, .. } => format!(
+                "memWrite({}n, {}); invalidate({}n); {}",
+                address, value, address, leave
+            ),
+            Store::Relative(o) => format!(
+                "const to = relative({}n, {}, {}); memWrite(to, {}); invalidate(to); \
+                 if (!valid[{}]) {{ {} }}",
+                o, opcode, at, value, self.cfg.index, leave
+            ),
+        }
+    }
+
+    fn arithmetic(
+        &self,
+        (n1, n2, to): (&Parameter, &Parameter, &Parameter),
+        operation: &str,
+        opcode: i64,
+        at: usize,
+        next: usize,
+    ) -> String {
+        format!(
+            "const n1 = {}; const n2 = {}; const v = {}; {}",
+            transpile_value(n1, opcode, at),
+            transpile_value(n2, opcode, at),
+            operation,
+            self.write(to, "v", opcode, at, next)
+        )
+    }
+
+    fn jump(&self, test: &Parameter, goto: &Parameter, condition: &str, at: usize) -> String {
+        let opcode = if condition == "!==" { 5 } else { 6 };
+        let taken = match self.cfg.target(goto) {
+            Some(g) => format!("pc = {}n; continue;", g),
+            None => format!(
+                "pc = target({}, {}, {}); continue;",
+                transpile_value(goto, opcode, at),
+                opcode,
+                at
+            ),
+        };
+        format!(
+            "if ({} {} 0n) {{ {} }}",
+            transpile_value(test, opcode, at),
+            condition,
+            taken
+        )
+    }
+
+    fn instruction(&self, instruction: &Instruction, at: usize, next: usize) -> String {
+        match instruction {
+            Instruction::Add { n1, n2, to } => self.arithmetic(
+                (n1, n2, to),
+                &format!("checked(n1 + n2, 1, {})", at),
+                1,
+                at,
+                next,
+            ),
+            Instruction::Multiply { n1, n2, to } => self.arithmetic(
+                (n1, n2, to),
+                &format!("checked(n1 * n2, 2, {})", at),
+                2,
+                at,
+                next,
+            ),
+            Instruction::Input { to } => format!(
+                "const v = input(yield); {}",
+                self.write(to, "v", 3, at, next)
+            ),
+            Instruction::Output { from } => format!("yield {};", transpile_value(from, 4, at)),
+            Instruction::JumpIfTrue { test, goto } => self.jump(test, goto, "!==", at),
+            Instruction::JumpIfFalse { test, goto } => self.jump(test, goto, "===", at),
+            Instruction::LessThan { n1, n2, to } => {
+                self.arithmetic((n1, n2, to), "n1 < n2 ? 1n : 0n", 7, at, next)
+            }
+            Instruction::Equals { n1, n2, to } => {
+                self.arithmetic((n1, n2, to), "n1 === n2 ? 1n : 0n", 8, at, next)
+            }
+            Instruction::AdjustRelativeBase { by } => format!(
+                "rb = checked({} + rb, 9, {});",
+                transpile_value(by, 9, at),
+                at
+            ),
+            Instruction::Halt | Instruction::End => "return;".to_owned(),
+        }
+    }
+
+    fn block(&self) -> String {
+        let mut result = format!(
+            "      case {}n:\n        if (!valid[{}]) break;\n",
+            self.cfg.block.start, self.cfg.index
+        );
+        for (at, instruction, next) in &self.cfg.block.instructions {
+            let statement = self.instruction(instruction, *at, *next);
+            // Statements declaring constants get their own scope
+            if statement.starts_with("const ") {
+                result.push_str(&format!("        {{ {} }}\n", statement));
+            } else {
+                result.push_str(&format!("        {}\n", statement));
+            }
+        }
+        match self.cfg.block.exit {
+            Exit::Next(next) | Exit::Jump(next) | Exit::Fallback(next) => {
+                result.push_str(&format!("        pc = {}n;\n        continue;\n", next))
+            }
+            Exit::Halt => (),
+        }
+        result
+    }
+}
+
+/// Translates the code reachable from `entry` into the cases of the `switch (pc)` state
+/// machine, along with the cases of `invalidate` and the number of blocks
+fn transpile_blocks(code: &Memory, entry: usize) -> (String, String, usize) {
+    let (blocks, covered) = cfg::blocks_and_coverage(code, entry);
+    let cases = cfg::translations(&blocks, &covered, code)
+        .map(|cfg| Translation { cfg }.block())
+        .collect::<Vec<String>>()
+        .join("");
+
+    (cases, transpile_invalidate(&covered), blocks.len())
+}
+
+/// Translates a program into the source of an equivalent JavaScript module
+///
+/// The module exports `machine`, a generator yielding the outputs of the program and
+/// `undefined` whenever it waits for an input to be passed to `next`, and `run`, which drives
+/// it with an array of inputs. Values are BigInts, and errors are thrown with the same messages
+/// as the interpreter's.
+pub fn transpile_js(code: Vec<i64>, input: Vec<i64>) -> Result<String, Error> {
+    let eval_results = interpreter::eval(code, input)?;
+    let output = transpile_output(&eval_results.output);
+
+    if eval_results.completed {
+        return Ok(format!(
+            "{}\n\nexport function* machine() {{\n  yield* OUTPUT;\n}}\n\n\
+             export function run(inputs = []) {{\n  return OUTPUT.slice();\n}}\n",
+            output
+        ));
+    }
+
+    let (blocks, invalidate, count) = transpile_blocks(&eval_results.code, eval_results.run_code);
+    Ok(MAIN
+        .replace("      // blocks\n", &blocks)
+        .replace("// invalidate", &invalidate)
+        .replace("// valid", &format!("const BLOCKS = {};", count))
+        .replace("// output", &output)
+        .replace("// code", &transpile_code(&eval_results.code))
+        .replace(
+            "// iterator",
+            &format!("const ENTRY = {}n;", eval_results.run_code),
+        )
+        .replace(
+            "// relative base",
+            &format!("const RELATIVE_BASE = {}n;", eval_results.relative_base),
+        ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::runtime::Memory;
+    use crate::transpiler::js::{transpile_blocks, transpile_code, transpile_js, transpile_output};
+    use std::{env, fs, process::Command};
+
+    #[test]
+    fn output() {
+        assert_eq!(
+            "const OUTPUT = [\n  1n, -2n,\n];",
+            transpile_output(&[1, -2])
+        );
+        assert_eq!("const OUTPUT = [];", transpile_output(&[]));
+    }
+
+    #[test]
+    fn code() {
+        let mut code = Memory::from(vec![1, i64::MIN]);
+        code.write(1 << 40, 4);
+        assert_eq!(
+            "const IMAGE = [\n  1n, -9223372036854775808n,\n];\n\
+             const SPARSE = [[1099511627776n, 4n]];",
+            transpile_code(&code)
+        );
+    }
+
+    #[test]
+    fn blocks() {
+        // 0: in [12]; 2: out [12]; 4: add [12], #-1, [12]; 8: jnz [12], #2; 11: hlt
+        let code = Memory::from(vec![3, 12, 4, 12, 1001, 12, -1, 12, 1005, 12, 2, 99, 0]);
+        let (cases, invalidate, count) = transpile_blocks(&code, 0);
+        assert_eq!(3, count);
+        assert!(cases.contains("case 2n:\n        if (!valid[1]) break;"));
+        assert!(cases.contains("yield memRead(12n);"));
+        assert!(cases.contains("{ const n1 = memRead(12n); const n2 = -1n; "));
+        assert!(cases.contains("if (memRead(12n) !== 0n) { pc = 2n; continue; }"));
+        assert!(cases.contains("pc = 11n;\n        continue;"));
+        assert!(invalidate.starts_with(
+            "if (address <= 1n) {\n      valid[0] = 0;\n      return;\n    }\n    \
+             if (address <= 10n) {\n      if (address >= 2n) { valid[1] = 0; }"
+        ));
+    }
+
+    /// Imports the module of a program in node and runs `script` against it
+    fn run_module(name: &str, code: Vec<i64>, script: &str) -> String {
+        let source = transpile_js(code, vec![]).unwrap();
+        let module =
+            env::temp_dir().join(format!("intcode_js_{}_{}.mjs", name, std::process::id()));
+        fs::write(&module, source).unwrap();
+
+        let script = format!(
+            "import {{ machine, run }} from {:?};\n{}",
+            module.to_str().unwrap(),
+            script
+        );
+        let output = Command::new("node")
+            .args(["--input-type=module", "-e", &script])
+            .output()
+            .expect("node is needed to run the emitted module");
+        fs::remove_file(&module).ok();
+
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    #[test]
+    fn emitted_module_runs() {
+        // Counts down from its input, then jumps out of bounds
+        let code = vec![3, 14, 4, 14, 1001, 14, -1, 14, 1005, 14, 2, 1105, 1, -1, 0];
+
+        // Runs the program until it fails or waits for more input, then steps through its
+        // machine by hand
+        let script = "try { run([3]); } catch (e) { console.log(e.message); }\n\
+                      console.log(run([]).length);\n\
+                      const m = machine();\n\
+                      console.log(m.next().value, m.next(2).value, m.next().value);\n";
+        assert_eq!(
+            "Jump target -1 out of bounds for opcode \"5\" at position 11\n0\nundefined 2n 1n\n",
+            run_module("countdown", code, script)
+        );
+    }
+
+    #[test]
+    fn jump_to_end() {
+        // Echoes its input, then jumps right past the end of memory to halt
+        let code = vec![3, 1, 4, 1, 5, 1, 7, 8];
+        assert_eq!(
+            "[ 5n ]\n",
+            run_module("end", code, "console.log(run([5n]));\n")
+        );
+    }
+}
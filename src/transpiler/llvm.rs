@@ -0,0 +1,533 @@
+use crate::{
+    error::Error,
+    interpreter::{self, Instruction, Parameter},
+    runtime::Memory,
+};
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::cfg::{self, coverage_ranges, Exit, Store};
+
+static MAIN: &str = include_str!("../../resources/main.ll");
+
+/// Constant array of bytes, escaping everything but printable ASCII
+fn bytes(text: &[u8]) -> String {
+    let mut result = format!("[{} x i8] c\"", text.len());
+    for byte in text {
+        match byte {
+            b'"' | b'\\' => result.push_str(&format!("\\{:02X}", byte)),
+            b' '..=b'~' => result.push(*byte as char),
+            _ => result.push_str(&format!("\\{:02X}", byte)),
+        }
+    }
+    result.push('"');
+    result
+}
+
+/// Global holding the output of the evaluated part of the program, along with its length
+fn transpile_output(output: &[i64], ascii: bool) -> (String, usize) {
+    let mut text = String::new();
+    for value in output {
+        match value {
+            0..=127 if ascii => text.push(*value as u8 as char),
+            _ => text.push_str(&format!("{}\n", value)),
+        }
+    }
+    (
+        format!(
+            "@output = private unnamed_addr constant {}",
+            bytes(text.as_bytes())
+        ),
+        text.len(),
+    )
+}
+
+/// Global holding the dense part of the memory image, and the statements loading the image
+fn transpile_code(code: &Memory) -> (String, String) {
+    let dense = code.dense();
+    let mut global = String::new();
+    let mut load = String::new();
+    if !dense.is_empty() {
+        let values: Vec<String> = dense.iter().map(|v| format!("i64 {}", v)).collect();
+        global = format!(
+            "@image = private unnamed_addr constant [{} x i64] [{}]\n",
+            dense.len(),
+            values.join(", ")
+        );
+        load = format!(
+            "  %dense = call ptr @allocate(ptr null, i64 {bytes})\n  \
+             call void @llvm.memcpy.p0.p0.i64(ptr %dense, ptr @image, i64 {bytes}, i1 false)\n  \
+             store ptr %dense, ptr @dense\n  \
+             store i64 {length}, ptr @dense_length\n  \
+             store i64 {length}, ptr @dense_capacity\n",
+            bytes = dense.len() * 8,
+            length = dense.len()
+        );
+    }
+    for (address, value) in code.sparse() {
+        load.push_str(&format!(
+            "  call void @mem_write(i64 {}, i64 {})\n",
+            address, value
+        ));
+    }
+    (global, load)
+}
+
+/// Constant pointer to the validity flag of a block
+fn valid(count: usize, index: usize) -> String {
+    format!(
+        "getelementptr inbounds ([{} x i8], ptr @valid, i64 0, i64 {})",
+        count, index
+    )
+}
+
+/// Body of `invalidate`, clearing the validity flags of the blocks covering an address
+fn transpile_invalidate(covered: &BTreeMap<usize, Vec<usize>>, count: usize) -> String {
+    let mut result = String::new();
+    for (range, (start, end, blocks)) in coverage_ranges(covered).iter().enumerate() {
+        result.push_str(&format!(
+            "  %end_{r} = icmp ule i64 %address, {}\n",
+            end,
+            r = range
+        ));
+        if *start == 0 {
+            result.push_str(&format!(
+                "  br i1 %end_{r}, label %clear_{r}, label %next_{r}\n",
+                r = range
+            ));
+        } else {
+            result.push_str(&format!(
+                "  br i1 %end_{r}, label %range_{r}, label %next_{r}\n\
+                 range_{r}:\n  \
+                 %start_{r} = icmp uge i64 %address, {}\n  \
+                 br i1 %start_{r}, label %clear_{r}, label %exit\n",
+                start,
+                r = range
+            ));
+        }
+        result.push_str(&format!("clear_{}:\n", range));
+        for block in blocks.iter() {
+            result.push_str(&format!("  store i8 0, ptr {}\n", valid(count, *block)));
+        }
+        result.push_str(&format!("  ret void\nnext_{}:\n", range));
+    }
+    result
+}
+
+/// Renders the instructions of a block as LLVM instructions
+struct Translation<'a> {
+    cfg: cfg::Translation<'a>,
+    /// Number of blocks in the validity table
+    count: usize,
+    /// Start of every translated block
+    starts: &'a BTreeSet<usize>,
+    lines: Vec<String>,
+}
+
+impl Translation<'_> {
+    fn emit(&mut self, line: String) {
+        self.lines.push(line);
+    }
+
+    /// Starts a new LLVM block
+    fn label(&mut self, name: &str) {
+        self.lines.push(format!("{}:", name));
+    }
+
+    /// Branches to the block starting at `address`, going through the dispatcher if it wasn't
+    /// translated
+    fn goto(&mut self, address: usize) {
+        if self.starts.contains(&address) {
+            self.emit(format!("  br label %block_{}", address));
+        } else {
+            self.emit(format!("  store i64 {}, ptr %pc", address));
+            self.emit("  br label %dispatch".to_owned());
+        }
+    }
+
+    /// Emits the address of a relative parameter
+    fn relative(&mut self, offset: isize, name: &str, opcode: i64, at: usize) -> String {
+        self.emit(format!("  %{}_rb = load i64, ptr %rb", name));
+        self.emit(format!(
+            "  %{name} = call i64 @relative(i64 %{name}_rb, i64 {}, i64 {}, i64 {})",
+            offset,
+            opcode,
+            at,
+            name = name
+        ));
+        format!("%{}", name)
+    }
+
+    /// Emits the read of a parameter, returning the operand holding its value
+    fn value(&mut self, parameter: &Parameter, name: &str, opcode: i64, at: usize) -> String {
+        let address = match parameter {
+            Parameter::Immediate(v) => return v.to_string(),
+            Parameter::Position(p) => p.to_string(),
+            Parameter::Relative(o) => self.relative(*o, &format!("{}_address", name), opcode, at),
+        };
+        self.emit(format!("  %{} = call i64 @mem_read(i64 {})", name, address));
+        format!("%{}", name)
+    }
+
+    /// Emits the store of `value` at the address of `to`
+    fn write(
+        &mut self,
+        to: &Parameter,
+        value: &str,
+        prefix: &str,
+        opcode: i64,
+        at: usize,
+        next: usize,
+    ) {
+        match self.cfg.store(to, next) {
+            Store::Data(address) => {
+                self.emit(format!(
+                    "  call void @mem_write(i64 {}, i64 {})",
+                    address, value
+                ));
+            }
+            Store::Code { address, leave, .. } => {
+                self.emit(format!(
+                    "  call void @mem_write(i64 {}, i64 {})",
+                    address, value
+                ));
+                self.emit(format!("  call void @invalidate(i64 {})", address));
+                if leave {
+                    self.emit(format!("  store i64 {}, ptr %pc", next));
+                    self.emit("  br label %dispatch".to_owned());
+                    self.label(&format!("{}_after", prefix));
+                }
+            }
+            Store::Relative(o) => {
+                let to = self.relative(o, &format!("{}_to", prefix), opcode, at);
+                self.emit(format!("  call void @mem_write(i64 {}, i64 {})", to, value));
+                self.emit(format!("  call void @invalidate(i64 {})", to));
+                self.emit(format!(
+                    "  %{}_flag = load i8, ptr {}",
+                    prefix,
+                    valid(self.count, self.cfg.index)
+                ));
+                self.emit(format!(
+                    "  %{p}_valid = icmp ne i8 %{p}_flag, 0",
+                    p = prefix
+                ));
+                self.emit(format!(
+                    "  br i1 %{p}_valid, label %{p}_after, label %{p}_leave",
+                    p = prefix
+                ));
+                self.label(&format!("{}_leave", prefix));
+                self.emit(format!("  store i64 {}, ptr %pc", next));
+                self.emit("  br label %dispatch".to_owned());
+                self.label(&format!("{}_after", prefix));
+            }
+        }
+    }
+
+    fn arithmetic(
+        &mut self,
+        (n1, n2, to): (&Parameter, &Parameter, &Parameter),
+        prefix: &str,
+        opcode: i64,
+        at: usize,
+        next: usize,
+    ) {
+        let n1 = self.value(n1, &format!("{}_n1", prefix), opcode, at);
+        let n2 = self.value(n2, &format!("{}_n2", prefix), opcode, at);
+        let result = format!("%{}_v", prefix);
+        match opcode {
+            1 | 2 => {
+                let function = if opcode == 1 {
+                    "checked_add"
+                } else {
+                    "checked_mul"
+                };
+                self.emit(format!(
+                    "  {} = call i64 @{}(i64 {}, i64 {}, i64 {}, i64 {})",
+                    result, function, n1, n2, opcode, at
+                ));
+            }
+            _ => {
+                let condition = if opcode == 7 { "slt" } else { "eq" };
+                self.emit(format!(
+                    "  %{}_c = icmp {} i64 {}, {}",
+                    prefix, condition, n1, n2
+                ));
+                self.emit(format!("  {} = zext i1 %{}_c to i64", result, prefix));
+            }
+        }
+        self.write(to, &result, prefix, opcode, at, next);
+    }
+
+    fn jump(&mut self, test: &Parameter, goto: &Parameter, opcode: i64, prefix: &str, at: usize) {
+        let test = self.value(test, &format!("{}_test", prefix), opcode, at);
+        let condition = if opcode == 5 { "ne" } else { "eq" };
+        self.emit(format!(
+            "  %{}_c = icmp {} i64 {}, 0",
+            prefix, condition, test
+        ));
+        self.emit(format!(
+            "  br i1 %{p}_c, label %{p}_taken, label %{p}_not_taken",
+            p = prefix
+        ));
+        self.label(&format!("{}_taken", prefix));
+        match self.cfg.target(goto) {
+            Some(g) => self.goto(g),
+            None => {
+                let goto = self.value(goto, &format!("{}_goto", prefix), opcode, at);
+                self.emit(format!(
+                    "  %{}_target = call i64 @target(i64 {}, i64 {}, i64 {})",
+                    prefix, goto, opcode, at
+                ));
+                self.emit(format!("  store i64 %{}_target, ptr %pc", prefix));
+                self.emit("  br label %dispatch".to_owned());
+            }
+        }
+        self.label(&format!("{}_not_taken", prefix));
+    }
+
+    fn instruction(&mut self, instruction: &Instruction, at: usize, next: usize) {
+        let prefix = format!("b{}_{}", self.cfg.block.start, at);
+        match instruction {
+            Instruction::Add { n1, n2, to } => self.arithmetic((n1, n2, to), &prefix, 1, at, next),
+            Instruction::Multiply { n1, n2, to } => {
+                self.arithmetic((n1, n2, to), &prefix, 2, at, next)
+            }
+            Instruction::Input { to } => {
+                self.emit(format!(
+                    "  %{}_read = call i1 @read_input(ptr %cell)",
+                    prefix
+                ));
+                self.emit(format!(
+                    "  br i1 %{p}_read, label %{p}_input, label %exit",
+                    p = prefix
+                ));
+                self.label(&format!("{}_input", prefix));
+                self.emit(format!("  %{}_v = load i64, ptr %cell", prefix));
+                self.write(to, &format!("%{}_v", prefix), &prefix, 3, at, next);
+            }
+            Instruction::Output { from } => {
+                let value = self.value(from, &format!("{}_v", prefix), 4, at);
+                self.emit(format!("  call void @write_output(i64 {})", value));
+            }
+            Instruction::JumpIfTrue { test, goto } => self.jump(test, goto, 5, &prefix, at),
+            Instruction::JumpIfFalse { test, goto } => self.jump(test, goto, 6, &prefix, at),
+            Instruction::LessThan { n1, n2, to } => {
+                self.arithmetic((n1, n2, to), &prefix, 7, at, next)
+            }
+            Instruction::Equals { n1, n2, to } => {
+                self.arithmetic((n1, n2, to), &prefix, 8, at, next)
+            }
+            Instruction::AdjustRelativeBase { by } => {
+                let by = self.value(by, &format!("{}_by", prefix), 9, at);
+                self.emit(format!("  %{}_rb = load i64, ptr %rb", prefix));
+                self.emit(format!(
+                    "  %{p}_base = call i64 @checked_add(i64 {}, i64 %{p}_rb, i64 9, i64 {})",
+                    by,
+                    at,
+                    p = prefix
+                ));
+                self.emit(format!("  store i64 %{}_base, ptr %rb", prefix));
+            }
+            Instruction::Halt | Instruction::End => self.emit("  br label %exit".to_owned()),
+        }
+    }
+
+    fn block(mut self) -> String {
+        let start = self.cfg.block.start;
+        self.label(&format!("block_{}", start));
+        self.emit(format!(
+            "  %block_{}_flag = load i8, ptr {}",
+            start,
+            valid(self.count, self.cfg.index)
+        ));
+        self.emit(format!(
+            "  %block_{s}_valid = icmp ne i8 %block_{s}_flag, 0",
+            s = start
+        ));
+        self.emit(format!(
+            "  br i1 %block_{s}_valid, label %block_{s}_body, label %block_{s}_invalid",
+            s = start
+        ));
+        self.label(&format!("block_{}_invalid", start));
+        self.emit(format!("  store i64 {}, ptr %pc", start));
+        self.emit("  br label %fallback".to_owned());
+        self.label(&format!("block_{}_body", start));
+
+        for (at, instruction, next) in &self.cfg.block.instructions {
+            self.instruction(instruction, *at, *next);
+        }
+        match self.cfg.block.exit {
+            Exit::Next(next) | Exit::Jump(next) | Exit::Fallback(next) => self.goto(next),
+            Exit::Halt => (),
+        }
+
+        let mut result = self.lines.join("\n");
+        result.push('\n');
+        result
+    }
+}
+
+/// Translates the code reachable from `entry` into LLVM blocks of the `run` function, along
+/// with the cases of the dispatcher, the parts of `invalidate` and the number of blocks
+fn transpile_blocks(code: &Memory, entry: usize) -> (String, String, String, usize) {
+    let (blocks, covered) = cfg::blocks_and_coverage(code, entry);
+    let count = blocks.len().max(1);
+    let starts: BTreeSet<usize> = blocks.iter().map(|b| b.start).collect();
+
+    let cases = starts
+        .iter()
+        .map(|s| format!("    i64 {}, label %block_{}\n", s, s))
+        .collect();
+    let translated = cfg::translations(&blocks, &covered, code)
+        .map(|cfg| {
+            Translation {
+                cfg,
+                count,
+                starts: &starts,
+                lines: Vec::new(),
+            }
+            .block()
+        })
+        .collect::<Vec<String>>()
+        .join("");
+
+    (
+        translated,
+        cases,
+        transpile_invalidate(&covered, count),
+        count,
+    )
+}
+
+/// Translates a program into textual LLVM IR
+///
+/// The module uses opaque pointers and only depends on the C library. Each basic block of the
+/// program becomes an LLVM block, and code that isn't known ahead of time is interpreted.
+pub fn transpile_llvm(code: Vec<i64>, input: Vec<i64>, ascii: bool) -> Result<String, Error> {
+    let eval_results = interpreter::eval(code, input)?;
+    let mut globals = format!("@ascii = internal constant i1 {}\n", ascii);
+    let mut main = String::new();
+    if !eval_results.output.is_empty() {
+        let (output, length) = transpile_output(&eval_results.output, ascii);
+        globals.push_str(&format!("{}\n", output));
+        main.push_str(&format!(
+            "  call void @print(ptr @output, i64 {})\n",
+            length
+        ));
+    }
+
+    let (blocks, cases, invalidate, count) = if eval_results.completed {
+        (String::new(), String::new(), String::new(), 1)
+    } else {
+        let (image, load) = transpile_code(&eval_results.code);
+        globals.push_str(&image);
+        main.push_str(&load);
+        main.push_str(&format!(
+            "  call void @run(i64 {}, i64 {})\n",
+            eval_results.run_code, eval_results.relative_base
+        ));
+        transpile_blocks(&eval_results.code, eval_results.run_code)
+    };
+    globals.push_str(&format!(
+        "@valid = internal global {}",
+        bytes(&vec![1; count])
+    ));
+
+    Ok(MAIN
+        .replace("; globals", &globals)
+        .replace("; invalidate\n", &invalidate)
+        .replace("; cases\n", &cases)
+        .replace("; blocks\n", &blocks)
+        .replace("; main\n", &main))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::runtime::Memory;
+    use crate::transpiler::llvm::{bytes, transpile_blocks, transpile_llvm};
+    use std::{
+        env, fs,
+        io::Write,
+        process::{Command, Stdio},
+    };
+
+    #[test]
+    fn constants() {
+        assert_eq!("[4 x i8] c\"a\\22\\0A\\01\"", bytes(b"a\"\n\x01"));
+    }
+
+    #[test]
+    fn blocks() {
+        // 0: in [12]; 2: out [12]; 4: add [12], #-1, [12]; 8: jnz [12], #2; 11: hlt
+        let code = Memory::from(vec![3, 12, 4, 12, 1001, 12, -1, 12, 1005, 12, 2, 99, 0]);
+        let (blocks, cases, invalidate, count) = transpile_blocks(&code, 0);
+        assert_eq!(3, count);
+        assert!(cases.contains("i64 2, label %block_2"));
+        assert!(blocks.contains("block_2:\n"));
+        assert!(blocks.contains("  call void @write_output(i64 %b2_2_v)"));
+        assert!(blocks.contains("  %b2_8_c = icmp ne i64 %b2_8_test, 0"));
+        assert!(blocks.contains("b2_8_taken:\n  br label %block_2"));
+        assert!(invalidate.starts_with(
+            "  %end_0 = icmp ule i64 %address, 1\n  \
+             br i1 %end_0, label %clear_0, label %next_0\n\
+             clear_0:\n"
+        ));
+        assert!(invalidate.contains(
+            "range_1:\n  %start_1 = icmp uge i64 %address, 2\n  \
+             br i1 %start_1, label %clear_1, label %exit\n"
+        ));
+    }
+
+    #[test]
+    fn emitted_module_runs() {
+        // Counts down from its input, then writes a halt over the invalid opcode at 17
+        let code = vec![
+            3, 19, 4, 19, 1001, 19, -1, 19, 1005, 19, 2, 109, 17, 21101, 0, 99, 0, 0, 0, 0,
+        ];
+        let source = transpile_llvm(code, vec![], false).unwrap();
+        let module = env::temp_dir().join(format!("intcode_llvm_{}.ll", std::process::id()));
+        fs::write(&module, source).unwrap();
+
+        // Builds it the same way as `ic compile` does without clang, and only LLVM 14 needs to
+        // be told about opaque pointers
+        let version = Command::new("llc")
+            .arg("--version")
+            .output()
+            .expect("llc is needed to build the emitted module");
+        let object = module.with_extension("o");
+        let mut llc = Command::new("llc");
+        llc.args(["-filetype=obj", "-relocation-model=pic", "-o"])
+            .arg(&object)
+            .arg(&module);
+        if String::from_utf8_lossy(&version.stdout).contains("version 14.") {
+            llc.arg("-opaque-pointers");
+        }
+        assert!(llc.status().unwrap().success());
+        let binary = module.with_extension("");
+        let cc = Command::new("cc")
+            .arg(&object)
+            .arg("-o")
+            .arg(&binary)
+            .status();
+        assert!(cc.unwrap().success());
+        fs::remove_file(&module).ok();
+        fs::remove_file(&object).ok();
+
+        let mut program = Command::new(&binary)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        program.stdin.take().unwrap().write_all(b"x\n3\n").unwrap();
+        let output = program.wait_with_output().unwrap();
+        fs::remove_file(&binary).ok();
+
+        assert!(output.status.success());
+        let output = String::from_utf8(output.stdout).unwrap();
+        let values: Vec<&str> = output
+            .lines()
+            .filter(|l| l.parse::<i64>().is_ok())
+            .collect();
+        assert_eq!(vec!["3", "2", "1"], values);
+        assert!(output.contains("Invalid"));
+    }
+}
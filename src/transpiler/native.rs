@@ -0,0 +1,1355 @@
+use crate::{
+    error::Error,
+    interpreter::{self, Instruction, Parameter},
+    runtime::Memory,
+};
+use std::collections::BTreeMap;
+
+use super::cfg::{self, coverage_ranges, Exit, Store};
+
+mod elf;
+mod x86;
+
+use x86::{
+    Alu, Assembler, Cond, Label, Mem,
+    Reg::{self, *},
+};
+
+/// Addresses below this are stored in a contiguous array, the same as in `Memory`
+const DENSE_CELLS: u32 = 1 << 20;
+/// Number of cells at higher addresses that can be written to
+const SPARSE_CELLS: u32 = 1 << 16;
+const BUFFER_SIZE: u32 = 0x1000;
+
+// Layout of the zeroed memory mapped at startup
+const CELLS: u32 = 0x1000_0000;
+const SPARSE_ADDRESSES: u32 = CELLS + DENSE_CELLS * 8;
+const SPARSE_VALUES: u32 = SPARSE_ADDRESSES + SPARSE_CELLS * 8;
+const OUTPUT: u32 = SPARSE_VALUES + SPARSE_CELLS * 8;
+const INPUT: u32 = OUTPUT + BUFFER_SIZE;
+const DIGITS: u32 = INPUT + BUFFER_SIZE;
+const OUTPUT_LENGTH: u32 = DIGITS + 32;
+const INPUT_POSITION: u32 = OUTPUT_LENGTH + 8;
+const INPUT_LENGTH: u32 = INPUT_POSITION + 8;
+const SPARSE_LENGTH: u32 = INPUT_LENGTH + 8;
+/// Whether part of an ASCII input line was already read
+const MID_LINE: u32 = SPARSE_LENGTH + 8;
+const SCRATCH: u32 = MID_LINE + 8;
+/// Modes and raw values of the parameters decoded by `step`
+const MODES: u32 = SCRATCH + 8;
+const WORDS: u32 = MODES + 3 * 8;
+/// Validity flags of the translated blocks
+const VALID: u32 = WORDS + 3 * 8;
+
+/// Number of cells of dense memory in use
+const LENGTH: Reg = R12;
+const RB: Reg = R13;
+/// Address of the next instruction when going through `dispatch`
+const PC: Reg = R14;
+
+fn cell(address: usize) -> Mem {
+    Mem::at(CELLS + address as u32 * 8)
+}
+
+fn valid(index: usize) -> Mem {
+    Mem::at(VALID + index as u32)
+}
+
+fn transpile_output(output: &[i64], ascii: bool) -> Vec<u8> {
+    let mut text = String::new();
+    for value in output {
+        match value {
+            0..=127 if ascii => text.push(*value as u8 as char),
+            _ => text.push_str(&format!("{}\n", value)),
+        }
+    }
+    text.into_bytes()
+}
+
+/// Entry points of the runtime
+///
+/// Routines take their arguments in `rdi`, `rsi`, `rdx` and `rcx`, return in `rax` and `rdx`,
+/// and preserve `rbx`, `rbp` and `r12` to `r15`, which hold the state of the machine.
+struct Runtime {
+    exit: Label,
+    flush: Label,
+    put_byte: Label,
+    put_bytes: Label,
+    write_decimal: Label,
+    write_output: Label,
+    read_byte: Label,
+    read_input: Label,
+    mem_get: Label,
+    mem_read: Label,
+    mem_write: Label,
+    check_target: Label,
+    invalidate: Label,
+    step: Label,
+    dispatch: Label,
+    fallback: Label,
+    halt: Label,
+    fail_overflow: Label,
+    fail_address: Label,
+    fail_jump: Label,
+    fail_opcode: Label,
+    fail_missing: Label,
+    fail_negative: Label,
+    fail_mode: Label,
+    fail_memory: Label,
+}
+
+impl Runtime {
+    fn new(asm: &mut Assembler) -> Self {
+        Self {
+            exit: asm.label(),
+            flush: asm.label(),
+            put_byte: asm.label(),
+            put_bytes: asm.label(),
+            write_decimal: asm.label(),
+            write_output: asm.label(),
+            read_byte: asm.label(),
+            read_input: asm.label(),
+            mem_get: asm.label(),
+            mem_read: asm.label(),
+            mem_write: asm.label(),
+            check_target: asm.label(),
+            invalidate: asm.label(),
+            step: asm.label(),
+            dispatch: asm.label(),
+            fallback: asm.label(),
+            halt: asm.label(),
+            fail_overflow: asm.label(),
+            fail_address: asm.label(),
+            fail_jump: asm.label(),
+            fail_opcode: asm.label(),
+            fail_missing: asm.label(),
+            fail_negative: asm.label(),
+            fail_mode: asm.label(),
+            fail_memory: asm.label(),
+        }
+    }
+
+    /// Emits everything but `invalidate` and the dispatch table
+    fn emit(&self, asm: &mut Assembler, ascii: bool) {
+        self.output(asm, ascii);
+        if ascii {
+            self.ascii_input(asm);
+        } else {
+            self.prompt_input(asm);
+        }
+        self.memory(asm);
+        self.step(asm);
+        self.failures(asm);
+    }
+
+    /// Buffered output to stdout
+    fn output(&self, asm: &mut Assembler, ascii: bool) {
+        asm.bind(self.exit);
+        asm.mov_imm(Rax, 60);
+        asm.syscall();
+
+        asm.bind(self.flush);
+        let done = asm.label();
+        asm.load(Rdx, Mem::at(OUTPUT_LENGTH));
+        asm.mov_imm(Rsi, OUTPUT as i64);
+        let write = asm.here();
+        asm.test(Rdx, Rdx);
+        asm.jcc(Cond::E, done);
+        asm.mov_imm(Rax, 1);
+        asm.mov_imm(Rdi, 1);
+        asm.syscall();
+        asm.test(Rax, Rax);
+        asm.jcc(Cond::Le, done);
+        asm.alu(Alu::Add, Rsi, Rax);
+        asm.alu(Alu::Sub, Rdx, Rax);
+        asm.jmp(write);
+        asm.bind(done);
+        asm.store_imm(Mem::at(OUTPUT_LENGTH), 0);
+        asm.ret();
+
+        asm.bind(self.put_byte);
+        let store = asm.label();
+        asm.load(Rax, Mem::at(OUTPUT_LENGTH));
+        asm.alu_imm(Alu::Cmp, Rax, BUFFER_SIZE as i32);
+        asm.jcc(Cond::B, store);
+        asm.push(Rdi);
+        asm.call(self.flush);
+        asm.pop(Rdi);
+        asm.mov_imm(Rax, 0);
+        asm.bind(store);
+        asm.mov(Rcx, Rdi);
+        asm.store_byte(Mem::base(Rax, OUTPUT as i32), Rcx);
+        asm.inc(Rax);
+        asm.store(Mem::at(OUTPUT_LENGTH), Rax);
+        asm.ret();
+
+        asm.bind(self.put_bytes);
+        let done = asm.label();
+        asm.test(Rdx, Rdx);
+        asm.jcc(Cond::E, done);
+        let next = asm.here();
+        asm.load_byte(Rdi, Mem::base(Rsi, 0));
+        asm.push(Rsi);
+        asm.push(Rdx);
+        asm.call(self.put_byte);
+        asm.pop(Rdx);
+        asm.pop(Rsi);
+        asm.inc(Rsi);
+        asm.dec(Rdx);
+        asm.jcc(Cond::Ne, next);
+        asm.bind(done);
+        asm.ret();
+
+        // Digits are written backwards from the end of the buffer
+        asm.bind(self.write_decimal);
+        let positive = asm.label();
+        let print = asm.label();
+        asm.mov(Rax, Rdi);
+        asm.mov(Rsi, Rdi);
+        asm.test(Rax, Rax);
+        asm.jcc(Cond::Ns, positive);
+        asm.neg(Rax);
+        asm.bind(positive);
+        asm.mov_imm(Rcx, 10);
+        asm.mov_imm(R8, (DIGITS + 32) as i64);
+        let digit = asm.here();
+        asm.mov_imm(Rdx, 0);
+        asm.div(Rcx);
+        asm.alu_imm(Alu::Add, Rdx, b'0' as i32);
+        asm.dec(R8);
+        asm.store_byte(Mem::base(R8, 0), Rdx);
+        asm.test(Rax, Rax);
+        asm.jcc(Cond::Ne, digit);
+        asm.test(Rsi, Rsi);
+        asm.jcc(Cond::Ns, print);
+        asm.dec(R8);
+        asm.store_byte_imm(Mem::base(R8, 0), b'-');
+        asm.bind(print);
+        asm.mov(Rsi, R8);
+        asm.mov_imm(Rdx, (DIGITS + 32) as i64);
+        asm.alu(Alu::Sub, Rdx, R8);
+        asm.jmp(self.put_bytes);
+
+        asm.bind(self.write_output);
+        if ascii {
+            let number = asm.label();
+            asm.alu_imm(Alu::Cmp, Rdi, 127);
+            asm.jcc(Cond::A, number);
+            asm.jmp(self.put_byte);
+            asm.bind(number);
+        }
+        asm.call(self.write_decimal);
+        asm.mov_imm(Rdi, b'\n' as i64);
+        asm.jmp(self.put_byte);
+    }
+
+    /// Buffered input from stdin, returning -1 at the end of input
+    fn read_byte(&self, asm: &mut Assembler) {
+        asm.bind(self.read_byte);
+        let have = asm.label();
+        let filled = asm.label();
+        asm.load(Rax, Mem::at(INPUT_POSITION));
+        asm.alu_load(Alu::Cmp, Rax, Mem::at(INPUT_LENGTH));
+        asm.jcc(Cond::B, have);
+        asm.mov_imm(Rax, 0);
+        asm.mov_imm(Rdi, 0);
+        asm.mov_imm(Rsi, INPUT as i64);
+        asm.mov_imm(Rdx, BUFFER_SIZE as i64);
+        asm.syscall();
+        asm.test(Rax, Rax);
+        asm.jcc(Cond::G, filled);
+        asm.mov_imm(Rax, -1);
+        asm.ret();
+        asm.bind(filled);
+        asm.store(Mem::at(INPUT_LENGTH), Rax);
+        asm.mov_imm(Rax, 0);
+        asm.bind(have);
+        asm.load_byte(Rcx, Mem::base(Rax, INPUT as i32));
+        asm.inc(Rax);
+        asm.store(Mem::at(INPUT_POSITION), Rax);
+        asm.mov(Rax, Rcx);
+        asm.ret();
+    }
+
+    /// Prompts for whole lines parsed as decimal numbers the same way Rust parses an `i64`,
+    /// returning the value in `rax` and 0 in `rdx` at the end of input
+    fn prompt_input(&self, asm: &mut Assembler) {
+        self.read_byte(asm);
+
+        // rbx holds the magnitude, r15 the number of digits and rbp flags
+        const NEGATIVE: i32 = 1;
+        const STARTED: i32 = 2;
+        const INVALID: i32 = 4;
+        const READ: i32 = 8;
+        let flag = |asm: &mut Assembler, flag: i32| {
+            asm.mov(Rcx, Rbp);
+            asm.alu_imm(Alu::And, Rcx, flag);
+        };
+
+        let prompt_text = asm.label();
+        let invalid_text = asm.label();
+        let read = asm.label();
+        let digit = asm.label();
+        let invalid = asm.label();
+        let eof = asm.label();
+        let line = asm.label();
+        let rejected = asm.label();
+        let positive = asm.label();
+        let done = asm.label();
+
+        asm.bind(self.read_input);
+        asm.push(Rbx);
+        asm.push(Rbp);
+        asm.push(R15);
+        let prompt = asm.here();
+        asm.mov_label(Rsi, prompt_text);
+        asm.mov_imm(Rdx, 2);
+        asm.call(self.put_bytes);
+        asm.call(self.flush);
+        asm.mov_imm(Rbx, 0);
+        asm.mov_imm(Rbp, 0);
+        asm.mov_imm(R15, 0);
+
+        asm.bind(read);
+        asm.call(self.read_byte);
+        asm.alu_imm(Alu::Cmp, Rax, -1);
+        asm.jcc(Cond::E, eof);
+        asm.alu_imm(Alu::Or, Rbp, READ);
+        asm.alu_imm(Alu::Cmp, Rax, b'\n' as i32);
+        asm.jcc(Cond::E, line);
+        asm.alu_imm(Alu::Cmp, Rax, b'\r' as i32);
+        asm.jcc(Cond::E, read);
+        // Only the first character can be a sign
+        flag(asm, STARTED);
+        asm.jcc(Cond::Ne, digit);
+        asm.alu_imm(Alu::Or, Rbp, STARTED);
+        asm.alu_imm(Alu::Cmp, Rax, b'+' as i32);
+        asm.jcc(Cond::E, read);
+        asm.alu_imm(Alu::Cmp, Rax, b'-' as i32);
+        asm.jcc(Cond::Ne, digit);
+        asm.alu_imm(Alu::Or, Rbp, NEGATIVE);
+        asm.jmp(read);
+
+        asm.bind(digit);
+        asm.alu_imm(Alu::Sub, Rax, b'0' as i32);
+        asm.alu_imm(Alu::Cmp, Rax, 9);
+        asm.jcc(Cond::A, invalid);
+        asm.mov(Rcx, Rax);
+        asm.inc(R15);
+        asm.mov(Rax, Rbx);
+        asm.mov_imm(R8, 10);
+        asm.mul(R8);
+        asm.jcc(Cond::B, invalid);
+        asm.alu(Alu::Add, Rax, Rcx);
+        asm.jcc(Cond::B, invalid);
+        // The magnitude of i64::MIN is one more than i64::MAX
+        asm.mov_imm(R8, i64::MAX);
+        flag(asm, NEGATIVE);
+        asm.alu(Alu::Add, R8, Rcx);
+        asm.alu(Alu::Cmp, Rax, R8);
+        asm.jcc(Cond::A, invalid);
+        asm.mov(Rbx, Rax);
+        asm.jmp(read);
+        asm.bind(invalid);
+        asm.alu_imm(Alu::Or, Rbp, INVALID);
+        asm.jmp(read);
+
+        asm.bind(eof);
+        flag(asm, READ);
+        asm.jcc(Cond::Ne, line);
+        asm.mov_imm(Rdx, 0);
+        asm.jmp(done);
+
+        asm.bind(line);
+        flag(asm, INVALID);
+        asm.jcc(Cond::Ne, rejected);
+        asm.test(R15, R15);
+        asm.jcc(Cond::E, rejected);
+        asm.mov_imm(Rdi, b'\n' as i64);
+        asm.call(self.put_byte);
+        asm.mov(Rax, Rbx);
+        flag(asm, NEGATIVE);
+        asm.jcc(Cond::E, positive);
+        asm.neg(Rax);
+        asm.bind(positive);
+        asm.mov_imm(Rdx, 1);
+        asm.bind(done);
+        asm.pop(R15);
+        asm.pop(Rbp);
+        asm.pop(Rbx);
+        asm.ret();
+
+        asm.bind(rejected);
+        asm.mov_label(Rsi, invalid_text);
+        asm.mov_imm(Rdx, 9);
+        asm.call(self.put_bytes);
+        asm.jmp(prompt);
+
+        asm.bind(prompt_text);
+        asm.bytes(b"> ");
+        asm.bind(invalid_text);
+        asm.bytes(b"Invalid\n\n");
+    }
+
+    /// Feeds lines of text one byte at a time followed by a newline, returning the byte in `rax`
+    /// and 0 in `rdx` at the end of input
+    fn ascii_input(&self, asm: &mut Assembler) {
+        self.read_byte(asm);
+
+        let read = asm.label();
+        let character = asm.label();
+        let eof = asm.label();
+        let none = asm.label();
+
+        asm.bind(self.read_input);
+        asm.alu_mem_imm(Alu::Cmp, Mem::at(MID_LINE), 0);
+        asm.jcc(Cond::Ne, read);
+        asm.call(self.flush);
+        asm.bind(read);
+        asm.call(self.read_byte);
+        asm.alu_imm(Alu::Cmp, Rax, -1);
+        asm.jcc(Cond::E, eof);
+        asm.alu_imm(Alu::Cmp, Rax, b'\r' as i32);
+        asm.jcc(Cond::Ne, character);
+        // A carriage return ending the line is dropped
+        asm.call(self.read_byte);
+        asm.alu_imm(Alu::Cmp, Rax, -1);
+        asm.jcc(Cond::E, eof);
+        asm.alu_imm(Alu::Cmp, Rax, b'\n' as i32);
+        asm.jcc(Cond::E, character);
+        asm.dec_mem(Mem::at(INPUT_POSITION));
+        asm.mov_imm(Rax, b'\r' as i64);
+        asm.bind(character);
+        asm.alu_imm(Alu::Cmp, Rax, b'\n' as i32);
+        asm.set(Cond::Ne, Rcx);
+        asm.store(Mem::at(MID_LINE), Rcx);
+        asm.mov_imm(Rdx, 1);
+        asm.ret();
+
+        // A last line without a line ending still gets one
+        asm.bind(eof);
+        asm.alu_mem_imm(Alu::Cmp, Mem::at(MID_LINE), 0);
+        asm.jcc(Cond::E, none);
+        asm.store_imm(Mem::at(MID_LINE), 0);
+        asm.mov_imm(Rax, b'\n' as i64);
+        asm.mov_imm(Rdx, 1);
+        asm.ret();
+        asm.bind(none);
+        asm.mov_imm(Rdx, 0);
+        asm.ret();
+    }
+
+    /// Memory accesses and jump target checks
+    fn memory(&self, asm: &mut Assembler) {
+        let search = |asm: &mut Assembler, found: Label, missing: Label| {
+            let next = asm.here();
+            asm.test(Rcx, Rcx);
+            asm.jcc(Cond::E, missing);
+            asm.dec(Rcx);
+            asm.alu_mem(Alu::Cmp, Mem::at(SPARSE_ADDRESSES).index(Rcx, 8), Rdi);
+            asm.jcc(Cond::Ne, next);
+            asm.jmp(found);
+        };
+
+        // Reads the cell at `rdi` into `rax`, setting `rdx` to 0 if it lies past the end of memory
+        asm.bind(self.mem_get);
+        let sparse = asm.label();
+        let found = asm.label();
+        let missing = asm.label();
+        asm.alu(Alu::Cmp, Rdi, LENGTH);
+        asm.jcc(Cond::Ae, sparse);
+        asm.load(Rax, Mem::at(CELLS).index(Rdi, 8));
+        asm.mov_imm(Rdx, 1);
+        asm.ret();
+        asm.bind(sparse);
+        asm.alu_imm(Alu::Cmp, Rdi, DENSE_CELLS as i32);
+        asm.jcc(Cond::B, missing);
+        asm.load(Rcx, Mem::at(SPARSE_LENGTH));
+        search(asm, found, missing);
+        asm.bind(found);
+        asm.load(Rax, Mem::at(SPARSE_VALUES).index(Rcx, 8));
+        asm.mov_imm(Rdx, 1);
+        asm.ret();
+        asm.bind(missing);
+        asm.mov_imm(Rax, 0);
+        asm.mov_imm(Rdx, 0);
+        asm.ret();
+
+        // Dense cells past the end of memory are still zeroed
+        asm.bind(self.mem_read);
+        asm.alu_imm(Alu::Cmp, Rdi, DENSE_CELLS as i32);
+        asm.jcc(Cond::Ae, self.mem_get);
+        asm.load(Rax, Mem::at(CELLS).index(Rdi, 8));
+        asm.ret();
+
+        // Writes `rsi` to the cell at `rdi`, preserving `rdi`
+        asm.bind(self.mem_write);
+        let sparse = asm.label();
+        let done = asm.label();
+        let found = asm.label();
+        let insert = asm.label();
+        asm.alu_imm(Alu::Cmp, Rdi, DENSE_CELLS as i32);
+        asm.jcc(Cond::Ae, sparse);
+        asm.store(Mem::at(CELLS).index(Rdi, 8), Rsi);
+        asm.alu(Alu::Cmp, Rdi, LENGTH);
+        asm.jcc(Cond::B, done);
+        asm.mov(LENGTH, Rdi);
+        asm.inc(LENGTH);
+        asm.bind(done);
+        asm.ret();
+        asm.bind(sparse);
+        asm.load(Rcx, Mem::at(SPARSE_LENGTH));
+        asm.mov(Rdx, Rcx);
+        search(asm, found, insert);
+        asm.bind(found);
+        asm.store(Mem::at(SPARSE_VALUES).index(Rcx, 8), Rsi);
+        asm.ret();
+        asm.bind(insert);
+        asm.alu_imm(Alu::Cmp, Rdx, SPARSE_CELLS as i32);
+        asm.jcc(Cond::Ae, self.fail_memory);
+        asm.store(Mem::at(SPARSE_ADDRESSES).index(Rdx, 8), Rdi);
+        asm.store(Mem::at(SPARSE_VALUES).index(Rdx, 8), Rsi);
+        asm.inc(Rdx);
+        asm.store(Mem::at(SPARSE_LENGTH), Rdx);
+        asm.ret();
+
+        // Fails unless `rdi` is the address of a cell or right past the end of memory, given the
+        // opcode in `rsi` and the position in `rdx`, preserving `rdi`
+        asm.bind(self.check_target);
+        let fail = asm.label();
+        let end = asm.label();
+        asm.test(Rdi, Rdi);
+        asm.jcc(Cond::S, fail);
+        asm.alu(Alu::Cmp, Rdi, LENGTH);
+        asm.jcc(Cond::E, end);
+        asm.push(Rdi);
+        asm.push(Rsi);
+        asm.push(Rdx);
+        asm.call(self.mem_get);
+        asm.mov(Rcx, Rdx);
+        asm.pop(Rdx);
+        asm.pop(Rsi);
+        asm.pop(Rdi);
+        asm.test(Rcx, Rcx);
+        asm.jcc(Cond::E, fail);
+        asm.bind(end);
+        asm.ret();
+        asm.bind(fail);
+        asm.jmp(self.fail_jump);
+    }
+
+    /// Interpreter executing the instruction at `pc`, returning 0 in `rax` once the program halts
+    /// or runs out of input
+    ///
+    /// While it runs, `r15` holds the position of the instruction, `rbx` the modes left to
+    /// decode and `rbp` the opcode.
+    fn step(&self, asm: &mut Assembler) {
+        let halted = asm.label();
+        let next = asm.label();
+        let invalid = asm.label();
+        let overflow = asm.label();
+        let out_of_bounds = asm.label();
+        let table = asm.label();
+        let handlers: Vec<Label> = (0..5).map(|_| asm.label()).collect();
+        let (arithmetic, input, output, jump, adjust) = (
+            handlers[0],
+            handlers[1],
+            handlers[2],
+            handlers[3],
+            handlers[4],
+        );
+
+        let decode = |asm: &mut Assembler, n: u32, destination: bool| {
+            let decoded = asm.label();
+            let nonzero = asm.label();
+            let bad_mode = asm.label();
+            let done = asm.label();
+            asm.mov(Rdi, PC);
+            asm.call(self.mem_get);
+            asm.test(Rdx, Rdx);
+            asm.jcc(Cond::Ne, decoded);
+            asm.mov_imm(Rdi, n as i64);
+            asm.mov(Rsi, Rbp);
+            asm.mov(Rdx, PC);
+            asm.jmp(self.fail_missing);
+
+            asm.bind(decoded);
+            asm.inc(PC);
+            asm.store(Mem::at(WORDS + n * 8), Rax);
+            asm.mov(R8, Rax);
+            asm.mov(Rax, Rbx);
+            asm.cqo();
+            asm.mov_imm(R9, 10);
+            asm.idiv(R9);
+            asm.mov(Rbx, Rax);
+            asm.store(Mem::at(MODES + n * 8), Rdx);
+            asm.test(Rdx, Rdx);
+            asm.jcc(Cond::Ne, nonzero);
+            asm.test(R8, R8);
+            asm.jcc(Cond::Ns, done);
+            asm.mov(Rdi, R8);
+            asm.mov_imm(Rsi, n as i64);
+            asm.mov(Rdx, Rbp);
+            asm.mov(Rcx, PC);
+            asm.jmp(self.fail_negative);
+
+            asm.bind(nonzero);
+            asm.alu_imm(Alu::Cmp, Rdx, 2);
+            asm.jcc(Cond::A, bad_mode);
+            if destination {
+                asm.alu_imm(Alu::Cmp, Rdx, 1);
+                asm.jcc(Cond::E, bad_mode);
+            }
+            asm.jmp(done);
+            asm.bind(bad_mode);
+            asm.mov(Rdi, Rdx);
+            asm.mov_imm(Rsi, n as i64);
+            asm.mov(Rdx, Rbp);
+            asm.mov(Rcx, PC);
+            asm.jmp(self.fail_mode);
+            asm.bind(done);
+        };
+        let address = |asm: &mut Assembler, n: u32| {
+            let done = asm.label();
+            asm.load(Rdi, Mem::at(WORDS + n * 8));
+            asm.alu_mem_imm(Alu::Cmp, Mem::at(MODES + n * 8), 2);
+            asm.jcc(Cond::Ne, done);
+            asm.alu(Alu::Add, Rdi, RB);
+            asm.jcc(Cond::O, overflow);
+            asm.test(Rdi, Rdi);
+            asm.jcc(Cond::S, out_of_bounds);
+            asm.bind(done);
+        };
+        let value = |asm: &mut Assembler, n: u32| {
+            let done = asm.label();
+            asm.load(Rax, Mem::at(WORDS + n * 8));
+            asm.alu_mem_imm(Alu::Cmp, Mem::at(MODES + n * 8), 1);
+            asm.jcc(Cond::E, done);
+            address(asm, n);
+            asm.call(self.mem_read);
+            asm.bind(done);
+        };
+        let write = |asm: &mut Assembler, n: u32| {
+            address(asm, n);
+            asm.load(Rsi, Mem::at(SCRATCH));
+            asm.call(self.mem_write);
+            asm.call(self.invalidate);
+            asm.jmp(next);
+        };
+
+        asm.bind(self.step);
+        asm.push(Rbx);
+        asm.push(Rbp);
+        asm.push(R15);
+        asm.mov(R15, PC);
+        asm.mov(Rdi, PC);
+        asm.call(self.mem_get);
+        asm.test(Rdx, Rdx);
+        asm.jcc(Cond::E, halted);
+        asm.inc(PC);
+        asm.cqo();
+        asm.mov_imm(Rcx, 100);
+        asm.idiv(Rcx);
+        asm.mov(Rbx, Rax);
+        asm.mov(Rbp, Rdx);
+        asm.alu_imm(Alu::Cmp, Rbp, 99);
+        asm.jcc(Cond::E, halted);
+        asm.mov(Rax, Rbp);
+        asm.dec(Rax);
+        asm.alu_imm(Alu::Cmp, Rax, 8);
+        asm.jcc(Cond::A, invalid);
+        asm.jmp_mem(Mem::label(table).index(Rax, 8));
+        asm.bind(invalid);
+        asm.mov(Rdi, Rbp);
+        asm.mov(Rsi, PC);
+        asm.jmp(self.fail_opcode);
+
+        asm.bind(arithmetic);
+        let (multiply, less_than, equals, store) =
+            (asm.label(), asm.label(), asm.label(), asm.label());
+        decode(asm, 0, false);
+        decode(asm, 1, false);
+        decode(asm, 2, true);
+        value(asm, 0);
+        asm.store(Mem::at(SCRATCH), Rax);
+        value(asm, 1);
+        asm.load(Rcx, Mem::at(SCRATCH));
+        asm.alu_imm(Alu::Cmp, Rbp, 1);
+        asm.jcc(Cond::Ne, multiply);
+        asm.alu(Alu::Add, Rcx, Rax);
+        asm.jcc(Cond::O, overflow);
+        asm.jmp(store);
+        asm.bind(multiply);
+        asm.alu_imm(Alu::Cmp, Rbp, 2);
+        asm.jcc(Cond::Ne, less_than);
+        asm.imul(Rcx, Rax);
+        asm.jcc(Cond::O, overflow);
+        asm.jmp(store);
+        asm.bind(less_than);
+        asm.alu_imm(Alu::Cmp, Rbp, 7);
+        asm.jcc(Cond::Ne, equals);
+        asm.alu(Alu::Cmp, Rcx, Rax);
+        asm.set(Cond::L, Rcx);
+        asm.jmp(store);
+        asm.bind(equals);
+        asm.alu(Alu::Cmp, Rcx, Rax);
+        asm.set(Cond::E, Rcx);
+        asm.bind(store);
+        asm.store(Mem::at(SCRATCH), Rcx);
+        write(asm, 2);
+
+        asm.bind(input);
+        decode(asm, 0, true);
+        asm.call(self.read_input);
+        asm.test(Rdx, Rdx);
+        asm.jcc(Cond::E, halted);
+        asm.store(Mem::at(SCRATCH), Rax);
+        write(asm, 0);
+
+        asm.bind(output);
+        decode(asm, 0, false);
+        value(asm, 0);
+        asm.mov(Rdi, Rax);
+        asm.call(self.write_output);
+        asm.jmp(next);
+
+        // Jumps if the test is non-zero for opcode 5, and if it is zero for opcode 6
+        asm.bind(jump);
+        decode(asm, 0, false);
+        decode(asm, 1, false);
+        value(asm, 0);
+        asm.test(Rax, Rax);
+        asm.set(Cond::Ne, Rax);
+        asm.alu_imm(Alu::Cmp, Rbp, 5);
+        asm.set(Cond::E, Rcx);
+        asm.alu(Alu::Cmp, Rax, Rcx);
+        asm.jcc(Cond::Ne, next);
+        value(asm, 1);
+        asm.mov(Rdi, Rax);
+        asm.mov(Rsi, Rbp);
+        asm.mov(Rdx, R15);
+        asm.call(self.check_target);
+        asm.mov(PC, Rdi);
+        asm.jmp(next);
+
+        asm.bind(adjust);
+        decode(asm, 0, false);
+        value(asm, 0);
+        asm.alu(Alu::Add, RB, Rax);
+        asm.jcc(Cond::O, overflow);
+
+        asm.bind(next);
+        asm.mov_imm(Rax, 1);
+        asm.pop(R15);
+        asm.pop(Rbp);
+        asm.pop(Rbx);
+        asm.ret();
+        asm.bind(halted);
+        asm.mov_imm(Rax, 0);
+        asm.pop(R15);
+        asm.pop(Rbp);
+        asm.pop(Rbx);
+        asm.ret();
+
+        asm.bind(overflow);
+        asm.mov(Rdi, Rbp);
+        asm.mov(Rsi, R15);
+        asm.jmp(self.fail_overflow);
+        asm.bind(out_of_bounds);
+        asm.mov(Rsi, Rbp);
+        asm.mov(Rdx, R15);
+        asm.jmp(self.fail_address);
+
+        asm.align(8);
+        asm.bind(table);
+        for handler in &[
+            arithmetic, arithmetic, input, output, jump, jump, arithmetic, arithmetic, adjust,
+        ] {
+            asm.address(*handler);
+        }
+    }
+
+    /// Prints a message, where each `{}` is replaced by one of the arguments in turn, then exits
+    /// with status 1
+    fn fail(&self, asm: &mut Assembler, label: Label, template: &str) {
+        let parts: Vec<&str> = template.split("{}").collect();
+        let arguments = [Rdi, Rsi, Rdx, Rcx];
+        let texts: Vec<Label> = parts.iter().map(|_| asm.label()).collect();
+
+        asm.bind(label);
+        for argument in arguments[..parts.len() - 1].iter().rev() {
+            asm.push(*argument);
+        }
+        for (n, part) in parts.iter().enumerate() {
+            if !part.is_empty() {
+                asm.mov_label(Rsi, texts[n]);
+                asm.mov_imm(Rdx, part.len() as i64);
+                asm.call(self.put_bytes);
+            }
+            if n + 1 < parts.len() {
+                asm.load(Rdi, Mem::base(Rsp, n as i32 * 8));
+                asm.call(self.write_decimal);
+            }
+        }
+        asm.mov_imm(Rdi, b'\n' as i64);
+        asm.call(self.put_byte);
+        asm.call(self.flush);
+        asm.mov_imm(Rdi, 1);
+        asm.jmp(self.exit);
+
+        for (text, part) in texts.iter().zip(&parts) {
+            asm.bind(*text);
+            asm.bytes(part.as_bytes());
+        }
+    }
+
+    /// Errors reported the same way as by the interpreter
+    fn failures(&self, asm: &mut Assembler) {
+        self.fail(
+            asm,
+            self.fail_overflow,
+            "Arithmetic overflow for opcode \"{}\" at position {}",
+        );
+        self.fail(
+            asm,
+            self.fail_address,
+            "Address {} out of bounds for opcode \"{}\" at position {}",
+        );
+        self.fail(
+            asm,
+            self.fail_jump,
+            "Jump target {} out of bounds for opcode \"{}\" at position {}",
+        );
+        self.fail(
+            asm,
+            self.fail_opcode,
+            "Invalid opcode \"{}\" at position {}",
+        );
+        self.fail(
+            asm,
+            self.fail_missing,
+            "Missing parameter {} for opcode \"{}\" at position {}",
+        );
+        self.fail(
+            asm,
+            self.fail_negative,
+            "Negative value {} for positional parameter {} for opcode \"{}\" at position {}",
+        );
+        self.fail(
+            asm,
+            self.fail_mode,
+            "Invalid parameter mode \"{}\" for parameter {} of opcode \"{}\" at position {}",
+        );
+        self.fail(asm, self.fail_memory, "Out of memory");
+    }
+
+    /// Clears the validity flags of the blocks covering the address in `rdi`
+    ///
+    /// Addresses that aren't covered are rejected through a byte map before looking for the
+    /// range they fall in.
+    fn invalidate(&self, asm: &mut Assembler, covered: &BTreeMap<usize, Vec<usize>>) {
+        let done = asm.label();
+        let map = asm.label();
+        let limit = covered.keys().last().map_or(0, |a| a + 1);
+
+        asm.bind(self.invalidate);
+        asm.mov_imm(Rcx, limit as i64);
+        asm.alu(Alu::Cmp, Rdi, Rcx);
+        asm.jcc(Cond::Ae, done);
+        if limit <= DENSE_CELLS as usize {
+            asm.cmp_byte_imm(Mem::label(map).index(Rdi, 1), 0);
+            asm.jcc(Cond::E, done);
+        }
+        for (start, end, blocks) in coverage_ranges(covered) {
+            let next = asm.label();
+            asm.mov_imm(Rcx, start as i64);
+            asm.alu(Alu::Cmp, Rdi, Rcx);
+            asm.jcc(Cond::B, done);
+            asm.mov_imm(Rcx, end as i64);
+            asm.alu(Alu::Cmp, Rdi, Rcx);
+            asm.jcc(Cond::A, next);
+            for block in blocks {
+                asm.store_byte_imm(valid(*block), 0);
+            }
+            asm.ret();
+            asm.bind(next);
+        }
+        asm.bind(done);
+        asm.ret();
+
+        if limit <= DENSE_CELLS as usize {
+            asm.bind(map);
+            let mut bytes = vec![0; limit];
+            for address in covered.keys() {
+                bytes[*address] = 1;
+            }
+            asm.bytes(&bytes);
+        }
+    }
+}
+
+/// Out of line code leaving a block
+enum Stub {
+    /// Reports an arithmetic overflow for an opcode at a position
+    Overflow(i64, usize),
+    /// Reports the negative address in `rdi` for an opcode at a position
+    Address(i64, usize),
+    /// Goes back through the dispatcher at the given address
+    Leave(usize),
+    /// Interprets the invalidated block starting at the given address
+    Invalid(usize),
+}
+
+/// Renders the instructions of a block as machine code
+struct Translation<'a> {
+    cfg: cfg::Translation<'a>,
+    /// Size of the image, past which writes may grow memory
+    image: usize,
+    runtime: &'a Runtime,
+    /// Labels of the translated blocks, by start address
+    starts: &'a BTreeMap<usize, Label>,
+    stubs: Vec<(Label, Stub)>,
+}
+
+impl Translation<'_> {
+    fn stub(&mut self, asm: &mut Assembler, stub: Stub) -> Label {
+        let label = asm.label();
+        self.stubs.push((label, stub));
+        label
+    }
+
+    /// Continues at an address, jumping straight to its block if it was translated
+    fn goto(&self, asm: &mut Assembler, address: usize) {
+        match self.starts.get(&address) {
+            Some(label) => asm.jmp(*label),
+            None => {
+                asm.mov_imm(PC, address as i64);
+                asm.jmp(self.runtime.dispatch);
+            }
+        }
+    }
+
+    /// Computes a relative address into `rdi`
+    fn relative(&mut self, asm: &mut Assembler, offset: isize, opcode: i64, at: usize) {
+        asm.mov(Rdi, RB);
+        if offset as i64 >= i32::MIN as i64 && offset as i64 <= i32::MAX as i64 {
+            asm.alu_imm(Alu::Add, Rdi, offset as i32);
+        } else {
+            asm.mov_imm(Rcx, offset as i64);
+            asm.alu(Alu::Add, Rdi, Rcx);
+        }
+        let overflow = self.stub(asm, Stub::Overflow(opcode, at));
+        asm.jcc(Cond::O, overflow);
+        asm.test(Rdi, Rdi);
+        let address = self.stub(asm, Stub::Address(opcode, at));
+        asm.jcc(Cond::S, address);
+    }
+
+    /// Loads the value of a parameter into `dst`, clobbering anything but `rbx`
+    fn value(
+        &mut self,
+        asm: &mut Assembler,
+        parameter: &Parameter,
+        dst: Reg,
+        opcode: i64,
+        at: usize,
+    ) {
+        match parameter {
+            Parameter::Immediate(v) => return asm.mov_imm(dst, *v),
+            Parameter::Position(p) if *p < DENSE_CELLS as usize => return asm.load(dst, cell(*p)),
+            Parameter::Position(p) => asm.mov_imm(Rdi, *p as i64),
+            Parameter::Relative(o) => self.relative(asm, *o, opcode, at),
+        }
+        asm.call(self.runtime.mem_read);
+        if dst != Rax {
+            asm.mov(dst, Rax);
+        }
+    }
+
+    /// Stores `rax` at the address of `to`
+    fn write(&mut self, asm: &mut Assembler, to: &Parameter, opcode: i64, at: usize, next: usize) {
+        let (address, blocks, leave) = match self.cfg.store(to, next) {
+            Store::Data(address) => (address, &[][..], false),
+            Store::Code {
+                address,
+                blocks,
+                leave,
+            } => (address, blocks, leave),
+            Store::Relative(o) => {
+                self.relative(asm, o, opcode, at);
+                asm.mov(Rsi, Rax);
+                asm.call(self.runtime.mem_write);
+                asm.call(self.runtime.invalidate);
+                asm.cmp_byte_imm(valid(self.cfg.index), 0);
+                let leave = self.stub(asm, Stub::Leave(next));
+                asm.jcc(Cond::E, leave);
+                return;
+            }
+        };
+        if address < DENSE_CELLS as usize {
+            asm.store(cell(address), Rax);
+            if address >= self.image {
+                asm.mov_imm(Rcx, address as i64 + 1);
+                asm.alu(Alu::Cmp, LENGTH, Rcx);
+                asm.cmov(Cond::B, LENGTH, Rcx);
+            }
+        } else {
+            asm.mov(Rsi, Rax);
+            asm.mov_imm(Rdi, address as i64);
+            asm.call(self.runtime.mem_write);
+        }
+        for block in blocks {
+            asm.store_byte_imm(valid(*block), 0);
+        }
+        if leave {
+            asm.mov_imm(PC, next as i64);
+            asm.jmp(self.runtime.dispatch);
+        }
+    }
+
+    fn arithmetic(
+        &mut self,
+        asm: &mut Assembler,
+        (n1, n2, to): (&Parameter, &Parameter, &Parameter),
+        opcode: i64,
+        at: usize,
+        next: usize,
+    ) {
+        self.value(asm, n1, Rbx, opcode, at);
+        self.value(asm, n2, Rax, opcode, at);
+        match opcode {
+            1 | 2 => {
+                if opcode == 1 {
+                    asm.alu(Alu::Add, Rax, Rbx);
+                } else {
+                    asm.imul(Rax, Rbx);
+                }
+                let overflow = self.stub(asm, Stub::Overflow(opcode, at));
+                asm.jcc(Cond::O, overflow);
+            }
+            7 => {
+                asm.alu(Alu::Cmp, Rbx, Rax);
+                asm.set(Cond::L, Rax);
+            }
+            _ => {
+                asm.alu(Alu::Cmp, Rbx, Rax);
+                asm.set(Cond::E, Rax);
+            }
+        }
+        self.write(asm, to, opcode, at, next);
+    }
+
+    fn jump(
+        &mut self,
+        asm: &mut Assembler,
+        test: &Parameter,
+        goto: &Parameter,
+        opcode: i64,
+        at: usize,
+    ) {
+        let skip = asm.label();
+        self.value(asm, test, Rax, opcode, at);
+        asm.test(Rax, Rax);
+        asm.jcc(if opcode == 5 { Cond::E } else { Cond::Ne }, skip);
+        match self.cfg.target(goto) {
+            Some(g) => self.goto(asm, g),
+            None => {
+                self.value(asm, goto, Rdi, opcode, at);
+                asm.mov_imm(Rsi, opcode);
+                asm.mov_imm(Rdx, at as i64);
+                asm.call(self.runtime.check_target);
+                asm.mov(PC, Rdi);
+                asm.jmp(self.runtime.dispatch);
+            }
+        }
+        asm.bind(skip);
+    }
+
+    fn instruction(
+        &mut self,
+        asm: &mut Assembler,
+        instruction: &Instruction,
+        at: usize,
+        next: usize,
+    ) {
+        match instruction {
+            Instruction::Add { n1, n2, to } => self.arithmetic(asm, (n1, n2, to), 1, at, next),
+            Instruction::Multiply { n1, n2, to } => self.arithmetic(asm, (n1, n2, to), 2, at, next),
+            Instruction::Input { to } => {
+                asm.call(self.runtime.read_input);
+                asm.test(Rdx, Rdx);
+                asm.jcc(Cond::E, self.runtime.halt);
+                self.write(asm, to, 3, at, next);
+            }
+            Instruction::Output { from } => {
+                self.value(asm, from, Rdi, 4, at);
+                asm.call(self.runtime.write_output);
+            }
+            Instruction::JumpIfTrue { test, goto } => self.jump(asm, test, goto, 5, at),
+            Instruction::JumpIfFalse { test, goto } => self.jump(asm, test, goto, 6, at),
+            Instruction::LessThan { n1, n2, to } => self.arithmetic(asm, (n1, n2, to), 7, at, next),
+            Instruction::Equals { n1, n2, to } => self.arithmetic(asm, (n1, n2, to), 8, at, next),
+            Instruction::AdjustRelativeBase { by } => {
+                self.value(asm, by, Rax, 9, at);
+                asm.alu(Alu::Add, RB, Rax);
+                let overflow = self.stub(asm, Stub::Overflow(9, at));
+                asm.jcc(Cond::O, overflow);
+            }
+            Instruction::Halt | Instruction::End => asm.jmp(self.runtime.halt),
+        }
+    }
+
+    fn block(mut self, asm: &mut Assembler) {
+        asm.bind(self.starts[&self.cfg.block.start]);
+        asm.cmp_byte_imm(valid(self.cfg.index), 0);
+        let invalid = self.stub(asm, Stub::Invalid(self.cfg.block.start));
+        asm.jcc(Cond::E, invalid);
+        for (at, instruction, next) in &self.cfg.block.instructions {
+            self.instruction(asm, instruction, *at, *next);
+        }
+        match self.cfg.block.exit {
+            Exit::Next(next) | Exit::Jump(next) | Exit::Fallback(next) => self.goto(asm, next),
+            Exit::Halt => (),
+        }
+
+        for (label, stub) in &self.stubs {
+            asm.bind(*label);
+            match stub {
+                Stub::Overflow(opcode, at) => {
+                    asm.mov_imm(Rdi, *opcode);
+                    asm.mov_imm(Rsi, *at as i64);
+                    asm.jmp(self.runtime.fail_overflow);
+                }
+                Stub::Address(opcode, at) => {
+                    asm.mov_imm(Rsi, *opcode);
+                    asm.mov_imm(Rdx, *at as i64);
+                    asm.jmp(self.runtime.fail_address);
+                }
+                Stub::Leave(next) => {
+                    asm.mov_imm(PC, *next as i64);
+                    asm.jmp(self.runtime.dispatch);
+                }
+                Stub::Invalid(start) => {
+                    asm.mov_imm(PC, *start as i64);
+                    asm.jmp(self.runtime.fallback);
+                }
+            }
+        }
+    }
+}
+
+/// Emits the dispatcher and the translated blocks, along with `invalidate`, returning the number
+/// of blocks
+fn transpile_blocks(asm: &mut Assembler, runtime: &Runtime, code: &Memory, entry: usize) -> usize {
+    let (blocks, covered) = cfg::blocks_and_coverage(code, entry);
+    let starts: BTreeMap<usize, Label> = blocks.iter().map(|b| (b.start, asm.label())).collect();
+    let table = asm.label();
+    let table_length = starts.keys().last().map_or(0, |s| s + 1);
+
+    // Addresses past the table or that don't start a block are interpreted
+    asm.bind(runtime.dispatch);
+    asm.mov_imm(Rax, table_length as i64);
+    asm.alu(Alu::Cmp, PC, Rax);
+    asm.jcc(Cond::Ae, runtime.fallback);
+    asm.jmp_mem(Mem::label(table).index(PC, 8));
+    asm.bind(runtime.fallback);
+    asm.call(runtime.step);
+    asm.test(Rax, Rax);
+    asm.jcc(Cond::Ne, runtime.dispatch);
+    asm.bind(runtime.halt);
+    asm.call(runtime.flush);
+    asm.mov_imm(Rdi, 0);
+    asm.jmp(runtime.exit);
+
+    for cfg in cfg::translations(&blocks, &covered, code) {
+        Translation {
+            cfg,
+            image: code.dense().len(),
+            runtime,
+            starts: &starts,
+            stubs: Vec::new(),
+        }
+        .block(asm);
+    }
+    runtime.invalidate(asm, &covered);
+
+    asm.align(8);
+    asm.bind(table);
+    for address in 0..table_length {
+        asm.address(*starts.get(&address).unwrap_or(&runtime.fallback));
+    }
+    blocks.len()
+}
+
+/// Translates a program into a static Linux x86-64 executable
+///
+/// The executable only uses system calls, so it runs without any toolchain or C library, and
+/// behaves like the one emitted by `transpile`. Writes to addresses past the first 2^20 are
+/// limited to 2^16 distinct cells.
+pub fn transpile_native(code: Vec<i64>, input: Vec<i64>, ascii: bool) -> Result<Vec<u8>, Error> {
+    let eval_results = interpreter::eval(code, input)?;
+    let output = transpile_output(&eval_results.output, ascii);
+    let code = &eval_results.code;
+    let image = &code.dense()[..code.dense().len().min(DENSE_CELLS as usize)];
+
+    let mut asm = Assembler::new();
+    let runtime = Runtime::new(&mut asm);
+    let text = asm.label();
+    let data = asm.label();
+
+    if !output.is_empty() {
+        asm.mov_label(Rsi, text);
+        asm.mov_imm(Rdx, output.len() as i64);
+        asm.call(runtime.put_bytes);
+    }
+    let count = if eval_results.completed {
+        asm.jmp(runtime.halt);
+        runtime.emit(&mut asm, ascii);
+        transpile_blocks(&mut asm, &runtime, &Memory::from(vec![]), 0)
+    } else {
+        if !image.is_empty() {
+            asm.mov_label(Rsi, data);
+            asm.mov_imm(Rdi, CELLS as i64);
+            asm.mov_imm(Rcx, image.len() as i64);
+            asm.rep_movsq();
+        }
+        asm.mov_imm(LENGTH, image.len() as i64);
+        let overflow = code.dense()[image.len()..]
+            .iter()
+            .enumerate()
+            .map(|(a, v)| (a + image.len(), *v));
+        for (address, value) in overflow.chain(code.sparse()) {
+            asm.mov_imm(Rdi, address as i64);
+            asm.mov_imm(Rsi, value);
+            asm.call(runtime.mem_write);
+        }
+        let valid_flags = asm.label();
+        asm.jmp(valid_flags);
+
+        runtime.emit(&mut asm, ascii);
+        let count = transpile_blocks(&mut asm, &runtime, code, eval_results.run_code);
+        asm.bind(valid_flags);
+        asm.mov_imm(Rdi, VALID as i64);
+        asm.mov_imm(Rcx, count as i64);
+        asm.mov_imm(Rax, 1);
+        asm.rep_stosb();
+        asm.mov_imm(RB, eval_results.relative_base as i64);
+        asm.mov_imm(PC, eval_results.run_code as i64);
+        asm.jmp(runtime.dispatch);
+        count
+    };
+
+    asm.bind(text);
+    asm.bytes(&output);
+    asm.align(8);
+    asm.bind(data);
+    if !eval_results.completed {
+        for value in image {
+            asm.quad(*value);
+        }
+    }
+
+    let machine_code = asm.finish(elf::BASE + elf::HEADERS);
+    let bss_size = VALID - CELLS + count as u32;
+    Ok(elf::executable(
+        &machine_code,
+        0,
+        CELLS as u64,
+        bss_size as u64,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::transpiler::native::{transpile_native, transpile_output};
+
+    #[test]
+    fn output() {
+        assert_eq!(b"1\n-2\n".to_vec(), transpile_output(&[1, -2], false));
+        assert_eq!(
+            b"H\x01\n1234\n".to_vec(),
+            transpile_output(&[72, 1, 10, 1234], true)
+        );
+    }
+
+    #[test]
+    fn completed_program() {
+        let elf = transpile_native(vec![104, 42, 99], vec![], false).unwrap();
+        assert_eq!(b"\x7fELF", &elf[..4]);
+        assert!(elf.windows(3).any(|w| w == b"42\n"));
+    }
+
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    mod linux {
+        use crate::transpiler::native::transpile_native;
+        use std::{
+            env, fs,
+            io::Write,
+            os::unix::fs::PermissionsExt,
+            process::{Command, Output, Stdio},
+        };
+
+        fn run(name: &str, code: Vec<i64>, ascii: bool, input: &[u8]) -> Output {
+            let elf = transpile_native(code, vec![], ascii).unwrap();
+            let binary =
+                env::temp_dir().join(format!("intcode_native_{}_{}", name, std::process::id()));
+            fs::write(&binary, elf).unwrap();
+            fs::set_permissions(&binary, fs::Permissions::from_mode(0o755)).unwrap();
+
+            let mut program = Command::new(&binary)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .spawn()
+                .unwrap();
+            program.stdin.take().unwrap().write_all(input).unwrap();
+            let output = program.wait_with_output().unwrap();
+            fs::remove_file(&binary).ok();
+            output
+        }
+
+        #[test]
+        fn emitted_program_runs() {
+            // Counts down from its input, then jumps out of bounds
+            let code = vec![3, 14, 4, 14, 1001, 14, -1, 14, 1005, 14, 2, 1105, 1, -1, 0];
+            let output = run("countdown", code, false, b"x\n3\n");
+
+            assert_eq!(Some(1), output.status.code());
+            let output = String::from_utf8(output.stdout).unwrap();
+            let values: Vec<&str> = output
+                .lines()
+                .filter(|l| l.parse::<i64>().is_ok())
+                .collect();
+            assert_eq!(vec!["3", "2", "1"], values);
+            assert!(output.contains("Invalid"));
+            assert!(
+                output.ends_with("Jump target -1 out of bounds for opcode \"5\" at position 11\n")
+            );
+        }
+
+        #[test]
+        fn jump_to_end() {
+            // Echoes its input, then jumps right past the end of memory to halt
+            let output = run("end", vec![3, 1, 4, 1, 5, 1, 7, 8], false, b"5\n");
+            assert!(output.status.success());
+            assert!(String::from_utf8(output.stdout).unwrap().ends_with("5\n"));
+        }
+
+        #[test]
+        fn relative_and_ascii() {
+            // Outputs a copy of itself
+            let quine = vec![
+                109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99,
+            ];
+            let output = run("quine", quine.clone(), false, b"");
+            assert!(output.status.success());
+            let expected: String = quine.iter().map(|v| format!("{}\n", v)).collect();
+            assert_eq!(expected, String::from_utf8(output.stdout).unwrap());
+
+            // Echoes its input until it reads a full stop
+            let echo = vec![3, 13, 4, 13, 1008, 13, 46, 14, 1006, 14, 0, 99, 0, 0, 0];
+            let output = run("echo", echo, true, b"ab.\r\n");
+            assert!(output.status.success());
+            assert_eq!(b"ab.".to_vec(), output.stdout);
+        }
+    }
+}
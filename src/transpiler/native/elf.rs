@@ -0,0 +1,85 @@
+/// Address the executable is loaded at
+pub(super) const BASE: u64 = 0x40_0000;
+
+const PROGRAM_HEADERS: u16 = 3;
+
+/// Size of the ELF header and of the program headers, which the code follows
+pub(super) const HEADERS: u64 = 64 + 56 * PROGRAM_HEADERS as u64;
+
+const PT_LOAD: u32 = 1;
+const PT_GNU_STACK: u32 = 0x6474_e551;
+
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+fn program_header(
+    result: &mut Vec<u8>,
+    kind: u32,
+    flags: u32,
+    (address, file_size, memory_size): (u64, u64, u64),
+    alignment: u64,
+) {
+    result.extend_from_slice(&kind.to_le_bytes());
+    result.extend_from_slice(&flags.to_le_bytes());
+    result.extend_from_slice(&0u64.to_le_bytes());
+    result.extend_from_slice(&address.to_le_bytes());
+    result.extend_from_slice(&address.to_le_bytes());
+    result.extend_from_slice(&file_size.to_le_bytes());
+    result.extend_from_slice(&memory_size.to_le_bytes());
+    result.extend_from_slice(&alignment.to_le_bytes());
+}
+
+/// Builds a static Linux x86-64 executable
+///
+/// The code is mapped read-only right after the headers and entered at offset `entry`, and
+/// `bss_size` bytes of zeroed writable memory are mapped at `bss`.
+pub(super) fn executable(code: &[u8], entry: u64, bss: u64, bss_size: u64) -> Vec<u8> {
+    let mut result = vec![0x7f, b'E', b'L', b'F', 2, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    // Executable, x86-64, version 1
+    result.extend_from_slice(&2u16.to_le_bytes());
+    result.extend_from_slice(&0x3eu16.to_le_bytes());
+    result.extend_from_slice(&1u32.to_le_bytes());
+    result.extend_from_slice(&(BASE + HEADERS + entry).to_le_bytes());
+    // Program headers right after this one, and no section headers
+    result.extend_from_slice(&64u64.to_le_bytes());
+    result.extend_from_slice(&0u64.to_le_bytes());
+    result.extend_from_slice(&0u32.to_le_bytes());
+    for size in &[64u16, 56, PROGRAM_HEADERS, 64, 0, 0] {
+        result.extend_from_slice(&size.to_le_bytes());
+    }
+
+    let size = HEADERS + code.len() as u64;
+    program_header(
+        &mut result,
+        PT_LOAD,
+        PF_R | PF_X,
+        (BASE, size, size),
+        0x1000,
+    );
+    program_header(
+        &mut result,
+        PT_LOAD,
+        PF_R | PF_W,
+        (bss, 0, bss_size),
+        0x1000,
+    );
+    program_header(&mut result, PT_GNU_STACK, PF_R | PF_W, (0, 0, 0), 16);
+
+    result.extend_from_slice(code);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{executable, BASE, HEADERS};
+
+    #[test]
+    fn headers() {
+        let elf = executable(&[0xc3], 0, 0x1000_0000, 0x1000);
+        assert_eq!(HEADERS as usize + 1, elf.len());
+        assert_eq!(b"\x7fELF", &elf[..4]);
+        assert_eq!((BASE + HEADERS).to_le_bytes(), elf[24..32]);
+        assert_eq!(0xc3, elf[HEADERS as usize]);
+    }
+}
@@ -0,0 +1,538 @@
+/// General purpose registers, numbered as in their encoding
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Reg {
+    Rax,
+    Rcx,
+    Rdx,
+    Rbx,
+    Rsp,
+    Rbp,
+    Rsi,
+    Rdi,
+    R8,
+    R9,
+    R10,
+    R11,
+    R12,
+    R13,
+    R14,
+    R15,
+}
+
+impl Reg {
+    fn low(self) -> u8 {
+        self as u8 & 7
+    }
+
+    fn high(self) -> bool {
+        self as u8 >= 8
+    }
+}
+
+/// Condition codes, numbered as in the encoding of `jcc`, `setcc` and `cmovcc`
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Cond {
+    O,
+    No,
+    B,
+    Ae,
+    E,
+    Ne,
+    Be,
+    A,
+    S,
+    Ns,
+    P,
+    Np,
+    L,
+    Ge,
+    Le,
+    G,
+}
+
+/// Two operand arithmetic and logic instructions, numbered as in their encoding
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Alu {
+    Add,
+    Or,
+    Adc,
+    Sbb,
+    And,
+    Sub,
+    Xor,
+    Cmp,
+}
+
+/// A position in the code, bound once the instructions it points to are emitted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct Label(usize);
+
+/// Memory operand `[base + index * scale + disp]`, `disp` being relative to `label` if any
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Mem {
+    base: Option<Reg>,
+    index: Option<(Reg, u8)>,
+    disp: i32,
+    label: Option<Label>,
+}
+
+impl Mem {
+    /// Absolute address
+    pub fn at(address: u32) -> Self {
+        Self {
+            base: None,
+            index: None,
+            disp: address as i32,
+            label: None,
+        }
+    }
+
+    /// Absolute address of a label
+    pub fn label(label: Label) -> Self {
+        Self {
+            label: Some(label),
+            ..Self::at(0)
+        }
+    }
+
+    /// Address held by a register, plus a displacement
+    pub fn base(base: Reg, disp: i32) -> Self {
+        Self {
+            base: Some(base),
+            ..Self::at(disp as u32)
+        }
+    }
+
+    /// Adds a register scaled by 1, 2, 4 or 8 to the address
+    pub fn index(self, index: Reg, scale: u8) -> Self {
+        assert!(index != Reg::Rsp, "rsp can't be used as an index");
+        Self {
+            index: Some((index, scale)),
+            ..self
+        }
+    }
+}
+
+/// A field that can only be filled in once labels are bound
+enum Fixup {
+    /// 32 bit offset from the end of the field
+    Relative(usize, Label),
+    /// 32 bit absolute address, added to the value already in the field
+    Absolute32(usize, Label),
+    /// 64 bit absolute address
+    Absolute64(usize, Label),
+}
+
+/// Encodes instructions into machine code, resolving labels once everything is emitted
+#[derive(Default)]
+pub(super) struct Assembler {
+    code: Vec<u8>,
+    labels: Vec<Option<usize>>,
+    fixups: Vec<Fixup>,
+}
+
+impl Assembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn label(&mut self) -> Label {
+        self.labels.push(None);
+        Label(self.labels.len() - 1)
+    }
+
+    /// Binds a label to the current position
+    pub fn bind(&mut self, label: Label) {
+        assert!(self.labels[label.0].is_none(), "label bound twice");
+        self.labels[label.0] = Some(self.code.len());
+    }
+
+    /// Creates a label bound to the current position
+    pub fn here(&mut self) -> Label {
+        let label = self.label();
+        self.bind(label);
+        label
+    }
+
+    /// Raw data
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.code.extend_from_slice(bytes);
+    }
+
+    pub fn quad(&mut self, value: i64) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    /// 64 bit absolute address of a label, as data
+    pub fn address(&mut self, label: Label) {
+        self.fixups.push(Fixup::Absolute64(self.code.len(), label));
+        self.quad(0);
+    }
+
+    /// Pads the code with `int3` up to a multiple of `alignment`
+    pub fn align(&mut self, alignment: usize) {
+        while !self.code.len().is_multiple_of(alignment) {
+            self.code.push(0xcc);
+        }
+    }
+
+    fn rex(&mut self, w: bool, reg: u8, index: Option<Reg>, base: Option<Reg>) {
+        let rex = (w as u8) << 3
+            | ((reg >= 8) as u8) << 2
+            | (index.is_some_and(Reg::high) as u8) << 1
+            | base.is_some_and(Reg::high) as u8;
+        if rex != 0 {
+            self.code.push(0x40 | rex);
+        }
+    }
+
+    fn disp32(&mut self, disp: i32, label: Option<Label>) {
+        if let Some(label) = label {
+            self.fixups.push(Fixup::Absolute32(self.code.len(), label));
+        }
+        self.bytes(&disp.to_le_bytes());
+    }
+
+    /// Instruction with a register (or opcode extension) and a memory operand
+    fn op_mem(&mut self, w: bool, opcode: &[u8], reg: u8, mem: Mem) {
+        self.rex(w, reg, mem.index.map(|(i, _)| i), mem.base);
+        self.bytes(opcode);
+        let reg = (reg & 7) << 3;
+        let scale = |s: u8| match s {
+            1 => 0,
+            2 => 1,
+            4 => 2,
+            8 => 3,
+            _ => panic!("invalid scale {}", s),
+        };
+        match (mem.base, mem.index) {
+            (None, None) => self.bytes(&[0x04 | reg, 0x25]),
+            (None, Some((i, s))) => self.bytes(&[0x04 | reg, scale(s) << 6 | i.low() << 3 | 5]),
+            (Some(b), None) if b.low() == 4 => self.bytes(&[0x84 | reg, 0x24]),
+            (Some(b), None) => self.bytes(&[0x80 | reg | b.low()]),
+            (Some(b), Some((i, s))) => {
+                self.bytes(&[0x84 | reg, scale(s) << 6 | i.low() << 3 | b.low()])
+            }
+        }
+        self.disp32(mem.disp, mem.label);
+    }
+
+    /// Instruction with a register (or opcode extension) and a register operand
+    fn op_reg(&mut self, w: bool, opcode: &[u8], reg: u8, rm: Reg) {
+        self.rex(w, reg, None, Some(rm));
+        self.bytes(opcode);
+        self.code.push(0xc0 | (reg & 7) << 3 | rm.low());
+    }
+
+    pub fn mov(&mut self, dst: Reg, src: Reg) {
+        self.op_reg(true, &[0x89], src as u8, dst);
+    }
+
+    /// Loads a constant, using the shortest encoding
+    pub fn mov_imm(&mut self, dst: Reg, imm: i64) {
+        if imm >= 0 && imm <= u32::MAX as i64 {
+            self.rex(false, 0, None, Some(dst));
+            self.code.push(0xb8 | dst.low());
+            self.bytes(&(imm as u32).to_le_bytes());
+        } else if imm >= i32::MIN as i64 && imm <= i32::MAX as i64 {
+            self.op_reg(true, &[0xc7], 0, dst);
+            self.bytes(&(imm as i32).to_le_bytes());
+        } else {
+            self.rex(true, 0, None, Some(dst));
+            self.code.push(0xb8 | dst.low());
+            self.quad(imm);
+        }
+    }
+
+    /// Loads the absolute address of a label
+    pub fn mov_label(&mut self, dst: Reg, label: Label) {
+        self.rex(false, 0, None, Some(dst));
+        self.code.push(0xb8 | dst.low());
+        self.disp32(0, Some(label));
+    }
+
+    pub fn load(&mut self, dst: Reg, mem: Mem) {
+        self.op_mem(true, &[0x8b], dst as u8, mem);
+    }
+
+    pub fn store(&mut self, mem: Mem, src: Reg) {
+        self.op_mem(true, &[0x89], src as u8, mem);
+    }
+
+    /// Stores the low byte of `rax`, `rcx`, `rdx` or `rbx`
+    pub fn store_byte(&mut self, mem: Mem, src: Reg) {
+        assert!(
+            (src as u8) < 4,
+            "only the low byte of a, b, c and d is encodable"
+        );
+        self.op_mem(false, &[0x88], src as u8, mem);
+    }
+
+    pub fn store_byte_imm(&mut self, mem: Mem, imm: u8) {
+        self.op_mem(false, &[0xc6], 0, mem);
+        self.code.push(imm);
+    }
+
+    pub fn store_imm(&mut self, mem: Mem, imm: i32) {
+        self.op_mem(true, &[0xc7], 0, mem);
+        self.bytes(&imm.to_le_bytes());
+    }
+
+    /// Loads a byte, zero-extended
+    pub fn load_byte(&mut self, dst: Reg, mem: Mem) {
+        self.op_mem(false, &[0x0f, 0xb6], dst as u8, mem);
+    }
+
+    pub fn cmp_byte_imm(&mut self, mem: Mem, imm: u8) {
+        self.op_mem(false, &[0x80], Alu::Cmp as u8, mem);
+        self.code.push(imm);
+    }
+
+    /// `dst = dst op src`
+    pub fn alu(&mut self, op: Alu, dst: Reg, src: Reg) {
+        self.op_reg(true, &[(op as u8) << 3 | 1], src as u8, dst);
+    }
+
+    /// `dst = dst op imm`
+    pub fn alu_imm(&mut self, op: Alu, dst: Reg, imm: i32) {
+        self.op_reg(true, &[0x81], op as u8, dst);
+        self.bytes(&imm.to_le_bytes());
+    }
+
+    /// `dst = dst op [mem]`
+    pub fn alu_load(&mut self, op: Alu, dst: Reg, mem: Mem) {
+        self.op_mem(true, &[(op as u8) << 3 | 3], dst as u8, mem);
+    }
+
+    /// `[mem] = [mem] op src`, or only a comparison for `Cmp`
+    pub fn alu_mem(&mut self, op: Alu, mem: Mem, src: Reg) {
+        self.op_mem(true, &[(op as u8) << 3 | 1], src as u8, mem);
+    }
+
+    /// `[mem] = [mem] op imm`, or only a comparison for `Cmp`
+    pub fn alu_mem_imm(&mut self, op: Alu, mem: Mem, imm: i32) {
+        self.op_mem(true, &[0x81], op as u8, mem);
+        self.bytes(&imm.to_le_bytes());
+    }
+
+    pub fn test(&mut self, a: Reg, b: Reg) {
+        self.op_reg(true, &[0x85], b as u8, a);
+    }
+
+    /// Signed multiplication, `dst = dst * src`
+    pub fn imul(&mut self, dst: Reg, src: Reg) {
+        self.op_reg(true, &[0x0f, 0xaf], dst as u8, src);
+    }
+
+    /// Unsigned multiplication of `rax` by `src` into `rdx:rax`
+    pub fn mul(&mut self, src: Reg) {
+        self.op_reg(true, &[0xf7], 4, src);
+    }
+
+    /// Unsigned division of `rdx:rax` by `src`
+    pub fn div(&mut self, src: Reg) {
+        self.op_reg(true, &[0xf7], 6, src);
+    }
+
+    /// Signed division of `rdx:rax` by `src`
+    pub fn idiv(&mut self, src: Reg) {
+        self.op_reg(true, &[0xf7], 7, src);
+    }
+
+    pub fn neg(&mut self, reg: Reg) {
+        self.op_reg(true, &[0xf7], 3, reg);
+    }
+
+    pub fn inc(&mut self, reg: Reg) {
+        self.op_reg(true, &[0xff], 0, reg);
+    }
+
+    pub fn dec(&mut self, reg: Reg) {
+        self.op_reg(true, &[0xff], 1, reg);
+    }
+
+    pub fn dec_mem(&mut self, mem: Mem) {
+        self.op_mem(true, &[0xff], 1, mem);
+    }
+
+    /// Sign-extends `rax` into `rdx:rax`
+    pub fn cqo(&mut self) {
+        self.bytes(&[0x48, 0x99]);
+    }
+
+    /// Sets `dst` to 1 if the condition holds and 0 otherwise
+    pub fn set(&mut self, cond: Cond, dst: Reg) {
+        assert!(
+            (dst as u8) < 4,
+            "only the low byte of a, b, c and d is encodable"
+        );
+        self.op_reg(false, &[0x0f, 0x90 | cond as u8], 0, dst);
+        self.op_reg(false, &[0x0f, 0xb6], dst as u8, dst);
+    }
+
+    pub fn cmov(&mut self, cond: Cond, dst: Reg, src: Reg) {
+        self.op_reg(true, &[0x0f, 0x40 | cond as u8], dst as u8, src);
+    }
+
+    fn relative(&mut self, label: Label) {
+        self.fixups.push(Fixup::Relative(self.code.len(), label));
+        self.bytes(&[0; 4]);
+    }
+
+    pub fn jmp(&mut self, label: Label) {
+        self.code.push(0xe9);
+        self.relative(label);
+    }
+
+    pub fn jcc(&mut self, cond: Cond, label: Label) {
+        self.bytes(&[0x0f, 0x80 | cond as u8]);
+        self.relative(label);
+    }
+
+    /// Jumps to the address stored in memory
+    pub fn jmp_mem(&mut self, mem: Mem) {
+        self.op_mem(false, &[0xff], 4, mem);
+    }
+
+    pub fn call(&mut self, label: Label) {
+        self.code.push(0xe8);
+        self.relative(label);
+    }
+
+    pub fn ret(&mut self) {
+        self.code.push(0xc3);
+    }
+
+    pub fn push(&mut self, reg: Reg) {
+        self.rex(false, 0, None, Some(reg));
+        self.code.push(0x50 | reg.low());
+    }
+
+    pub fn pop(&mut self, reg: Reg) {
+        self.rex(false, 0, None, Some(reg));
+        self.code.push(0x58 | reg.low());
+    }
+
+    pub fn syscall(&mut self) {
+        self.bytes(&[0x0f, 0x05]);
+    }
+
+    /// Copies `rcx` quadwords from `[rsi]` to `[rdi]`
+    pub fn rep_movsq(&mut self) {
+        self.bytes(&[0xf3, 0x48, 0xa5]);
+    }
+
+    /// Fills `rcx` bytes at `[rdi]` with `al`
+    pub fn rep_stosb(&mut self) {
+        self.bytes(&[0xf3, 0xaa]);
+    }
+
+    /// Resolves labels, the code being loaded at `base`
+    pub fn finish(mut self, base: u64) -> Vec<u8> {
+        let labels = self.labels;
+        let offset = |label: Label| labels[label.0].expect("unbound label");
+        for fixup in self.fixups {
+            match fixup {
+                Fixup::Relative(at, label) => {
+                    let relative = offset(label) as i64 - (at as i64 + 4);
+                    self.code[at..at + 4].copy_from_slice(&(relative as i32).to_le_bytes());
+                }
+                Fixup::Absolute32(at, label) => {
+                    let mut field = [0; 4];
+                    field.copy_from_slice(&self.code[at..at + 4]);
+                    let address = base + offset(label) as u64 + i32::from_le_bytes(field) as u64;
+                    self.code[at..at + 4].copy_from_slice(&(address as u32).to_le_bytes());
+                }
+                Fixup::Absolute64(at, label) => {
+                    let address = base + offset(label) as u64;
+                    self.code[at..at + 8].copy_from_slice(&address.to_le_bytes());
+                }
+            }
+        }
+        self.code
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Alu, Assembler, Cond, Mem, Reg};
+
+    fn assemble(f: impl FnOnce(&mut Assembler)) -> Vec<u8> {
+        let mut asm = Assembler::new();
+        f(&mut asm);
+        asm.finish(0x1000)
+    }
+
+    #[test]
+    fn registers_and_immediates() {
+        assert_eq!(
+            vec![0x4c, 0x89, 0xf0],
+            assemble(|a| a.mov(Reg::Rax, Reg::R14))
+        );
+        assert_eq!(
+            vec![0x41, 0xbc, 1, 0, 0, 0],
+            assemble(|a| a.mov_imm(Reg::R12, 1))
+        );
+        assert_eq!(
+            vec![0x48, 0xc7, 0xc1, 0xff, 0xff, 0xff, 0xff],
+            assemble(|a| a.mov_imm(Reg::Rcx, -1))
+        );
+        assert_eq!(
+            vec![0x48, 0xb8, 0, 0, 0, 0, 1, 0, 0, 0],
+            assemble(|a| a.mov_imm(Reg::Rax, 1 << 32))
+        );
+        assert_eq!(
+            vec![0x4c, 0x01, 0xe8],
+            assemble(|a| a.alu(Alu::Add, Reg::Rax, Reg::R13))
+        );
+        assert_eq!(
+            vec![0x0f, 0x9c, 0xc0, 0x0f, 0xb6, 0xc0],
+            assemble(|a| a.set(Cond::L, Reg::Rax))
+        );
+    }
+
+    #[test]
+    fn memory_operands() {
+        assert_eq!(
+            vec![0x48, 0x8b, 0x04, 0x25, 0x10, 0, 0, 0x10],
+            assemble(|a| a.load(Reg::Rax, Mem::at(0x1000_0010)))
+        );
+        assert_eq!(
+            vec![0x48, 0x89, 0x34, 0xfd, 0, 0, 0, 0x10],
+            assemble(|a| a.store(Mem::at(0x1000_0000).index(Reg::Rdi, 8), Reg::Rsi))
+        );
+        assert_eq!(
+            vec![0x48, 0x8b, 0x84, 0x24, 8, 0, 0, 0],
+            assemble(|a| a.load(Reg::Rax, Mem::base(Reg::Rsp, 8)))
+        );
+        assert_eq!(
+            vec![0x42, 0xff, 0x24, 0xf5, 0x09, 0x10, 0, 0, 0xc3, 0, 0, 0, 0, 0, 0, 0, 0],
+            assemble(|a| {
+                let table = a.label();
+                a.jmp_mem(Mem::label(table).index(Reg::R14, 8));
+                a.ret();
+                a.bind(table);
+                a.quad(0);
+            })
+        );
+    }
+
+    #[test]
+    fn labels() {
+        let code = assemble(|a| {
+            let start = a.here();
+            let end = a.label();
+            a.jcc(Cond::E, end);
+            a.jmp(start);
+            a.bind(end);
+            a.address(start);
+        });
+        assert_eq!(
+            vec![0x0f, 0x84, 5, 0, 0, 0, 0xe9, 0xf5, 0xff, 0xff, 0xff, 0, 0x10, 0, 0, 0, 0, 0, 0],
+            code
+        );
+    }
+}
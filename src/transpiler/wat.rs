@@ -0,0 +1,503 @@
+use crate::{
+    error::Error,
+    interpreter::{self, Instruction, Parameter},
+    runtime::Memory,
+};
+use std::collections::BTreeMap;
+
+use super::cfg::{self, coverage_ranges, Exit, Store};
+
+static MAIN: &str = include_str!("../../resources/main.wat");
+
+/// Offset of the validity flags of the blocks in linear memory
+const VALID: usize = 1 << 20;
+
+const PAGE: usize = 1 << 16;
+
+/// Lines calling `output` with the values the program printed ahead of time
+fn transpile_output(output: &[i64]) -> String {
+    output
+        .iter()
+        .map(|value| format!("    (call $output (i64.const {}))\n", value))
+        .collect()
+}
+
+/// String literal spelling out every byte
+fn bytes(bytes: &[u8]) -> String {
+    let escaped: Vec<String> = bytes.iter().map(|b| format!("\\{:02x}", b)).collect();
+    format!("\"{}\"", escaped.concat())
+}
+
+/// Offset of the dense cells, right after the validity flags of `count` blocks
+fn cells(count: usize) -> usize {
+    (VALID + count + 7) & !7
+}
+
+/// Memory declaration and the data segments initialising the validity flags and dense cells
+fn transpile_memory(code: &Memory, count: usize) -> String {
+    let cells = cells(count);
+    let end = cells + code.dense().len() * 8;
+    let mut result = format!(
+        "(memory (export \"memory\") {})\n  (data (i32.const {}) {})",
+        end.div_ceil(PAGE),
+        VALID,
+        bytes(&vec![1; count])
+    );
+    if !code.dense().is_empty() {
+        let lines: Vec<String> = code
+            .dense()
+            .chunks(4)
+            .map(|values| {
+                let values: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+                bytes(&values)
+            })
+            .collect();
+        result.push_str(&format!(
+            "\n  (data (i32.const {})\n    {})",
+            cells,
+            lines.join("\n    ")
+        ));
+    }
+    result
+}
+
+/// Globals holding the position of the dense cells, their number, and the registers
+fn transpile_state(code: &Memory, count: usize, pc: usize, rb: isize) -> String {
+    format!(
+        "(global $cells (export \"cells\") i32 (i32.const {}))\n  \
+         (global $length (mut i64) (i64.const {}))\n  \
+         (global $pc (mut i64) (i64.const {}))\n  \
+         (global $rb (mut i64) (i64.const {}))",
+        cells(count),
+        code.dense().len(),
+        pc,
+        rb
+    )
+}
+
+/// Writes of the cells stored past the dense ones
+fn transpile_code(code: &Memory) -> String {
+    code.sparse()
+        .iter()
+        .map(|(address, value)| {
+            format!(
+                "    (call $mem_write (i64.const {}) (i64.const {}))\n",
+                address, value
+            )
+        })
+        .collect()
+}
+
+/// Body of `invalidate`, clearing the validity flags of the blocks covering an address
+fn transpile_invalidate(covered: &BTreeMap<usize, Vec<usize>>) -> String {
+    coverage_ranges(covered)
+        .iter()
+        .map(|(start, end, blocks)| {
+            let clear: Vec<String> = blocks
+                .iter()
+                .map(|b| format!("(i32.store8 (i32.const {}) (i32.const 0))", VALID + b))
+                .collect();
+            format!(
+                "(if (i64.le_u (local.get $address) (i64.const {}))\n      (then\n        \
+                 (if (i64.ge_u (local.get $address) (i64.const {}))\n          \
+                 (then\n            {}))\n        (return)))",
+                end,
+                start,
+                clear.join("\n            ")
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n    ")
+}
+
+/// Expression reading the value of a parameter
+fn transpile_value(parameter: &Parameter, opcode: i64, at: usize) -> String {
+    match parameter {
+        Parameter::Position(p) => format!("(call $mem_read (i64.const {}))", p),
+        Parameter::Immediate(v) => format!("(i64.const {})", v),
+        Parameter::Relative(o) => format!(
+            "(call $mem_read (call $relative (global.get $rb) (i64.const {}) (i64.const {}) \
+             (i64.const {})))",
+            o, opcode, at
+        ),
+    }
+}
+
+/// Renders the instructions of a block as WebAssembly instructions
+struct Translation<'a> {
+    cfg: cfg::Translation<'a>,
+}
+
+impl Translation<'_> {
+    /// Instructions storing `$v` at the address of `to`
+    fn write(&self, to: &Parameter, opcode: i64, at: usize, next: usize) -> Vec<String> {
+        let leave = format!("(global.set $pc (i64.const {})) (br $dispatch)", next);
+        match self.cfg.store(to, next) {
+            Store::Data(address) => vec![format!(
+                "(call $mem_write (i64.const {}) (local.get $v))",
+                address
+            )],
+            Store::Code {
+                address,
+                leave: stop,
+                ..
+            } => {
+                let mut result = vec![
+                    format!("(call $mem_write (i64.const {}) (local.get $v))", address),
+                    format!("(call $invalidate (i64.const {}))", address),
+                ];
+                if stop {
+                    result.push(leave);
+                }
+                result
+            }
+            Store::Relative(o) => vec![
+                format!(
+                    "(local.set $to (call $relative (global.get $rb) (i64.const {}) \
+                     (i64.const {}) (i64.const {})))",
+                    o, opcode, at
+                ),
+                "(call $mem_write (local.get $to) (local.get $v))".to_owned(),
+                "(call $invalidate (local.get $to))".to_owned(),
+                format!(
+                    "(if (i32.eqz (i32.load8_u (i32.const {}))) (then {}))",
+                    VALID + self.cfg.index,
+                    leave
+                ),
+            ],
+        }
+    }
+
+    fn arithmetic(
+        &self,
+        (n1, n2, to): (&Parameter, &Parameter, &Parameter),
+        operation: &str,
+        opcode: i64,
+        at: usize,
+        next: usize,
+    ) -> Vec<String> {
+        let mut result = vec![
+            format!("(local.set $n1 {})", transpile_value(n1, opcode, at)),
+            format!("(local.set $n2 {})", transpile_value(n2, opcode, at)),
+            format!("(local.set $v {})", operation),
+        ];
+        result.extend(self.write(to, opcode, at, next));
+        result
+    }
+
+    fn jump(&self, test: &Parameter, goto: &Parameter, condition: &str, at: usize) -> String {
+        let opcode = if condition == "i64.ne" { 5 } else { 6 };
+        let target = match self.cfg.target(goto) {
+            Some(g) => format!("(i64.const {})", g),
+            None => format!(
+                "(call $target {} (i64.const {}) (i64.const {}))",
+                transpile_value(goto, opcode, at),
+                opcode,
+                at
+            ),
+        };
+        format!(
+            "(if ({} {} (i64.const 0)) (then (global.set $pc {}) (br $dispatch)))",
+            condition,
+            transpile_value(test, opcode, at),
+            target
+        )
+    }
+
+    fn instruction(&self, instruction: &Instruction, at: usize, next: usize) -> Vec<String> {
+        match instruction {
+            Instruction::Add { n1, n2, to } => self.arithmetic(
+                (n1, n2, to),
+                &format!("(call $checked_add (local.get $n1) (local.get $n2) (i64.const 1) (i64.const {}))", at),
+                1,
+                at,
+                next,
+            ),
+            Instruction::Multiply { n1, n2, to } => self.arithmetic(
+                (n1, n2, to),
+                &format!("(call $checked_mul (local.get $n1) (local.get $n2) (i64.const 2) (i64.const {}))", at),
+                2,
+                at,
+                next,
+            ),
+            Instruction::Input { to } => {
+                let mut result = vec!["(local.set $v (call $input))".to_owned()];
+                result.extend(self.write(to, 3, at, next));
+                result
+            }
+            Instruction::Output { from } => {
+                vec![format!("(call $output {})", transpile_value(from, 4, at))]
+            }
+            Instruction::JumpIfTrue { test, goto } => vec![self.jump(test, goto, "i64.ne", at)],
+            Instruction::JumpIfFalse { test, goto } => vec![self.jump(test, goto, "i64.eq", at)],
+            Instruction::LessThan { n1, n2, to } => self.arithmetic(
+                (n1, n2, to),
+                "(i64.extend_i32_u (i64.lt_s (local.get $n1) (local.get $n2)))",
+                7,
+                at,
+                next,
+            ),
+            Instruction::Equals { n1, n2, to } => self.arithmetic(
+                (n1, n2, to),
+                "(i64.extend_i32_u (i64.eq (local.get $n1) (local.get $n2)))",
+                8,
+                at,
+                next,
+            ),
+            Instruction::AdjustRelativeBase { by } => vec![format!(
+                "(global.set $rb (call $checked_add {} (global.get $rb) (i64.const 9) \
+                 (i64.const {})))",
+                transpile_value(by, 9, at),
+                at
+            )],
+            Instruction::Halt | Instruction::End => vec!["(return)".to_owned()],
+        }
+    }
+
+    fn block(&self) -> String {
+        let mut result = format!(
+            "      end\n      ;; Block {} at {}\n      \
+             (if (i32.eqz (i32.load8_u (i32.const {}))) (then (br $fallback)))\n",
+            self.cfg.index,
+            self.cfg.block.start,
+            VALID + self.cfg.index
+        );
+        for (at, instruction, next) in &self.cfg.block.instructions {
+            for line in self.instruction(instruction, *at, *next) {
+                result.push_str(&format!("      {}\n", line));
+            }
+        }
+        match self.cfg.block.exit {
+            Exit::Next(next) | Exit::Jump(next) | Exit::Fallback(next) => {
+                result.push_str(&format!(
+                    "      (global.set $pc (i64.const {}))\n      (br $dispatch)\n",
+                    next
+                ))
+            }
+            Exit::Halt => (),
+        }
+        result
+    }
+}
+
+/// Translates the code reachable from `entry` into nested blocks entered through a `br_table`
+/// on the program counter, along with the body of `invalidate` and the number of blocks
+fn transpile_blocks(code: &Memory, entry: usize) -> (String, String, usize) {
+    let (blocks, covered) = cfg::blocks_and_coverage(code, entry);
+
+    // Addresses that don't start a block, and those past the table, go to the interpreter
+    let length = blocks.iter().map(|b| b.start + 1).max().unwrap_or(0);
+    let mut table = vec!["$fallback".to_owned(); length];
+    for (index, block) in blocks.iter().enumerate() {
+        table[block.start] = format!("$block_{}", index);
+    }
+    let table: Vec<String> = table.chunks(8).map(|t| t.join(" ")).collect();
+
+    let mut result = "      block $fallback\n".to_owned();
+    for index in (0..blocks.len()).rev() {
+        result.push_str(&format!("      block $block_{}\n", index));
+    }
+    result.push_str(&format!(
+        "      (br_table\n        {}\n        $fallback\n        \
+         (select\n          (i32.wrap_i64 (global.get $pc))\n          (i32.const {})\n          \
+         (i64.lt_u (global.get $pc) (i64.const {}))))\n",
+        table.join("\n        "),
+        length,
+        length
+    ));
+    for cfg in cfg::translations(&blocks, &covered, code) {
+        result.push_str(&Translation { cfg }.block());
+    }
+    result.push_str("      end\n");
+
+    (result, transpile_invalidate(&covered), blocks.len())
+}
+
+/// Translates a program into the text of an equivalent WebAssembly module
+///
+/// The module imports `input` and `output` functions from `env`, each passing a single `i64`,
+/// and exports its linear memory along with a `run` function. The dense cells of the program
+/// live in that memory at the offset held by the exported `cells` global, and the cause of a
+/// trap is described by the exported `fault` globals.
+pub fn transpile_wat(code: Vec<i64>, input: Vec<i64>) -> Result<String, Error> {
+    let eval_results = interpreter::eval(code, input)?;
+    let output = transpile_output(&eval_results.output);
+
+    if eval_results.completed {
+        return Ok(format!(
+            "(module\n  (import \"env\" \"output\" (func $output (param i64)))\n\n  \
+             (func (export \"run\")\n{}  )\n)\n",
+            output
+        ));
+    }
+
+    let (blocks, invalidate, count) = transpile_blocks(&eval_results.code, eval_results.run_code);
+    Ok(MAIN
+        .replace("      ;; blocks\n", &blocks)
+        .replace("    ;; invalidate\n", &format!("    {}\n", invalidate))
+        .replace(";; memory", &transpile_memory(&eval_results.code, count))
+        .replace(
+            ";; state",
+            &transpile_state(
+                &eval_results.code,
+                count,
+                eval_results.run_code,
+                eval_results.relative_base,
+            ),
+        )
+        .replace("    ;; output\n", &output)
+        .replace("    ;; code\n", &transpile_code(&eval_results.code)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::runtime::Memory;
+    use crate::transpiler::wat::{
+        transpile_blocks, transpile_memory, transpile_output, transpile_wat, VALID,
+    };
+    use std::{env, fs, process::Command};
+    use wasmparser::Validator;
+
+    /// Assembles a module, validates it, and checks that printing it back to text and
+    /// assembling that again gives the same binary
+    fn round_trip(text: &str) -> Vec<u8> {
+        let binary = wat::parse_str(text).unwrap();
+        Validator::new().validate_all(&binary).unwrap();
+        let printed = wasmprinter::print_bytes(&binary).unwrap();
+        assert_eq!(binary, wat::parse_str(&printed).unwrap());
+        binary
+    }
+
+    #[test]
+    fn output() {
+        assert_eq!(
+            "    (call $output (i64.const 1))\n    (call $output (i64.const -2))\n",
+            transpile_output(&[1, -2])
+        );
+    }
+
+    #[test]
+    fn memory() {
+        let memory = transpile_memory(&Memory::from(vec![1, -1]), 3);
+        assert!(memory.starts_with(&format!(
+            "(memory (export \"memory\") 17)\n  (data (i32.const {}) \"\\01\\01\\01\")",
+            VALID
+        )));
+        assert!(memory.ends_with(&format!(
+            "(data (i32.const {})\n    \"\\01\\00\\00\\00\\00\\00\\00\\00\
+             \\ff\\ff\\ff\\ff\\ff\\ff\\ff\\ff\")",
+            VALID + 8
+        )));
+    }
+
+    #[test]
+    fn blocks() {
+        // 0: in [12]; 2: out [12]; 4: add [12], #-1, [12]; 8: jnz [12], #2; 11: hlt
+        let code = Memory::from(vec![3, 12, 4, 12, 1001, 12, -1, 12, 1005, 12, 2, 99, 0]);
+        let (blocks, invalidate, count) = transpile_blocks(&code, 0);
+        assert_eq!(3, count);
+        assert!(blocks.contains(
+            "$block_0 $fallback $block_1 $fallback $fallback $fallback $fallback $fallback\n        \
+             $fallback $fallback $fallback $block_2\n        $fallback\n"
+        ));
+        assert!(blocks.contains(&format!(
+            "      ;; Block 1 at 2\n      \
+             (if (i32.eqz (i32.load8_u (i32.const {}))) (then (br $fallback)))\n      \
+             (call $output (call $mem_read (i64.const 12)))\n",
+            VALID + 1
+        )));
+        assert!(blocks.contains(
+            "(if (i64.ne (call $mem_read (i64.const 12)) (i64.const 0)) \
+             (then (global.set $pc (i64.const 2)) (br $dispatch)))"
+        ));
+        assert!(invalidate.starts_with(&format!(
+            "(if (i64.le_u (local.get $address) (i64.const 1))\n      (then\n        \
+             (if (i64.ge_u (local.get $address) (i64.const 0))\n          \
+             (then\n            (i32.store8 (i32.const {}) (i32.const 0))))\n        (return)))",
+            VALID
+        )));
+    }
+
+    #[test]
+    fn emitted_module_round_trips() {
+        // Counts down from its input, then jumps out of bounds
+        let countdown = vec![3, 14, 4, 14, 1001, 14, -1, 14, 1005, 14, 2, 1105, 1, -1, 0];
+        // Prints itself through the relative base
+        let quine = vec![
+            109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99,
+        ];
+        // Writes past the dense cells, then reads the result back in and multiplies it
+        let sparse = vec![
+            1101,
+            2,
+            3,
+            1_000_000_000,
+            3,
+            20,
+            2,
+            20,
+            1_000_000_000,
+            21,
+            4,
+            21,
+            99,
+        ];
+        for code in [countdown, quine, sparse] {
+            let binary = round_trip(&transpile_wat(code, vec![]).unwrap());
+            assert_eq!(b"\0asm", &binary[..4]);
+        }
+
+        let completed = transpile_wat(vec![104, i64::MIN, 99], vec![]).unwrap();
+        assert!(completed.contains("(call $output (i64.const -9223372036854775808))"));
+        round_trip(&completed);
+    }
+
+    /// Runs the module of a program with node, feeding it `input`, until it halts or traps, then
+    /// returns its outputs and the fault globals
+    fn run_module(name: &str, code: Vec<i64>, input: i64) -> String {
+        let binary = wat::parse_str(transpile_wat(code, vec![]).unwrap()).unwrap();
+        let module =
+            env::temp_dir().join(format!("intcode_wat_{}_{}.wasm", name, std::process::id()));
+        fs::write(&module, binary).unwrap();
+
+        let script = format!(
+            "import {{ readFileSync }} from 'fs';\n\
+             const outputs = [];\n\
+             const {{ instance }} = await WebAssembly.instantiate(readFileSync({:?}), {{\n\
+             env: {{ input: () => {}n, output: (v) => outputs.push(v) }},\n\
+             }});\n\
+             const e = instance.exports;\n\
+             try {{ e.run(); }} catch (x) {{ outputs.push(x.constructor.name); }}\n\
+             console.log(outputs.join(' '));\n\
+             console.log([e.fault, e.fault_value, e.fault_opcode, e.fault_position]\
+             .map((g) => g.value).join(' '));\n",
+            module.to_str().unwrap(),
+            input
+        );
+        let output = Command::new("node")
+            .args(["--input-type=module", "-e", &script])
+            .output()
+            .expect("node is needed to run the emitted module");
+        fs::remove_file(&module).ok();
+
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    #[test]
+    fn emitted_module_runs() {
+        // Counts down from its input, then jumps out of bounds
+        let code = vec![3, 14, 4, 14, 1001, 14, -1, 14, 1005, 14, 2, 1105, 1, -1, 0];
+        assert_eq!(
+            "3 2 1 RuntimeError\n3 -1 5 11\n",
+            run_module("countdown", code, 3)
+        );
+    }
+
+    #[test]
+    fn jump_to_end() {
+        // Echoes its input, then jumps right past the end of memory to halt
+        let code = vec![3, 1, 4, 1, 5, 1, 7, 8];
+        assert_eq!("5\n0 0 0 0\n", run_module("end", code, 5));
+    }
+}